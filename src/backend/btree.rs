@@ -0,0 +1,448 @@
+use std::mem;
+
+use super::key::Comparator;
+use super::page::{self, PageType};
+use super::{overflow, Pager};
+use crate::Error;
+
+/// One B-tree, its cells ordered by their keys: the rows of a table, or the entries of an index
+pub(crate) struct BTree<'a> {
+    pub(crate) root_page_num: usize,
+    pub(crate) pager: &'a mut Pager,
+    pub(super) comparator: Comparator,
+}
+
+impl<'a> BTree<'a> {
+    pub(super) fn new(pager: &'a mut Pager, root_page_num: usize, comparator: Comparator) -> Self {
+        Self {
+            root_page_num,
+            pager,
+            comparator,
+        }
+    }
+
+    /// The leaf and cell holding `key`, or where it would go. A prefix of the
+    /// keys of the tree finds the first key it starts.
+    pub(super) fn find(&mut self, key: &[u8]) -> Result<(usize, usize), Error> {
+        let mut page_num = self.root_page_num;
+        loop {
+            let page = self.pager.get_page(page_num)?;
+            match page.get_type()? {
+                PageType::Leaf => return Ok((page_num, page.leaf_find(key, &self.comparator)?)),
+                PageType::Internal => {
+                    page_num =
+                        page.get_internal_child(page.internal_find(key, &self.comparator)?)?;
+                }
+            }
+        }
+    }
+
+    /// The leaf and cell holding a key equal to `key`, if the tree has one
+    pub(super) fn position(&mut self, key: &[u8]) -> Result<Option<(usize, usize)>, Error> {
+        let (page_num, cell_num) = self.find(key)?;
+        let page = self.pager.get_page(page_num)?;
+        let found = cell_num < page.get_leaf_num_cells()?
+            && (self.comparator)
+                .compare(page.get_leaf_key(cell_num)?, key)
+                .is_eq();
+
+        Ok(found.then_some((page_num, cell_num)))
+    }
+
+    /// Add a cell holding `value` for `key`, which the tree does not have yet
+    pub(super) fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let (page_num, cell_num) = self.find(key)?;
+        self.insert_at(page_num, cell_num, key, value)
+    }
+
+    /// Add a cell where `find` said its key goes
+    pub(super) fn insert_at(
+        &mut self,
+        page_num: usize,
+        cell_num: usize,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<(), Error> {
+        let body = self.store(key.len(), value)?;
+        let page = self.pager.get_page_mut(page_num)?;
+        if !page.leaf_has_room(key.len(), body.len())? {
+            // Node full
+            return self.leaf_split_and_insert(page_num, cell_num, key.to_vec(), body);
+        }
+
+        page.insert_leaf_cell(cell_num, key, &body)?;
+
+        Ok(())
+    }
+
+    /// Remove a cell along with its overflow pages
+    pub(super) fn remove(&mut self, page_num: usize, cell_num: usize) -> Result<(), Error> {
+        self.free_overflow(page_num, cell_num)?;
+        let page = self.pager.get_page_mut(page_num)?;
+        page.remove_leaf_cell(cell_num)?;
+
+        self.rebalance(page_num)
+    }
+
+    /// Give a cell another value, keeping its key
+    pub(super) fn replace(
+        &mut self,
+        page_num: usize,
+        cell_num: usize,
+        value: &[u8],
+    ) -> Result<(), Error> {
+        let key = self
+            .pager
+            .get_page(page_num)?
+            .get_leaf_key(cell_num)?
+            .to_vec();
+
+        // the value may have grown past the room left in its page
+        self.free_overflow(page_num, cell_num)?;
+        let page = self.pager.get_page_mut(page_num)?;
+        page.remove_leaf_cell(cell_num)?;
+        self.insert_at(page_num, cell_num, &key, value)
+    }
+
+    /// The number of cells, added up from the leaves without reading any value
+    pub(super) fn count(&mut self) -> Result<usize, Error> {
+        let (mut page_num, _) = self.find(&[])?;
+        let mut count = 0;
+        loop {
+            let page = self.pager.get_page(page_num)?;
+            count += page.get_leaf_num_cells()?;
+            page_num = page.get_leaf_next_leaf()?;
+            if page_num == 0 {
+                return Ok(count);
+            }
+        }
+    }
+
+    /// The largest key of the tree, if it has any
+    pub(super) fn last_key(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let root = self.pager.get_page(self.root_page_num)?;
+        if let PageType::Leaf = root.get_type()? {
+            if root.get_leaf_num_cells()? == 0 {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(self.get_max_key(self.root_page_num)?))
+    }
+
+    /// Lay out the cell body holding `value`, moving what the page has no room for to overflow pages
+    fn store(&mut self, key_size: usize, value: &[u8]) -> Result<Vec<u8>, Error> {
+        if value.len() <= page::leaf_max_value_size(key_size) {
+            return Ok(page::leaf_cell_body(value, None));
+        }
+
+        let (local, rest) = value.split_at(page::leaf_max_local_size(key_size));
+        let first_overflow_page = overflow::write(self.pager, rest)?;
+
+        Ok(page::leaf_cell_body(
+            local,
+            Some((value.len(), first_overflow_page)),
+        ))
+    }
+
+    /// The whole value of a cell, gathered from its overflow pages if need be
+    pub(super) fn load(&mut self, page_num: usize, cell_num: usize) -> Result<Vec<u8>, Error> {
+        let page = self.pager.get_page(page_num)?;
+        let (mut value, overflow) = page.get_leaf_value(cell_num)?;
+        if let Some((value_size, first_overflow_page)) = overflow {
+            let rest = value_size - value.len();
+            value.extend(overflow::read(self.pager, first_overflow_page, rest)?);
+        }
+
+        Ok(value)
+    }
+
+    /// Give the overflow pages of a cell back to the pager
+    fn free_overflow(&mut self, page_num: usize, cell_num: usize) -> Result<(), Error> {
+        let page = self.pager.get_page(page_num)?;
+        let (local, overflow) = page.get_leaf_value(cell_num)?;
+        if let Some((value_size, first_overflow_page)) = overflow {
+            overflow::free(self.pager, first_overflow_page, value_size - local.len())?;
+        }
+
+        Ok(())
+    }
+
+    fn get_max_key(&mut self, page_num: usize) -> Result<Vec<u8>, Error> {
+        let page = self.pager.get_page(page_num)?;
+
+        match page.get_type()? {
+            PageType::Leaf => Ok(page.get_leaf_key(page.get_leaf_num_cells()? - 1)?.to_vec()),
+            PageType::Internal => {
+                let right_child_page_num = page.get_internal_right_child()?;
+                self.get_max_key(right_child_page_num)
+            }
+        }
+    }
+
+    fn leaf_split_and_insert(
+        &mut self,
+        page_num: usize,
+        cell_num: usize,
+        key: Vec<u8>,
+        body: Vec<u8>,
+    ) -> Result<(), Error> {
+        let new_page_num = self.pager.get_unused_page_num()?;
+
+        let old_page = self.pager.get_page(page_num)?;
+        let next_page_num = old_page.get_leaf_next_leaf()?;
+        let mut cells = old_page.get_leaf_cells()?;
+        cells.insert(cell_num, (key, body));
+        let (left_cells, right_cells) = cells.split_at(page::leaf_split_point(&cells));
+
+        let new_page = self.pager.get_page_mut(new_page_num)?;
+        new_page.init_leaf();
+        new_page.set_leaf_cells(right_cells)?;
+        new_page.set_leaf_next_leaf(next_page_num);
+
+        let old_page = self.pager.get_page_mut(page_num)?;
+        old_page.set_leaf_cells(left_cells)?;
+        old_page.set_leaf_next_leaf(new_page_num);
+
+        let left_max_key = left_cells[left_cells.len() - 1].0.clone();
+        self.add_sibling(page_num, left_max_key, new_page_num)
+    }
+
+    /// Hang `new_page_num`, which took the upper part of the cells of `page_num`,
+    /// right after it in their parent, `page_num` keeping keys up to `left_max_key`
+    fn add_sibling(
+        &mut self,
+        page_num: usize,
+        left_max_key: Vec<u8>,
+        new_page_num: usize,
+    ) -> Result<(), Error> {
+        let page = self.pager.get_page(page_num)?;
+        if page.get_is_root() {
+            return self.new_root(new_page_num, left_max_key);
+        }
+
+        let parent_page_num = page.get_parent()?;
+        self.pager
+            .get_page_mut(new_page_num)?
+            .set_parent(parent_page_num);
+        let parent = self.pager.get_page(parent_page_num)?;
+        let index = parent.internal_find_child(page_num)?;
+        // the key paired with the right child is never written back
+        let mut cells = parent.get_internal_cells(Vec::new())?;
+        let max_key = mem::replace(&mut cells[index].1, left_max_key);
+        cells.insert(index + 1, (new_page_num, max_key));
+
+        self.set_internal_cells(parent_page_num, cells)
+    }
+
+    /// Overwrite the cells of an internal page, splitting it once they no longer fit
+    fn set_internal_cells(
+        &mut self,
+        page_num: usize,
+        cells: Vec<(usize, Vec<u8>)>,
+    ) -> Result<(), Error> {
+        if page::internal_cells_space(&cells) <= page::CELL_SPACE {
+            self.pager
+                .get_page_mut(page_num)?
+                .set_internal_cells(&cells)?;
+            return Ok(());
+        }
+
+        let new_page_num = self.pager.get_unused_page_num()?;
+        let (left_cells, right_cells) = cells.split_at(page::internal_split_point(&cells));
+        let new_page = self.pager.get_page_mut(new_page_num)?;
+        new_page.init_internal();
+        new_page.set_internal_cells(right_cells)?;
+        for &(child, _) in right_cells {
+            self.pager.get_page_mut(child)?.set_parent(new_page_num);
+        }
+        self.pager
+            .get_page_mut(page_num)?
+            .set_internal_cells(left_cells)?;
+
+        let left_max_key = left_cells[left_cells.len() - 1].1.clone();
+        self.add_sibling(page_num, left_max_key, new_page_num)
+    }
+
+    /// Move the root into a new left child, the root keeping its page number
+    fn new_root(
+        &mut self,
+        right_child_page_num: usize,
+        left_child_max_key: Vec<u8>,
+    ) -> Result<(), Error> {
+        let root_copy = self.pager.get_page(self.root_page_num)?.clone();
+        let left_child_page_num = self.pager.get_unused_page_num()?;
+        let left_child = self.pager.get_page_mut(left_child_page_num)?;
+        left_child.clone_from(&root_copy);
+        left_child.set_is_root(false);
+        left_child.set_parent(self.root_page_num);
+
+        if let PageType::Internal = root_copy.get_type()? {
+            // The children of the old root now belong to the left child
+            for i in 0..=root_copy.get_internal_num_keys()? {
+                let child_page_num = root_copy.get_internal_child(i)?;
+                let child = self.pager.get_page_mut(child_page_num)?;
+                child.set_parent(left_child_page_num);
+            }
+        }
+
+        let right_child = self.pager.get_page_mut(right_child_page_num)?;
+        right_child.set_parent(self.root_page_num);
+
+        let root = self.pager.get_page_mut(self.root_page_num)?;
+        root.init_internal();
+        root.set_is_root(true);
+        root.set_internal_cells(&[
+            (left_child_page_num, left_child_max_key),
+            (right_child_page_num, Vec::new()),
+        ])?;
+
+        Ok(())
+    }
+
+    /// Walk from a page that just lost a cell up to the root, refreshing the
+    /// keys of the parents and fixing every page that became underfull
+    fn rebalance(&mut self, page_num: usize) -> Result<(), Error> {
+        let mut page_num = page_num;
+        loop {
+            let page = self.pager.get_page(page_num)?;
+            if page.get_is_root() {
+                if let PageType::Internal = page.get_type()? {
+                    if page.get_internal_num_keys()? == 0 {
+                        self.collapse_root()?;
+                    }
+                }
+                return Ok(());
+            }
+
+            let parent_page_num = page.get_parent()?;
+            if page.is_underfull()? {
+                self.merge_or_redistribute(parent_page_num, page_num)?;
+            } else {
+                let parent = self.pager.get_page(parent_page_num)?;
+                let index = parent.internal_find_child(page_num)?;
+                if index < parent.get_internal_num_keys()? {
+                    let max_key = self.get_max_key(page_num)?;
+                    let parent = self.pager.get_page(parent_page_num)?;
+                    if parent.get_internal_key(index)? != max_key {
+                        // a longer key may no longer fit
+                        let mut cells = parent.get_internal_cells(Vec::new())?;
+                        cells[index].1 = max_key;
+                        self.set_internal_cells(parent_page_num, cells)?;
+                    }
+                }
+            }
+
+            page_num = parent_page_num;
+        }
+    }
+
+    /// Merge an underfull page with a sibling if both fit in one page,
+    /// otherwise share their cells evenly between them
+    fn merge_or_redistribute(
+        &mut self,
+        parent_page_num: usize,
+        page_num: usize,
+    ) -> Result<(), Error> {
+        let parent = self.pager.get_page(parent_page_num)?;
+        let index = parent.internal_find_child(page_num)?;
+        // the key paired with the right child is never written back
+        let mut parent_cells = parent.get_internal_cells(Vec::new())?;
+        let left_index = index.saturating_sub(1);
+        let left_page_num = parent_cells[left_index].0;
+        let right_page_num = parent_cells[left_index + 1].0;
+
+        let merged = match self.pager.get_page(left_page_num)?.get_type()? {
+            PageType::Leaf => {
+                let right = self.pager.get_page(right_page_num)?;
+                let next_page_num = right.get_leaf_next_leaf()?;
+                let right_cells = right.get_leaf_cells()?;
+                let left = self.pager.get_page_mut(left_page_num)?;
+                let mut cells = left.get_leaf_cells()?;
+                cells.extend(right_cells);
+
+                if page::leaf_cells_space(&cells) <= page::CELL_SPACE {
+                    left.set_leaf_cells(&cells)?;
+                    left.set_leaf_next_leaf(next_page_num);
+                    parent_cells[left_index].1 = cells[cells.len() - 1].0.clone();
+                    true
+                } else {
+                    let (left_cells, right_cells) = cells.split_at(page::leaf_split_point(&cells));
+                    left.set_leaf_cells(left_cells)?;
+                    let right = self.pager.get_page_mut(right_page_num)?;
+                    right.set_leaf_cells(right_cells)?;
+                    parent_cells[left_index].1 = left_cells[left_cells.len() - 1].0.clone();
+                    parent_cells[left_index + 1].1 = right_cells[right_cells.len() - 1].0.clone();
+                    false
+                }
+            }
+            PageType::Internal => {
+                let left_max_key = self.get_max_key(left_page_num)?;
+                let right_max_key = self.get_max_key(right_page_num)?;
+                let mut cells = self
+                    .pager
+                    .get_page(left_page_num)?
+                    .get_internal_cells(left_max_key)?;
+                cells.extend(
+                    self.pager
+                        .get_page(right_page_num)?
+                        .get_internal_cells(right_max_key.clone())?,
+                );
+
+                let merged = page::internal_cells_space(&cells) <= page::CELL_SPACE;
+                let (left_cells, right_cells) = if merged {
+                    (&cells[..], &cells[cells.len()..])
+                } else {
+                    cells.split_at(page::internal_split_point(&cells))
+                };
+                self.pager
+                    .get_page_mut(left_page_num)?
+                    .set_internal_cells(left_cells)?;
+                for &(child, _) in left_cells {
+                    self.pager.get_page_mut(child)?.set_parent(left_page_num);
+                }
+                if merged {
+                    parent_cells[left_index].1 = right_max_key;
+                } else {
+                    self.pager
+                        .get_page_mut(right_page_num)?
+                        .set_internal_cells(right_cells)?;
+                    for &(child, _) in right_cells {
+                        self.pager.get_page_mut(child)?.set_parent(right_page_num);
+                    }
+                    parent_cells[left_index].1 = left_cells[left_cells.len() - 1].1.clone();
+                    parent_cells[left_index + 1].1 = right_max_key;
+                }
+                merged
+            }
+        };
+
+        if merged {
+            parent_cells.remove(left_index + 1);
+            self.pager.free_page(right_page_num)?;
+        }
+        self.set_internal_cells(parent_page_num, parent_cells)
+    }
+
+    /// Pull the only child of an internal root up into the root page
+    fn collapse_root(&mut self) -> Result<(), Error> {
+        let root = self.pager.get_page(self.root_page_num)?;
+        let child_page_num = root.get_internal_right_child()?;
+        let child = self.pager.get_page(child_page_num)?.clone();
+
+        let root = self.pager.get_page_mut(self.root_page_num)?;
+        root.clone_from(&child);
+        root.set_is_root(true);
+        root.set_parent(0);
+
+        if let PageType::Internal = child.get_type()? {
+            for i in 0..=child.get_internal_num_keys()? {
+                let grandchild_page_num = child.get_internal_child(i)?;
+                let grandchild = self.pager.get_page_mut(grandchild_page_num)?;
+                grandchild.set_parent(self.root_page_num);
+            }
+        }
+
+        self.pager.free_page(child_page_num)
+    }
+}
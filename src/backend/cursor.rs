@@ -1,131 +1,107 @@
 use std::cmp::Ordering;
+use std::ops::Bound;
 
-use super::{page, Row, Table};
+use super::btree::BTree;
+use super::Pager;
+use crate::Error;
 
 pub(super) struct Cursor<'a> {
-    table: &'a mut Table,
+    tree: BTree<'a>,
     page_num: usize,
     cell_num: usize,
+    /// The key past which the cursor reaches the end of the tree
+    end: Bound<Vec<u8>>,
     pub(super) end_of_table: bool,
 }
 
 impl<'a> Cursor<'a> {
-    pub(super) fn from_start(table: &'a mut Table) -> Self {
-        let (page_num, cell_num) = table.find(0, table.root_page_num);
-        let num_cells = table.pager.get_page(page_num).get_leaf_num_cells();
-
-        Self {
+    /// Seek the first cell whose key falls between `start` and `end`, the cursor
+    /// ending after its last one. A prefix bound takes in or leaves out every key it starts.
+    pub(super) fn new(
+        tree: BTree<'a>,
+        start: Bound<Vec<u8>>,
+        end: Bound<Vec<u8>>,
+    ) -> Result<Self, Error> {
+        let mut tree = tree;
+        let (page_num, cell_num) = match &start {
+            Bound::Included(key) | Bound::Excluded(key) => tree.find(key)?,
+            Bound::Unbounded => tree.find(&[])?,
+        };
+
+        let mut cursor = Self {
+            tree,
             page_num,
             cell_num,
-            end_of_table: num_cells == 0,
-            table,
+            end,
+            end_of_table: false,
+        };
+        cursor.settle()?;
+        if let Bound::Excluded(start) = &start {
+            while !cursor.end_of_table && cursor.compare_key(start)?.is_eq() {
+                cursor.advance()?;
+            }
         }
+
+        Ok(cursor)
     }
 
-    pub(super) fn from_pos(table: &'a mut Table, page_num: usize, cell_num: usize) -> Self {
-        let is_last_page = page_num == table.pager.num_pages;
-        let page = table.pager.get_page(page_num);
-        let is_last_cell = page.get_leaf_num_cells() == cell_num;
+    pub(super) fn get_key(&mut self) -> Result<&[u8], Error> {
+        let page = self.tree.pager.get_page(self.page_num)?;
 
-        Self {
-            table,
-            page_num,
-            cell_num,
-            end_of_table: is_last_page && is_last_cell,
-        }
+        page.get_leaf_key(self.cell_num)
     }
 
-    pub(super) fn get_row(&mut self) -> Row {
-        let page = self.table.pager.get_page(self.page_num);
+    /// How the key under the cursor compares with `key`, in the order of the tree
+    fn compare_key(&mut self, key: &[u8]) -> Result<Ordering, Error> {
+        let page = self.tree.pager.get_page(self.page_num)?;
 
-        Row::deserialize(&page.get_leaf_value(self.cell_num))
+        Ok(self
+            .tree
+            .comparator
+            .compare(page.get_leaf_key(self.cell_num)?, key))
     }
 
-    pub(super) fn advance(&mut self) {
-        let page_num = self.page_num;
-        let page = self.table.pager.get_page(page_num);
-        self.cell_num += 1;
-        if self.cell_num >= page.get_leaf_num_cells() {
-            // advance to the next leaf node
-            let next_page_num = page.get_leaf_next_leaf();
-            if next_page_num == 0 {
-                self.end_of_table = true;
-            } else {
-                self.page_num = next_page_num;
-                self.cell_num = 0;
-            }
-        }
+    pub(super) fn get_value(&mut self) -> Result<Vec<u8>, Error> {
+        self.tree.load(self.page_num, self.cell_num)
     }
 
-    pub(super) fn leaf_insert(&mut self, key: usize, value: &Row) {
-        let page = self.table.pager.get_page(self.page_num);
-        let num_cells = page.get_leaf_num_cells();
-        if num_cells >= page::LEAF_MAX_CELLS {
-            // Node full
-            self.leaf_split_and_insert(key, value);
-            return;
-        }
-
-        if self.cell_num < num_cells {
-            // Make room for new cell
-            for i in (self.cell_num..num_cells).rev() {
-                let cell = page.get_leaf_cell(i);
-                page.set_leaf_cell(i + 1, cell);
-            }
-        }
-
-        page.set_leaf_num_cells(num_cells + 1);
-        page.set_leaf_key(self.cell_num, key);
-        page.set_leaf_value(self.cell_num, value.serialize());
+    /// The pager of the tree, for work done alongside a scan that leaves the tree alone
+    pub(super) fn pager(&mut self) -> &mut Pager {
+        self.tree.pager
     }
 
-    fn leaf_split_and_insert(&mut self, key: usize, value: &Row) {
-        let new_page_num = self.table.pager.get_unused_page_num();
-        let new_page = self.table.pager.get_page(new_page_num);
-        new_page.init_leaf();
-        self.table.pager.num_pages += 1;
-
-        let old_page_clone = self.table.pager.get_page(self.page_num).clone();
-        let next_page_num = old_page_clone.get_leaf_next_leaf();
-        let old_parent = old_page_clone.get_parent();
-        let old_max_key = old_page_clone.get_max_key();
-        for i in (0..=page::LEAF_MAX_CELLS).rev() {
-            let cell_num = i % page::LEAF_LEFT_SPLIT_COUNT;
-
-            let destination = if i >= page::LEAF_LEFT_SPLIT_COUNT {
-                self.table.pager.get_page(new_page_num)
-            } else {
-                self.table.pager.get_page(self.page_num)
-            };
+    pub(super) fn advance(&mut self) -> Result<(), Error> {
+        self.cell_num += 1;
+        self.settle()
+    }
 
-            match i.cmp(&self.cell_num) {
-                Ordering::Equal => destination.set_leaf_cell(cell_num, (key, value.serialize())),
-                Ordering::Greater => {
-                    destination.set_leaf_cell(cell_num, old_page_clone.get_leaf_cell(i - 1))
-                }
-                Ordering::Less => {
-                    destination.set_leaf_cell(cell_num, old_page_clone.get_leaf_cell(i))
-                }
+    /// Move on to the next leaf once past the last cell of this one,
+    /// and stop at the end of the tree or of the range
+    fn settle(&mut self) -> Result<(), Error> {
+        let page = self.tree.pager.get_page(self.page_num)?;
+        if self.cell_num >= page.get_leaf_num_cells()? {
+            // advance to the next leaf node
+            let next_page_num = page.get_leaf_next_leaf()?;
+            if next_page_num == 0 {
+                self.end_of_table = true;
+                return Ok(());
             }
+            self.page_num = next_page_num;
+            self.cell_num = 0;
         }
 
-        let new_page = self.table.pager.get_page(new_page_num); // the same as 'new_page' above
-        new_page.set_leaf_num_cells(page::LEAF_RIGHT_SPLIT_COUNT);
-        new_page.set_leaf_next_leaf(next_page_num);
-        new_page.set_parent(old_parent);
-
-        let old_page = self.table.pager.get_page(self.page_num);
-        old_page.set_leaf_num_cells(page::LEAF_LEFT_SPLIT_COUNT);
-        old_page.set_leaf_next_leaf(new_page_num);
-        let new_max_key = old_page.get_max_key();
-
-        if old_page.get_is_root() {
-            self.table.new_root(new_page_num);
-        } else {
-            let parent_page_num = old_page.get_parent();
-            let parent = self.table.pager.get_page(parent_page_num);
-            parent.internal_update_key(old_max_key, new_max_key);
-            self.table.internal_insert(parent_page_num, new_page_num);
+        let page = self.tree.pager.get_page(self.page_num)?;
+        let key = page.get_leaf_key(self.cell_num)?;
+        let comparator = &self.tree.comparator;
+        let past_end = match &self.end {
+            Bound::Included(end) => comparator.compare(key, end).is_gt(),
+            Bound::Excluded(end) => comparator.compare(key, end).is_ge(),
+            Bound::Unbounded => false,
+        };
+        if past_end {
+            self.end_of_table = true;
         }
+
+        Ok(())
     }
 }
@@ -0,0 +1,270 @@
+use super::pager::{self, CacheStats};
+use super::row::{self, Value};
+use super::schema::{self, Index, Schema};
+use super::{Pager, Storage, Table};
+use crate::Error;
+
+/// The tutorial's table, which every new database starts with
+pub(crate) const DEFAULT_TABLE: &str = "users";
+pub(super) const DEFAULT_TABLE_SQL: &str =
+    "CREATE TABLE users (id INTEGER PRIMARY KEY, username VARCHAR(31), email VARCHAR(255))";
+
+/// An open database file and the tables its catalog lists
+pub struct Database {
+    pub(crate) pager: Pager,
+    catalog: Schema,
+    schemas: Vec<Schema>,
+    in_transaction: bool,
+}
+
+impl Database {
+    pub fn open(filename: &str) -> Result<Self, Error> {
+        Self::open_with_cache_capacity(filename, pager::DEFAULT_CACHE_CAPACITY)
+    }
+
+    /// Open a database keeping at most `capacity` pages in memory
+    pub fn open_with_cache_capacity(filename: &str, capacity: usize) -> Result<Self, Error> {
+        Self::from_pager(Pager::new(filename, capacity)?)
+    }
+
+    /// Open the database kept in `storage`, logging to `wal`. `name` is what errors call it.
+    pub fn open_with_storage(
+        name: &str,
+        storage: impl Storage + 'static,
+        wal: impl Storage + 'static,
+    ) -> Result<Self, Error> {
+        Self::from_pager(Pager::with_storage(
+            name,
+            Box::new(storage),
+            Box::new(wal),
+            pager::DEFAULT_CACHE_CAPACITY,
+        )?)
+    }
+
+    fn from_pager(mut pager: Pager) -> Result<Self, Error> {
+        let catalog_root = pager.get_schema_root()?;
+        let mut database = Self {
+            catalog: Schema::catalog(catalog_root),
+            pager,
+            schemas: Vec::new(),
+            in_transaction: false,
+        };
+
+        if catalog_root == 0 {
+            // New database file
+            let catalog_root = database.new_tree()?;
+            database.pager.set_schema_root(catalog_root)?;
+            database.catalog.root_page_num = catalog_root;
+
+            let schema = crate::core::schema_from_sql(DEFAULT_TABLE_SQL).unwrap();
+            database.create_table(schema)?;
+        } else {
+            database.load_schemas()?;
+        }
+        database.pager.commit()?;
+
+        Ok(database)
+    }
+
+    /// Read the definition of every table from the catalog
+    fn load_schemas(&mut self) -> Result<(), Error> {
+        let corrupted =
+            |name: &str| Error::Corrupt(format!("corrupted catalog entry for '{name}'"));
+
+        let rows = Table::new(&mut self.pager, &self.catalog).rows()?;
+        self.schemas.clear();
+        for row::Row(values) in rows {
+            let [Value::Text(kind), Value::Text(name), Value::Integer(root_page_num), Value::Text(sql)] =
+                &values[..]
+            else {
+                return Err(corrupted("?"));
+            };
+            let root_page_num = *root_page_num as usize;
+            if root_page_num == 0 || root_page_num >= self.pager.num_pages {
+                return Err(corrupted(name));
+            }
+
+            if kind == "index" {
+                // an index comes after the table it belongs to
+                let mut index = crate::core::index_from_sql(sql).map_err(|_| corrupted(name))?;
+                index.root_page_num = root_page_num;
+                let Some(schema) = (self.schemas.iter_mut())
+                    .find(|schema| schema.name.eq_ignore_ascii_case(&index.table))
+                else {
+                    return Err(corrupted(name));
+                };
+                schema.indexes.push(index);
+                continue;
+            }
+
+            let mut schema = crate::core::schema_from_sql(sql).map_err(|_| corrupted(name))?;
+            schema.root_page_num = root_page_num;
+            self.schemas.push(schema);
+        }
+
+        Ok(())
+    }
+
+    /// Give a new table an empty B-tree and record it in the catalog
+    pub(crate) fn create_table(&mut self, mut schema: Schema) -> Result<(), Error> {
+        if self.schema(&schema.name).is_some() {
+            return Err(Error::Invalid(format!(
+                "table '{}' already exists",
+                schema.name
+            )));
+        }
+
+        schema.root_page_num = self.new_tree()?;
+        let entry = row::Row(vec![
+            Value::Text("table".into()),
+            Value::Text(schema.name.clone()),
+            Value::Integer(schema.root_page_num as i64),
+            Value::Text(schema.sql()),
+        ]);
+        Table::new(&mut self.pager, &self.catalog).insert(&entry)?;
+        self.schemas.push(schema);
+
+        Ok(())
+    }
+
+    /// Build the B-tree of a new index from the rows of its table and record it in the catalog
+    pub(crate) fn create_index(&mut self, mut index: Index) -> Result<(), Error> {
+        if (self.schemas.iter().flat_map(|schema| &schema.indexes))
+            .any(|other| other.name.eq_ignore_ascii_case(&index.name))
+        {
+            return Err(Error::Invalid(format!(
+                "index '{}' already exists",
+                index.name
+            )));
+        }
+        let Some(position) =
+            (self.schemas.iter()).position(|schema| schema.name.eq_ignore_ascii_case(&index.table))
+        else {
+            return Err(Error::Invalid(format!("no such table: '{}'", index.table)));
+        };
+
+        // a failed check leaves no tree behind
+        Table::new(&mut self.pager, &self.schemas[position]).check_index(&index)?;
+        index.root_page_num = self.new_tree()?;
+        Table::new(&mut self.pager, &self.schemas[position]).fill_index(&index)?;
+
+        let entry = row::Row(vec![
+            Value::Text("index".into()),
+            Value::Text(index.name.clone()),
+            Value::Integer(index.root_page_num as i64),
+            Value::Text(index.sql()),
+        ]);
+        Table::new(&mut self.pager, &self.catalog).insert(&entry)?;
+        self.schemas[position].indexes.push(index);
+
+        Ok(())
+    }
+
+    /// An empty B-tree, returning its root page
+    fn new_tree(&mut self) -> Result<usize, Error> {
+        let root_page_num = self.pager.get_unused_page_num()?;
+        let page = self.pager.get_page_mut(root_page_num)?;
+        page.init_leaf();
+        page.set_is_root(true);
+
+        Ok(root_page_num)
+    }
+
+    pub(crate) fn schema(&self, name: &str) -> Option<&Schema> {
+        if name.eq_ignore_ascii_case(schema::CATALOG_NAME) {
+            return Some(&self.catalog);
+        }
+
+        self.schemas
+            .iter()
+            .find(|schema| schema.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Every table created so far, leaving out the catalog
+    pub(crate) fn schemas(&self) -> &[Schema] {
+        &self.schemas
+    }
+
+    pub(crate) fn table(&mut self, name: &str) -> Option<Table<'_>> {
+        let schema = if name.eq_ignore_ascii_case(schema::CATALOG_NAME) {
+            &self.catalog
+        } else {
+            self.schemas
+                .iter()
+                .find(|schema| schema.name.eq_ignore_ascii_case(name))?
+        };
+
+        Some(Table::new(&mut self.pager, schema))
+    }
+
+    pub(crate) fn begin(&mut self) -> Result<(), Error> {
+        if self.in_transaction {
+            return Err(Error::Invalid("a transaction is already active".into()));
+        }
+        self.in_transaction = true;
+        self.pager.savepoint();
+
+        Ok(())
+    }
+
+    pub(crate) fn commit(&mut self) -> Result<(), Error> {
+        if !self.in_transaction {
+            return Err(Error::Invalid("no transaction is active".into()));
+        }
+        self.in_transaction = false;
+        self.pager.commit()
+    }
+
+    /// Throw away every change made since `begin`, tables created included
+    pub(crate) fn rollback(&mut self) -> Result<(), Error> {
+        if !self.in_transaction {
+            return Err(Error::Invalid("no transaction is active".into()));
+        }
+        self.abort()
+    }
+
+    /// Throw away every change not committed yet, ending any transaction.
+    /// A statement that failed partway through changing its trees leaves them to this.
+    pub(crate) fn abort(&mut self) -> Result<(), Error> {
+        self.in_transaction = false;
+        self.pager.rollback()?;
+        self.load_schemas()
+    }
+
+    /// Make the changes of the statement just executed durable, unless a
+    /// transaction holds them, in which case the next statement starts from them
+    pub(crate) fn autocommit(&mut self) -> Result<(), Error> {
+        if self.in_transaction {
+            self.pager.savepoint();
+            return Ok(());
+        }
+
+        self.pager.commit()
+    }
+
+    /// Throw away the changes of the statement that just failed, and only those
+    pub(crate) fn undo_statement(&mut self) -> Result<(), Error> {
+        if !self.in_transaction {
+            return self.abort();
+        }
+        self.pager.rollback_to_savepoint()?;
+        self.load_schemas()
+    }
+
+    /// Move every committed change into the database file and empty the log
+    pub(crate) fn checkpoint(&mut self) -> Result<(), Error> {
+        self.pager.checkpoint()
+    }
+
+    /// Close the database, an unfinished transaction is rolled back
+    pub fn close(&mut self) -> Result<(), Error> {
+        if self.in_transaction {
+            self.rollback()?;
+        }
+        self.pager.close()
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.pager.get_cache_stats()
+    }
+}
@@ -0,0 +1,137 @@
+use std::cmp::Ordering;
+
+use super::row::{self, Value};
+use super::schema::Collation;
+
+// A key is a tuple of values, each behind a type tag as in records
+
+/// The values of a key, as they are compared
+enum Part<'a> {
+    Null,
+    Boolean(bool),
+    Integer(i64),
+    Real(f64),
+    Text(&'a [u8]),
+    Blob(&'a [u8]),
+}
+
+/// Orders the keys of a tree value by value, in the order of `Value`, the text
+/// of each value compared with the collation of its column.
+/// A key that runs out first equals the longer one, so a prefix of a key finds
+/// where it starts.
+#[derive(Clone, Debug, Default)]
+pub(super) struct Comparator {
+    /// The collation of each value of a key, those past the end being binary
+    collations: Vec<Collation>,
+}
+
+impl Comparator {
+    pub(super) fn new(collations: Vec<Collation>) -> Self {
+        Self { collations }
+    }
+
+    pub(super) fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        for (i, (a, b)) in parts(a).zip(parts(b)).enumerate() {
+            let ordering = match (a, b) {
+                (Part::Null, Part::Null) => Ordering::Equal,
+                (Part::Boolean(a), Part::Boolean(b)) => a.cmp(&b),
+                (Part::Integer(a), Part::Integer(b)) => a.cmp(&b),
+                (Part::Integer(a), Part::Real(b)) => row::compare_numbers(a, b),
+                (Part::Real(a), Part::Integer(b)) => row::compare_numbers(b, a).reverse(),
+                (Part::Real(a), Part::Real(b)) => row::compare_reals(a, b),
+                (Part::Text(a), Part::Text(b)) => {
+                    let collation = self.collations.get(i).copied().unwrap_or_default();
+                    collation.compare(a, b)
+                }
+                (Part::Blob(a), Part::Blob(b)) => a.cmp(b),
+                (a, b) => a.rank().cmp(&b.rank()),
+            };
+            if ordering.is_ne() {
+                return ordering;
+            }
+        }
+
+        Ordering::Equal
+    }
+}
+
+impl Part<'_> {
+    /// Where values of this type sort among those of other types
+    fn rank(&self) -> u8 {
+        match self {
+            Part::Null => 0,
+            Part::Boolean(_) => 1,
+            Part::Integer(_) | Part::Real(_) => 2,
+            Part::Text(_) => 3,
+            Part::Blob(_) => 4,
+        }
+    }
+}
+
+pub(super) fn encode(values: &[Value]) -> Vec<u8> {
+    let mut key = Vec::new();
+    for value in values {
+        value.encode(&mut key);
+    }
+
+    key
+}
+
+pub(super) fn decode(key: &[u8]) -> Vec<Value> {
+    parts(key)
+        .map(|part| match part {
+            Part::Null => Value::Null,
+            Part::Boolean(b) => Value::Boolean(b),
+            Part::Integer(n) => Value::Integer(n),
+            Part::Real(n) => Value::Real(n),
+            Part::Text(bytes) => Value::Text(String::from_utf8_lossy(bytes).into_owned()),
+            Part::Blob(bytes) => Value::Blob(bytes.to_vec()),
+        })
+        .collect()
+}
+
+/// The key of a row keyed by an integer
+pub(super) fn encode_integer(n: usize) -> Vec<u8> {
+    encode(&[Value::Integer(n as i64)])
+}
+
+/// The row id a row is keyed by
+pub(super) fn decode_integer(key: &[u8]) -> i64 {
+    match parts(key).next() {
+        Some(Part::Integer(n)) => n,
+        _ => 0,
+    }
+}
+
+/// The values of a key as the tree shows them
+pub(super) fn to_string(key: &[u8]) -> String {
+    let values: Vec<String> = decode(key).iter().map(Value::to_string).collect();
+
+    values.join(", ")
+}
+
+/// Walk the values of a key, stopping at the first malformed one
+fn parts(key: &[u8]) -> impl Iterator<Item = Part<'_>> {
+    let mut rest = key;
+    std::iter::from_fn(move || {
+        let (&tag, tail) = rest.split_first()?;
+        let (start, len) = match row::payload_size(tag)? {
+            Some(size) => (0, size),
+            None => (
+                4,
+                u32::from_le_bytes(tail.get(..4)?.try_into().unwrap()) as usize,
+            ),
+        };
+        let bytes = tail.get(start..start + len)?;
+        rest = &tail[start + len..];
+        let part = match tag {
+            row::NULL_TAG => Part::Null,
+            row::BOOLEAN_TAG => Part::Boolean(bytes[0] != 0),
+            row::INTEGER_TAG => Part::Integer(i64::from_le_bytes(bytes.try_into().unwrap())),
+            row::REAL_TAG => Part::Real(f64::from_le_bytes(bytes.try_into().unwrap())),
+            row::TEXT_TAG => Part::Text(bytes),
+            _ => Part::Blob(bytes),
+        };
+        Some(part)
+    })
+}
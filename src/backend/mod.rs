@@ -1,13 +1,34 @@
 pub(super) mod row;
 
-pub(super) type Row = row::Row;
-pub type Table = table::Table;
+pub type Row = row::Row;
+pub type Value = row::Value;
+pub type CacheStats = pager::CacheStats;
+pub type Database = database::Database;
+pub type Index = schema::Index;
+pub type Schema = schema::Schema;
+pub type Table<'a> = table::Table<'a>;
 
+pub use storage::{Fault, FaultyStorage, FileStorage, MemoryStorage, Storage};
+
+pub(crate) use database::DEFAULT_TABLE;
+pub(crate) use schema::{Collation, Column, ColumnType, CATALOG_NAME};
+pub(crate) use sorter::{record_size, Sorter};
+pub(crate) use table::{KeyRange, Scan};
+
+mod btree;
 mod cursor;
+mod database;
+mod key;
+mod overflow;
 mod page;
 mod pager;
+mod schema;
+mod sorter;
+mod storage;
 mod table;
+mod upgrade;
+mod wal;
 
 type Cursor<'a> = cursor::Cursor<'a>;
 type Page = page::Page;
-type Pager = pager::Pager;
+pub(crate) type Pager = pager::Pager;
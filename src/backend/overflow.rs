@@ -0,0 +1,57 @@
+use super::{page, Pager};
+use crate::Error;
+
+/// Spread `data` over a chain of fresh overflow pages, returning the first of them
+pub(super) fn write(pager: &mut Pager, data: &[u8]) -> Result<usize, Error> {
+    let chunks: Vec<&[u8]> = data.chunks(page::OVERFLOW_DATA_SIZE).collect();
+    // take every page before writing any, the free list runs through them
+    let page_nums = (chunks.iter())
+        .map(|_| pager.get_unused_page_num())
+        .collect::<Result<Vec<usize>, Error>>()?;
+    for (i, chunk) in chunks.iter().enumerate() {
+        let next_page_num = page_nums.get(i + 1).copied().unwrap_or(0);
+        pager
+            .get_page_mut(page_nums[i])?
+            .init_overflow(next_page_num, chunk);
+    }
+
+    Ok(page_nums[0])
+}
+
+/// Gather the `size` bytes stored in the chain starting at `page_num`
+pub(super) fn read(pager: &mut Pager, page_num: usize, size: usize) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::with_capacity(size);
+    let mut page_num = page_num;
+    while data.len() < size {
+        check(pager, page_num)?;
+        let page = pager.get_page(page_num)?;
+        let chunk_size = (size - data.len()).min(page::OVERFLOW_DATA_SIZE);
+        data.extend_from_slice(page.get_overflow_data(chunk_size));
+        page_num = page.get_overflow_next_page()?;
+    }
+
+    Ok(data)
+}
+
+/// Return the chain holding `size` bytes, starting at `page_num`, to the free list
+pub(super) fn free(pager: &mut Pager, page_num: usize, size: usize) -> Result<(), Error> {
+    let mut page_num = page_num;
+    for _ in 0..size.div_ceil(page::OVERFLOW_DATA_SIZE) {
+        check(pager, page_num)?;
+        let next_page_num = pager.get_page(page_num)?.get_overflow_next_page()?;
+        pager.free_page(page_num)?;
+        page_num = next_page_num;
+    }
+
+    Ok(())
+}
+
+fn check(pager: &Pager, page_num: usize) -> Result<(), Error> {
+    if page_num == 0 || page_num >= pager.num_pages {
+        return Err(Error::Corrupt(format!(
+            "corrupted overflow chain at page '{page_num}'"
+        )));
+    }
+
+    Ok(())
+}
@@ -1,6 +1,7 @@
 use std::{cmp::Ordering, mem};
 
-use super::row;
+use super::key::Comparator;
+use crate::Error;
 
 pub(super) enum PageType {
     Internal,
@@ -10,57 +11,217 @@ pub(super) enum PageType {
 #[derive(Clone)]
 pub(super) struct Page(pub(super) Vec<u8>);
 
+/// A key and the bytes following it in its leaf cell
+type LeafCell = (Vec<u8>, Vec<u8>);
+/// The part of a value kept in its leaf cell, and where the rest of it is
+type LeafValue = (Vec<u8>, Option<(usize, usize)>);
+
 pub(super) const PAGE_SIZE: usize = 4096;
 
+// Header page layout
+pub(super) const MAGIC: &[u8; MAGIC_SIZE] = b"resql database\0\0";
+pub(super) const FORMAT_VERSION: u8 = 1;
+const MAGIC_OFFSET: usize = 0;
+const MAGIC_SIZE: usize = 16;
+const FORMAT_VERSION_OFFSET: usize = MAGIC_OFFSET + MAGIC_SIZE;
+const FORMAT_VERSION_SIZE: usize = mem::size_of::<u8>();
+const HEADER_PAGE_SIZE_OFFSET: usize = FORMAT_VERSION_OFFSET + FORMAT_VERSION_SIZE;
+const HEADER_PAGE_SIZE_SIZE: usize = mem::size_of::<u32>();
+const NUM_PAGES_OFFSET: usize = HEADER_PAGE_SIZE_OFFSET + HEADER_PAGE_SIZE_SIZE;
+const NUM_PAGES_SIZE: usize = mem::size_of::<u32>();
+const FIRST_FREE_PAGE_OFFSET: usize = NUM_PAGES_OFFSET + NUM_PAGES_SIZE;
+const FIRST_FREE_PAGE_SIZE: usize = mem::size_of::<u32>();
+const NUM_FREE_PAGES_OFFSET: usize = FIRST_FREE_PAGE_OFFSET + FIRST_FREE_PAGE_SIZE;
+const NUM_FREE_PAGES_SIZE: usize = mem::size_of::<u32>();
+const SCHEMA_ROOT_OFFSET: usize = NUM_FREE_PAGES_OFFSET + NUM_FREE_PAGES_SIZE;
+
+// Free page layout
+const NEXT_FREE_PAGE_OFFSET: usize = 0;
+
+// Overflow page layout
+const OVERFLOW_NEXT_PAGE_OFFSET: usize = 0;
+const OVERFLOW_NEXT_PAGE_SIZE: usize = mem::size_of::<u32>();
+const OVERFLOW_DATA_OFFSET: usize = OVERFLOW_NEXT_PAGE_OFFSET + OVERFLOW_NEXT_PAGE_SIZE;
+pub(super) const OVERFLOW_DATA_SIZE: usize = PAGE_SIZE - OVERFLOW_DATA_OFFSET;
+
 // Common page header layout
 const TYPE_OFFSET: usize = 0;
 const TYPE_SIZE: usize = mem::size_of::<u8>();
 const IS_ROOT_OFFSET: usize = TYPE_SIZE;
 const IS_ROOT_SIZE: usize = mem::size_of::<u8>();
 const PARENT_OFFSET: usize = IS_ROOT_OFFSET + IS_ROOT_SIZE;
-const PARENT_SIZE: usize = mem::size_of::<usize>();
+const PARENT_SIZE: usize = mem::size_of::<u32>();
 pub(super) const COMMON_HEADER_SIZE: usize = TYPE_SIZE + IS_ROOT_SIZE + PARENT_SIZE;
 
-// Leaf page header layout
-const LEAF_NUM_CELLS_OFFSET: usize = COMMON_HEADER_SIZE;
-const LEAF_NUM_CELLS_SIZE: usize = mem::size_of::<usize>();
-const LEAF_NEXT_LEAF_OFFSET: usize = LEAF_NUM_CELLS_OFFSET + LEAF_NUM_CELLS_SIZE;
-const LEAF_NEXT_LEAF_SIZE: usize = mem::size_of::<usize>();
-pub(super) const LEAF_HEADER_SIZE: usize =
-    COMMON_HEADER_SIZE + LEAF_NUM_CELLS_SIZE + LEAF_NEXT_LEAF_SIZE;
-
-// Leaf page body layout
-const LEAF_KEY_OFFSET: usize = 0;
-const LEAF_KEY_SIZE: usize = mem::size_of::<usize>();
-const LEAF_VALUE_OFFSET: usize = LEAF_KEY_SIZE;
-const LEAF_VALUE_SIZE: usize = row::ROW_SIZE;
-pub(super) const LEAF_CELL_SIZE: usize = LEAF_KEY_SIZE + LEAF_VALUE_SIZE;
-
-pub(super) const LEAF_MAX_CELLS: usize = (PAGE_SIZE - LEAF_HEADER_SIZE) / LEAF_CELL_SIZE;
-pub(super) const LEAF_RIGHT_SPLIT_COUNT: usize = (LEAF_MAX_CELLS + 1) / 2;
-pub(super) const LEAF_LEFT_SPLIT_COUNT: usize = LEAF_MAX_CELLS + 1 - LEAF_RIGHT_SPLIT_COUNT;
-
-// Internal page header layout
-const INTERNAL_NUM_KEYS_OFFSET: usize = COMMON_HEADER_SIZE;
-const INTERNAL_NUM_KEYS_SIZE: usize = mem::size_of::<usize>();
-const INTERNAL_RIGHT_CHILD_OFFSET: usize = INTERNAL_NUM_KEYS_OFFSET + INTERNAL_NUM_KEYS_SIZE;
-const INTERNAL_RIGHT_CHILD_SIZE: usize = mem::size_of::<usize>();
-const INTERNAL_HEADER_SIZE: usize =
-    COMMON_HEADER_SIZE + INTERNAL_NUM_KEYS_SIZE + INTERNAL_RIGHT_CHILD_SIZE;
-
-// Internal page body layout
-const INTERNAL_KEY_SIZE: usize = mem::size_of::<usize>();
-const INTERNAL_CHILD_SIZE: usize = mem::size_of::<usize>();
-const INTERNAL_CELL_SIZE: usize = INTERNAL_KEY_SIZE + INTERNAL_CHILD_SIZE;
-
-pub(super) const INTERNAL_MAX_CELLS: usize = 3; // keep this small for testing
+// B-tree page header layout, the same for leaves and internal pages: where a
+// leaf points to the next leaf, an internal page points to its right child
+const NUM_CELLS_OFFSET: usize = COMMON_HEADER_SIZE;
+const NUM_CELLS_SIZE: usize = mem::size_of::<u32>();
+const NEXT_LEAF_OFFSET: usize = NUM_CELLS_OFFSET + NUM_CELLS_SIZE;
+const RIGHT_CHILD_OFFSET: usize = NEXT_LEAF_OFFSET;
+const LINK_SIZE: usize = mem::size_of::<u32>();
+const CONTENT_START_OFFSET: usize = NEXT_LEAF_OFFSET + LINK_SIZE;
+const CONTENT_START_SIZE: usize = mem::size_of::<u16>();
+const FRAGMENTED_SIZE_OFFSET: usize = CONTENT_START_OFFSET + CONTENT_START_SIZE;
+const FRAGMENTED_SIZE_SIZE: usize = mem::size_of::<u16>();
+pub(super) const TREE_HEADER_SIZE: usize =
+    COMMON_HEADER_SIZE + NUM_CELLS_SIZE + LINK_SIZE + CONTENT_START_SIZE + FRAGMENTED_SIZE_SIZE;
+
+// B-tree page body layout: an array of cell offsets follows the header, while
+// the cells themselves fill the page from its end. A cell starts with its key.
+const CELL_POINTER_SIZE: usize = mem::size_of::<u16>();
+const KEY_SIZE_SIZE: usize = mem::size_of::<u16>();
+// The key of a leaf cell is followed by the size of the part of its value kept in the page
+const LEAF_LOCAL_SIZE_SIZE: usize = mem::size_of::<u16>();
+// A cell whose value goes on in overflow pages sets this bit of its local size,
+// and follows it with the size of the whole value and the first overflow page
+const LEAF_OVERFLOW_FLAG: usize = 0x8000;
+const LEAF_VALUE_SIZE_OFFSET: usize = LEAF_LOCAL_SIZE_SIZE;
+const LEAF_VALUE_SIZE_SIZE: usize = mem::size_of::<u32>();
+const LEAF_FIRST_OVERFLOW_PAGE_OFFSET: usize = LEAF_VALUE_SIZE_OFFSET + LEAF_VALUE_SIZE_SIZE;
+const LEAF_FIRST_OVERFLOW_PAGE_SIZE: usize = mem::size_of::<u32>();
+const LEAF_OVERFLOW_HEADER_SIZE: usize = LEAF_VALUE_SIZE_SIZE + LEAF_FIRST_OVERFLOW_PAGE_SIZE;
+// The key of an internal cell is followed by the child it is the largest key of
+const INTERNAL_CHILD_SIZE: usize = mem::size_of::<u32>();
+
+pub(super) const CELL_SPACE: usize = PAGE_SIZE - TREE_HEADER_SIZE;
+// A cell takes at most a quarter of a page, so a full page plus one cell always splits in two
+pub(super) const MAX_CELL_SPACE: usize = CELL_SPACE / 4;
+// The largest key still leaves room for a value that goes on in overflow pages
+pub(super) const MAX_KEY_SIZE: usize = MAX_CELL_SPACE
+    - CELL_POINTER_SIZE
+    - KEY_SIZE_SIZE
+    - LEAF_LOCAL_SIZE_SIZE
+    - LEAF_OVERFLOW_HEADER_SIZE;
+pub(super) const MIN_USED_SPACE: usize = CELL_SPACE / 3;
+
+// Every integer is stored little-endian, whatever the host: offsets and
+// sizes within a page as u16, everything else as u32
+impl Page {
+    fn read_u32(&self, offset: usize) -> Result<usize, Error> {
+        let bytes = self.read_bytes(offset, mem::size_of::<u32>())?;
+
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+    }
+
+    fn write_u32(&mut self, offset: usize, value: usize) {
+        let end = offset + mem::size_of::<u32>();
+        self.0[offset..end].copy_from_slice(&(value as u32).to_le_bytes());
+    }
+
+    fn read_u16(&self, offset: usize) -> Result<usize, Error> {
+        let bytes = self.read_bytes(offset, mem::size_of::<u16>())?;
+
+        Ok(u16::from_le_bytes(bytes.try_into().unwrap()) as usize)
+    }
+
+    /// `size` bytes at `offset`, which a corrupted page can point past its end
+    fn read_bytes(&self, offset: usize, size: usize) -> Result<&[u8], Error> {
+        self.0.get(offset..offset + size).ok_or_else(corrupted)
+    }
+
+    fn write_u16(&mut self, offset: usize, value: usize) {
+        let end = offset + mem::size_of::<u16>();
+        self.0[offset..end].copy_from_slice(&(value as u16).to_le_bytes());
+    }
+}
+
+// Header page methods
+impl Page {
+    pub(super) fn init_header(&mut self) {
+        self.0 = vec![0; PAGE_SIZE];
+        self.0[MAGIC_OFFSET..MAGIC_OFFSET + MAGIC_SIZE].copy_from_slice(MAGIC);
+        self.0[FORMAT_VERSION_OFFSET] = FORMAT_VERSION;
+
+        self.write_u32(HEADER_PAGE_SIZE_OFFSET, PAGE_SIZE);
+
+        self.set_num_pages(1); // the header itself
+        self.set_first_free_page(0);
+        self.set_num_free_pages(0);
+        self.set_schema_root(0); // 0 represents no schema yet
+    }
+
+    pub(super) fn get_magic(&self) -> &[u8] {
+        &self.0[MAGIC_OFFSET..MAGIC_OFFSET + MAGIC_SIZE]
+    }
+
+    pub(super) fn get_format_version(&self) -> u8 {
+        self.0[FORMAT_VERSION_OFFSET]
+    }
+
+    pub(super) fn get_page_size(&self) -> Result<usize, Error> {
+        self.read_u32(HEADER_PAGE_SIZE_OFFSET)
+    }
+
+    pub(super) fn get_num_pages(&self) -> Result<usize, Error> {
+        self.read_u32(NUM_PAGES_OFFSET)
+    }
+
+    pub(super) fn get_first_free_page(&self) -> Result<usize, Error> {
+        self.read_u32(FIRST_FREE_PAGE_OFFSET)
+    }
+
+    pub(super) fn get_num_free_pages(&self) -> Result<usize, Error> {
+        self.read_u32(NUM_FREE_PAGES_OFFSET)
+    }
+
+    pub(super) fn get_schema_root(&self) -> Result<usize, Error> {
+        self.read_u32(SCHEMA_ROOT_OFFSET)
+    }
+
+    pub(super) fn set_num_pages(&mut self, num_pages: usize) {
+        self.write_u32(NUM_PAGES_OFFSET, num_pages);
+    }
+
+    pub(super) fn set_first_free_page(&mut self, page_num: usize) {
+        self.write_u32(FIRST_FREE_PAGE_OFFSET, page_num);
+    }
+
+    pub(super) fn set_num_free_pages(&mut self, num_free_pages: usize) {
+        self.write_u32(NUM_FREE_PAGES_OFFSET, num_free_pages);
+    }
+
+    pub(super) fn set_schema_root(&mut self, page_num: usize) {
+        self.write_u32(SCHEMA_ROOT_OFFSET, page_num);
+    }
+}
+
+// Free page methods
+impl Page {
+    pub(super) fn init_free(&mut self, next_page_num: usize) {
+        self.0 = vec![0; PAGE_SIZE];
+        self.write_u32(NEXT_FREE_PAGE_OFFSET, next_page_num);
+    }
+
+    pub(super) fn get_next_free_page(&self) -> Result<usize, Error> {
+        self.read_u32(NEXT_FREE_PAGE_OFFSET)
+    }
+}
+
+// Overflow page methods
+impl Page {
+    pub(super) fn init_overflow(&mut self, next_page_num: usize, data: &[u8]) {
+        self.0 = vec![0; PAGE_SIZE];
+        self.write_u32(OVERFLOW_NEXT_PAGE_OFFSET, next_page_num);
+        self.0[OVERFLOW_DATA_OFFSET..OVERFLOW_DATA_OFFSET + data.len()].copy_from_slice(data);
+    }
+
+    pub(super) fn get_overflow_next_page(&self) -> Result<usize, Error> {
+        self.read_u32(OVERFLOW_NEXT_PAGE_OFFSET)
+    }
+
+    pub(super) fn get_overflow_data(&self, size: usize) -> &[u8] {
+        &self.0[OVERFLOW_DATA_OFFSET..OVERFLOW_DATA_OFFSET + size]
+    }
+}
 
 // Common page methods
 impl Page {
-    pub(super) fn get_type(&self) -> PageType {
+    pub(super) fn get_type(&self) -> Result<PageType, Error> {
         match self.0[TYPE_OFFSET] {
-            0 => PageType::Internal,
-            _ => PageType::Leaf,
+            0 => Ok(PageType::Internal),
+            1 => Ok(PageType::Leaf),
+            _ => Err(corrupted()),
         }
     }
 
@@ -68,18 +229,12 @@ impl Page {
         !matches!(self.0[IS_ROOT_OFFSET], 0)
     }
 
-    pub(super) fn get_parent(&self) -> usize {
-        let start = PARENT_OFFSET;
-        let end = start + PARENT_SIZE;
-
-        usize::from_ne_bytes(self.0[start..end].try_into().unwrap())
+    pub(super) fn get_parent(&self) -> Result<usize, Error> {
+        self.read_u32(PARENT_OFFSET)
     }
 
-    pub(super) fn get_max_key(&self) -> usize {
-        match self.get_type() {
-            PageType::Internal => self.get_internal_key(self.get_internal_num_keys() - 1),
-            PageType::Leaf => self.get_leaf_key(self.get_leaf_num_cells() - 1),
-        }
+    pub(super) fn is_underfull(&self) -> Result<bool, Error> {
+        Ok(self.get_used_space()? < MIN_USED_SPACE)
     }
 
     pub(super) fn set_type(&mut self, page_type: PageType) {
@@ -97,198 +252,489 @@ impl Page {
     }
 
     pub(super) fn set_parent(&mut self, parent_page_num: usize) {
-        let start = PARENT_OFFSET;
-        let end = start + PARENT_SIZE;
-        self.0[start..end].clone_from_slice(&parent_page_num.to_ne_bytes());
+        self.write_u32(PARENT_OFFSET, parent_page_num);
     }
 }
 
-// Leaf page methods
+// B-tree page methods, the cells of leaves and internal pages only differing by what follows their key
 impl Page {
-    pub(super) fn init_leaf(&mut self) {
+    fn init_tree(&mut self, page_type: PageType) {
         self.0 = vec![0; PAGE_SIZE];
-        self.set_type(PageType::Leaf);
+        self.set_type(page_type);
         self.set_is_root(false);
-        self.set_leaf_num_cells(0);
-        self.set_leaf_next_leaf(0); // 0 represents no sibling. page 0 is reserved for root
+        self.set_num_cells(0);
+        self.write_u32(NEXT_LEAF_OFFSET, 0); // 0 represents no page. page 0 is reserved for the header
+        self.set_content_start(PAGE_SIZE);
+        self.set_fragmented_size(0);
     }
 
-    pub(super) fn get_leaf_num_cells(&self) -> usize {
-        let start = LEAF_NUM_CELLS_OFFSET;
-        let end = start + LEAF_NUM_CELLS_SIZE;
+    /// The number of cells, whose pointers must end before the cells start
+    fn get_num_cells(&self) -> Result<usize, Error> {
+        let num_cells = self.read_u32(NUM_CELLS_OFFSET)?;
+        if num_cells > (self.get_content_start()? - TREE_HEADER_SIZE) / CELL_POINTER_SIZE {
+            return Err(corrupted());
+        }
 
-        usize::from_ne_bytes(self.0[start..end].try_into().unwrap())
+        Ok(num_cells)
     }
 
-    pub(super) fn get_leaf_next_leaf(&self) -> usize {
-        let start = LEAF_NEXT_LEAF_OFFSET;
-        let end = start + LEAF_NEXT_LEAF_SIZE;
+    fn get_content_start(&self) -> Result<usize, Error> {
+        let content_start = self.read_u16(CONTENT_START_OFFSET)?;
+        if !(TREE_HEADER_SIZE..=PAGE_SIZE).contains(&content_start) {
+            return Err(corrupted());
+        }
 
-        usize::from_ne_bytes(self.0[start..end].try_into().unwrap())
+        Ok(content_start)
     }
 
-    pub(super) fn get_leaf_cell(&self, cell_num: usize) -> (usize, Vec<u8>) {
-        (self.get_leaf_key(cell_num), self.get_leaf_value(cell_num))
+    fn get_fragmented_size(&self) -> Result<usize, Error> {
+        self.read_u16(FRAGMENTED_SIZE_OFFSET)
     }
 
-    pub(super) fn get_leaf_key(&self, cell_num: usize) -> usize {
-        let start = LEAF_HEADER_SIZE + cell_num * LEAF_CELL_SIZE + LEAF_KEY_OFFSET;
-        let end = start + LEAF_KEY_SIZE;
+    /// Where a cell starts, somewhere between the start of the content and the end of the page
+    fn get_cell_offset(&self, cell_num: usize) -> Result<usize, Error> {
+        if cell_num >= self.get_num_cells()? {
+            return Err(corrupted());
+        }
+        let offset = self.read_u16(TREE_HEADER_SIZE + cell_num * CELL_POINTER_SIZE)?;
+        if offset < self.get_content_start()? || offset >= PAGE_SIZE {
+            return Err(corrupted());
+        }
 
-        usize::from_ne_bytes(self.0[start..end].try_into().unwrap())
+        Ok(offset)
     }
 
-    pub(super) fn get_leaf_value(&self, cell_num: usize) -> Vec<u8> {
-        let start = LEAF_HEADER_SIZE + cell_num * LEAF_CELL_SIZE + LEAF_VALUE_OFFSET;
-        let end = start + LEAF_VALUE_SIZE;
+    /// The bytes a cell takes, its key included, which must end within the page
+    fn get_cell_size(&self, offset: usize) -> Result<usize, Error> {
+        let key_end = offset + KEY_SIZE_SIZE + self.read_u16(offset)?;
+        let rest_size = match self.get_type()? {
+            PageType::Internal => INTERNAL_CHILD_SIZE,
+            PageType::Leaf => {
+                let local_size = self.read_u16(key_end)?;
+                if local_size & LEAF_OVERFLOW_FLAG == 0 {
+                    LEAF_LOCAL_SIZE_SIZE + local_size
+                } else {
+                    LEAF_LOCAL_SIZE_SIZE
+                        + LEAF_OVERFLOW_HEADER_SIZE
+                        + (local_size & !LEAF_OVERFLOW_FLAG)
+                }
+            }
+        };
+        if key_end + rest_size > PAGE_SIZE {
+            return Err(corrupted());
+        }
 
-        Vec::from(&self.0[start..end])
+        Ok(key_end - offset + rest_size)
     }
 
-    pub(super) fn set_leaf_num_cells(&mut self, num_cells: usize) {
-        let start = LEAF_NUM_CELLS_OFFSET;
-        let end = start + LEAF_NUM_CELLS_SIZE;
-        self.0[start..end].clone_from_slice(&num_cells.to_ne_bytes());
+    fn get_cell_key(&self, cell_num: usize) -> Result<&[u8], Error> {
+        let offset = self.get_cell_offset(cell_num)?;
+        let cell_size = self.get_cell_size(offset)?;
+        let key_size = self.read_u16(offset)?;
+
+        Ok(&self.0[offset + KEY_SIZE_SIZE..offset + cell_size][..key_size])
     }
 
-    pub(super) fn set_leaf_next_leaf(&mut self, next_page_num: usize) {
-        let start = LEAF_NEXT_LEAF_OFFSET;
-        let end = start + LEAF_NEXT_LEAF_SIZE;
-        self.0[start..end].clone_from_slice(&next_page_num.to_ne_bytes());
+    /// The bytes of a cell following its key
+    fn get_cell_rest(&self, cell_num: usize) -> Result<&[u8], Error> {
+        let offset = self.get_cell_offset(cell_num)?;
+        let cell_size = self.get_cell_size(offset)?;
+        let key_size = self.read_u16(offset)?;
+
+        Ok(&self.0[offset + KEY_SIZE_SIZE + key_size..offset + cell_size])
     }
 
-    pub(super) fn set_leaf_cell(&mut self, cell_num: usize, (key, value): (usize, Vec<u8>)) {
-        self.set_leaf_key(cell_num, key);
-        self.set_leaf_value(cell_num, value);
+    /// The bytes taken by cells and their pointers
+    pub(super) fn get_used_space(&self) -> Result<usize, Error> {
+        Ok(CELL_SPACE - self.get_free_space()?)
     }
 
-    pub(super) fn set_leaf_key(&mut self, cell_num: usize, key: usize) {
-        let start = LEAF_HEADER_SIZE + cell_num * LEAF_CELL_SIZE;
-        let end = start + LEAF_KEY_SIZE;
-        self.0[start..end].clone_from_slice(&key.to_ne_bytes());
+    /// The bytes left for new cells, counting the holes deleted cells left behind
+    fn get_free_space(&self) -> Result<usize, Error> {
+        let pointers_end = TREE_HEADER_SIZE + self.get_num_cells()? * CELL_POINTER_SIZE;
+        let free_space = self.get_content_start()? - pointers_end + self.get_fragmented_size()?;
+        if free_space > CELL_SPACE {
+            return Err(corrupted());
+        }
+
+        Ok(free_space)
     }
 
-    pub(super) fn set_leaf_value(&mut self, cell_num: usize, value: Vec<u8>) {
-        let start = LEAF_HEADER_SIZE + cell_num * LEAF_CELL_SIZE + LEAF_KEY_SIZE;
-        let end = start + LEAF_VALUE_SIZE;
-        self.0[start..end].copy_from_slice(&value);
+    fn set_num_cells(&mut self, num_cells: usize) {
+        self.write_u32(NUM_CELLS_OFFSET, num_cells);
     }
 
-    pub(super) fn leaf_find(&self, key: usize) -> usize {
+    fn set_content_start(&mut self, offset: usize) {
+        self.write_u16(CONTENT_START_OFFSET, offset);
+    }
+
+    fn set_fragmented_size(&mut self, size: usize) {
+        self.write_u16(FRAGMENTED_SIZE_OFFSET, size);
+    }
+
+    /// Add a cell at `cell_num`, which the caller made sure there is room for
+    fn insert_cell(&mut self, cell_num: usize, key: &[u8], rest: &[u8]) -> Result<(), Error> {
+        let num_cells = self.get_num_cells()?;
+        let cell_size = KEY_SIZE_SIZE + key.len() + rest.len();
+        let pointers_end = TREE_HEADER_SIZE + (num_cells + 1) * CELL_POINTER_SIZE;
+        if self.get_content_start()? < pointers_end + cell_size {
+            // the room is there, but scattered between the cells
+            self.defragment()?;
+        }
+
+        let offset = self.get_content_start()? - cell_size;
+        let key_end = offset + KEY_SIZE_SIZE + key.len();
+        self.write_u16(offset, key.len());
+        self.0[offset + KEY_SIZE_SIZE..key_end].copy_from_slice(key);
+        self.0[key_end..offset + cell_size].copy_from_slice(rest);
+        self.set_content_start(offset);
+
+        let pointer = TREE_HEADER_SIZE + cell_num * CELL_POINTER_SIZE;
+        let pointers_end = TREE_HEADER_SIZE + num_cells * CELL_POINTER_SIZE;
+        self.0
+            .copy_within(pointer..pointers_end, pointer + CELL_POINTER_SIZE);
+        self.write_u16(pointer, offset);
+        self.set_num_cells(num_cells + 1);
+
+        Ok(())
+    }
+
+    fn remove_cell(&mut self, cell_num: usize) -> Result<(), Error> {
+        let num_cells = self.get_num_cells()?;
+        let offset = self.get_cell_offset(cell_num)?;
+        let cell_size = self.get_cell_size(offset)?;
+        self.0[offset..offset + cell_size].fill(0);
+        if offset == self.get_content_start()? {
+            self.set_content_start(offset + cell_size);
+        } else {
+            let fragmented_size = self.get_fragmented_size()?;
+            self.set_fragmented_size(fragmented_size + cell_size);
+        }
+
+        let pointer = TREE_HEADER_SIZE + cell_num * CELL_POINTER_SIZE;
+        let pointers_end = TREE_HEADER_SIZE + num_cells * CELL_POINTER_SIZE;
+        self.0
+            .copy_within(pointer + CELL_POINTER_SIZE..pointers_end, pointer);
+        self.0[pointers_end - CELL_POINTER_SIZE..pointers_end].fill(0);
+        self.set_num_cells(num_cells - 1);
+
+        Ok(())
+    }
+
+    /// Drop every cell, keeping the header
+    fn clear_cells(&mut self) {
+        self.0[TREE_HEADER_SIZE..].fill(0);
+        self.set_num_cells(0);
+        self.set_content_start(PAGE_SIZE);
+        self.set_fragmented_size(0);
+    }
+
+    /// Move every cell against the end of the page, closing the holes between them
+    fn defragment(&mut self) -> Result<(), Error> {
+        let cells = (0..self.get_num_cells()?)
+            .map(|i| {
+                Ok((
+                    self.get_cell_key(i)?.to_vec(),
+                    self.get_cell_rest(i)?.to_vec(),
+                ))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        self.clear_cells();
+        for (i, (key, rest)) in cells.iter().enumerate() {
+            self.insert_cell(i, key, rest)?;
+        }
+
+        Ok(())
+    }
+
+    /// The first cell whose key is not less than `key`, or the number of cells if there is none
+    fn find_cell(&self, key: &[u8], comparator: &Comparator) -> Result<usize, Error> {
         let mut min_index = 0;
-        let mut one_past_max_index = self.get_leaf_num_cells();
+        let mut one_past_max_index = self.get_num_cells()?;
         while one_past_max_index != min_index {
             let index = (min_index + one_past_max_index) / 2;
-            match self.get_leaf_key(index).cmp(&key) {
-                Ordering::Equal => return index,
-                Ordering::Greater => one_past_max_index = index,
+            match comparator.compare(self.get_cell_key(index)?, key) {
+                Ordering::Equal | Ordering::Greater => one_past_max_index = index,
                 Ordering::Less => min_index = index + 1,
             }
         }
 
-        min_index
+        Ok(min_index)
     }
 }
 
+// Leaf page methods
+impl Page {
+    pub(super) fn init_leaf(&mut self) {
+        self.init_tree(PageType::Leaf);
+    }
+
+    pub(super) fn get_leaf_num_cells(&self) -> Result<usize, Error> {
+        self.get_num_cells()
+    }
+
+    pub(super) fn get_leaf_next_leaf(&self) -> Result<usize, Error> {
+        self.read_u32(NEXT_LEAF_OFFSET)
+    }
+
+    /// A key and the bytes following it in its cell, which can move to another page as they are
+    pub(super) fn get_leaf_cell(&self, cell_num: usize) -> Result<LeafCell, Error> {
+        Ok((
+            self.get_cell_key(cell_num)?.to_vec(),
+            self.get_cell_rest(cell_num)?.to_vec(),
+        ))
+    }
+
+    pub(super) fn get_leaf_cells(&self) -> Result<Vec<LeafCell>, Error> {
+        (0..self.get_leaf_num_cells()?)
+            .map(|i| self.get_leaf_cell(i))
+            .collect()
+    }
+
+    pub(super) fn get_leaf_key(&self, cell_num: usize) -> Result<&[u8], Error> {
+        self.get_cell_key(cell_num)
+    }
+
+    /// The part of a value kept in the page and, if it goes on in overflow pages,
+    /// the size of the whole value and the first of those pages
+    pub(super) fn get_leaf_value(&self, cell_num: usize) -> Result<LeafValue, Error> {
+        // `get_cell_size` made sure the local part and the overflow header are all there
+        let rest = self.get_cell_rest(cell_num)?;
+        let read_u32 = |offset: usize| {
+            u32::from_le_bytes(rest[offset..offset + 4].try_into().unwrap()) as usize
+        };
+        let local_size = u16::from_le_bytes([rest[0], rest[1]]) as usize;
+        if local_size & LEAF_OVERFLOW_FLAG == 0 {
+            return Ok((rest[LEAF_LOCAL_SIZE_SIZE..].to_vec(), None));
+        }
+
+        Ok((
+            rest[LEAF_LOCAL_SIZE_SIZE + LEAF_OVERFLOW_HEADER_SIZE..].to_vec(),
+            Some((
+                read_u32(LEAF_VALUE_SIZE_OFFSET),
+                read_u32(LEAF_FIRST_OVERFLOW_PAGE_OFFSET),
+            )),
+        ))
+    }
+
+    pub(super) fn leaf_has_room(&self, key_size: usize, body_size: usize) -> Result<bool, Error> {
+        Ok(leaf_cell_space(key_size, body_size) <= self.get_free_space()?)
+    }
+
+    pub(super) fn set_leaf_next_leaf(&mut self, next_page_num: usize) {
+        self.write_u32(NEXT_LEAF_OFFSET, next_page_num);
+    }
+
+    /// Overwrite the cells of the page, packing them against its end
+    pub(super) fn set_leaf_cells(&mut self, cells: &[(Vec<u8>, Vec<u8>)]) -> Result<(), Error> {
+        self.clear_cells();
+        for (i, (key, body)) in cells.iter().enumerate() {
+            self.insert_cell(i, key, body)?;
+        }
+
+        Ok(())
+    }
+
+    /// Add a cell at `cell_num`, which the caller made sure there is room for
+    pub(super) fn insert_leaf_cell(
+        &mut self,
+        cell_num: usize,
+        key: &[u8],
+        body: &[u8],
+    ) -> Result<(), Error> {
+        self.insert_cell(cell_num, key, body)
+    }
+
+    pub(super) fn remove_leaf_cell(&mut self, cell_num: usize) -> Result<(), Error> {
+        self.remove_cell(cell_num)
+    }
+
+    /// The cell holding `key`, or where it would go
+    pub(super) fn leaf_find(&self, key: &[u8], comparator: &Comparator) -> Result<usize, Error> {
+        self.find_cell(key, comparator)
+    }
+}
+
+/// The bytes following the key of a cell holding `local`, the part of a value kept in the page
+pub(super) fn leaf_cell_body(local: &[u8], overflow: Option<(usize, usize)>) -> Vec<u8> {
+    let mut body =
+        Vec::with_capacity(LEAF_LOCAL_SIZE_SIZE + LEAF_OVERFLOW_HEADER_SIZE + local.len());
+    match overflow {
+        None => body.extend_from_slice(&(local.len() as u16).to_le_bytes()),
+        Some((value_size, first_overflow_page)) => {
+            let local_size = local.len() | LEAF_OVERFLOW_FLAG;
+            body.extend_from_slice(&(local_size as u16).to_le_bytes());
+            body.extend_from_slice(&(value_size as u32).to_le_bytes());
+            body.extend_from_slice(&(first_overflow_page as u32).to_le_bytes());
+        }
+    }
+    body.extend_from_slice(local);
+
+    body
+}
+
+/// The largest value a cell with a key of `key_size` bytes keeps whole in the page
+pub(super) fn leaf_max_value_size(key_size: usize) -> usize {
+    MAX_CELL_SPACE - CELL_POINTER_SIZE - KEY_SIZE_SIZE - key_size - LEAF_LOCAL_SIZE_SIZE
+}
+
+/// What a cell with a key of `key_size` bytes keeps in the page of a larger value,
+/// so that the cell takes the same room
+pub(super) fn leaf_max_local_size(key_size: usize) -> usize {
+    leaf_max_value_size(key_size) - LEAF_OVERFLOW_HEADER_SIZE
+}
+
+/// The bytes a leaf cell takes, its pointer included
+pub(super) fn leaf_cell_space(key_size: usize, body_size: usize) -> usize {
+    CELL_POINTER_SIZE + KEY_SIZE_SIZE + key_size + body_size
+}
+
+pub(super) fn leaf_cells_space(cells: &[(Vec<u8>, Vec<u8>)]) -> usize {
+    cells
+        .iter()
+        .map(|(key, body)| leaf_cell_space(key.len(), body.len()))
+        .sum()
+}
+
+/// How many of `cells`, which overflow one page, go to the left page so that
+/// both halves take about the same bytes
+pub(super) fn leaf_split_point(cells: &[(Vec<u8>, Vec<u8>)]) -> usize {
+    split_point(
+        cells
+            .iter()
+            .map(|(key, body)| leaf_cell_space(key.len(), body.len())),
+    )
+}
+
+/// How many cells taking `spaces` bytes each go to the left of a split,
+/// so that both sides take about the same bytes and keep at least one cell
+fn split_point(spaces: impl Iterator<Item = usize> + Clone) -> usize {
+    let num_cells = spaces.clone().count();
+    let half = spaces.clone().sum::<usize>() / 2;
+    let mut left_space = 0;
+    let mut split_point = 0;
+    for space in spaces {
+        left_space += space;
+        if left_space > half {
+            break;
+        }
+        split_point += 1;
+    }
+
+    split_point.clamp(1, num_cells - 1)
+}
+
 // Internal page methods
 impl Page {
     pub(super) fn init_internal(&mut self) {
-        self.0 = vec![0; PAGE_SIZE];
-        self.set_type(PageType::Internal);
-        self.set_is_root(false);
-        self.set_internal_num_keys(0);
+        self.init_tree(PageType::Internal);
     }
 
-    pub(super) fn get_internal_num_keys(&self) -> usize {
-        let start = INTERNAL_NUM_KEYS_OFFSET;
-        let end = start + INTERNAL_NUM_KEYS_SIZE;
-        usize::from_ne_bytes(self.0[start..end].try_into().unwrap())
+    pub(super) fn get_internal_num_keys(&self) -> Result<usize, Error> {
+        self.get_num_cells()
     }
 
-    pub(super) fn get_internal_right_child(&self) -> usize {
-        let start = INTERNAL_RIGHT_CHILD_OFFSET;
-        let end = start + INTERNAL_RIGHT_CHILD_SIZE;
-        usize::from_ne_bytes(self.0[start..end].try_into().unwrap())
+    pub(super) fn get_internal_right_child(&self) -> Result<usize, Error> {
+        self.read_u32(RIGHT_CHILD_OFFSET)
     }
 
-    pub(super) fn get_internal_key(&self, key_num: usize) -> usize {
-        let start = INTERNAL_HEADER_SIZE + key_num * INTERNAL_CELL_SIZE + INTERNAL_CHILD_SIZE;
-        let end = start + INTERNAL_KEY_SIZE;
-        usize::from_ne_bytes(self.0[start..end].try_into().unwrap())
+    pub(super) fn get_internal_key(&self, key_num: usize) -> Result<&[u8], Error> {
+        self.get_cell_key(key_num)
     }
 
-    pub(super) fn get_internal_child(&self, child_num: usize) -> usize {
-        let num_keys = self.get_internal_num_keys();
+    pub(super) fn get_internal_child(&self, child_num: usize) -> Result<usize, Error> {
+        let num_keys = self.get_internal_num_keys()?;
         match child_num.cmp(&num_keys) {
-            Ordering::Greater => {
-                crate::error(
-                    format!("trying to access child '{child_num}' with only '{num_keys}' keys.")
-                        .as_str(),
-                );
-                std::process::exit(1);
-            }
+            Ordering::Greater => Err(Error::OutOfBounds(format!(
+                "trying to access child '{child_num}' with only '{num_keys}' keys."
+            ))),
             Ordering::Equal => self.get_internal_right_child(),
             Ordering::Less => {
-                let start = INTERNAL_HEADER_SIZE + child_num * INTERNAL_CELL_SIZE;
-                let end = start + INTERNAL_CHILD_SIZE;
-                usize::from_ne_bytes(self.0[start..end].try_into().unwrap())
+                let rest = self.get_cell_rest(child_num)?;
+                Ok(u32::from_le_bytes(rest.try_into().unwrap()) as usize)
             }
         }
     }
 
-    pub(super) fn set_internal_num_keys(&mut self, num_keys: usize) {
-        let start = INTERNAL_NUM_KEYS_OFFSET;
-        let end = start + INTERNAL_NUM_KEYS_SIZE;
-        self.0[start..end].copy_from_slice(num_keys.to_ne_bytes().as_slice());
+    /// The right child comes last, paired with `right_child_max_key`,
+    /// which the page itself does not store
+    pub(super) fn get_internal_cells(
+        &self,
+        right_child_max_key: Vec<u8>,
+    ) -> Result<Vec<(usize, Vec<u8>)>, Error> {
+        let num_keys = self.get_internal_num_keys()?;
+        let mut cells = (0..num_keys)
+            .map(|i| {
+                Ok((
+                    self.get_internal_child(i)?,
+                    self.get_internal_key(i)?.to_vec(),
+                ))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        cells.push((self.get_internal_right_child()?, right_child_max_key));
+
+        Ok(cells)
     }
 
     pub(super) fn set_internal_right_child(&mut self, right_child_page_num: usize) {
-        let start = INTERNAL_RIGHT_CHILD_OFFSET;
-        let end = start + INTERNAL_RIGHT_CHILD_SIZE;
-        self.0[start..end].copy_from_slice(right_child_page_num.to_ne_bytes().as_slice());
-    }
-
-    pub(super) fn set_internal_key(&mut self, key_num: usize, key: usize) {
-        let start = INTERNAL_HEADER_SIZE + key_num * INTERNAL_CELL_SIZE + INTERNAL_CHILD_SIZE;
-        let end = start + INTERNAL_KEY_SIZE;
-        self.0[start..end].copy_from_slice(key.to_ne_bytes().as_slice());
+        self.write_u32(RIGHT_CHILD_OFFSET, right_child_page_num);
     }
 
-    pub(super) fn set_internal_child(&mut self, child_num: usize, child: usize) {
-        let num_keys = self.get_internal_num_keys();
-        match child_num.cmp(&num_keys) {
-            Ordering::Greater => {
-                crate::error(
-                    format!("trying to access child '{child_num}' with only '{num_keys}' keys.")
-                        .as_str(),
-                );
-                std::process::exit(1);
-            }
-            Ordering::Equal => self.set_internal_right_child(child),
-            Ordering::Less => {
-                let start = INTERNAL_HEADER_SIZE + child_num * INTERNAL_CELL_SIZE;
-                let end = start + INTERNAL_CHILD_SIZE;
-                self.0[start..end].copy_from_slice(child.to_ne_bytes().as_slice());
-            }
+    /// Overwrite the cells of the page, which the caller made sure fit,
+    /// the last child becoming its right child
+    pub(super) fn set_internal_cells(&mut self, cells: &[(usize, Vec<u8>)]) -> Result<(), Error> {
+        let num_keys = cells.len() - 1;
+        self.clear_cells();
+        for (i, (child, key)) in cells[..num_keys].iter().enumerate() {
+            self.insert_cell(i, key, &(*child as u32).to_le_bytes())?;
         }
+        self.set_internal_right_child(cells[num_keys].0);
+
+        Ok(())
     }
 
-    pub(super) fn internal_find(&self, key: usize) -> usize {
-        let mut min_index = 0;
-        let mut max_index = self.get_internal_num_keys(); // there is one more child than key
-        while max_index != min_index {
-            let index = (min_index + max_index) / 2;
-            match self.get_internal_key(index).cmp(&key) {
-                Ordering::Equal | Ordering::Greater => max_index = index,
-                Ordering::Less => min_index = index + 1,
+    pub(super) fn internal_find_child(&self, child_page_num: usize) -> Result<usize, Error> {
+        let num_keys = self.get_internal_num_keys()?;
+        for i in 0..=num_keys {
+            if self.get_internal_child(i)? == child_page_num {
+                return Ok(i);
             }
         }
 
-        min_index
+        Err(Error::Corrupt(format!(
+            "page '{child_page_num}' is not a child"
+        )))
     }
 
-    pub(super) fn internal_update_key(&mut self, old_key: usize, new_key: usize) {
-        let old_child_index = self.internal_find(old_key);
-        self.set_internal_key(old_child_index, new_key);
+    /// The child whose subtree holds `key`, or would
+    pub(super) fn internal_find(
+        &self,
+        key: &[u8],
+        comparator: &Comparator,
+    ) -> Result<usize, Error> {
+        // there is one more child than key
+        self.find_cell(key, comparator)
     }
 }
+
+/// The bytes the cells of an internal page take, its right child being kept in the header
+pub(super) fn internal_cells_space(cells: &[(usize, Vec<u8>)]) -> usize {
+    cells[..cells.len() - 1]
+        .iter()
+        .map(|(_, key)| internal_cell_space(key.len()))
+        .sum()
+}
+
+fn internal_cell_space(key_size: usize) -> usize {
+    CELL_POINTER_SIZE + KEY_SIZE_SIZE + key_size + INTERNAL_CHILD_SIZE
+}
+
+/// How many of `cells`, which overflow one page, go to the left page, the key
+/// of the last of them moving up to the parent
+pub(super) fn internal_split_point(cells: &[(usize, Vec<u8>)]) -> usize {
+    split_point(cells.iter().map(|(_, key)| internal_cell_space(key.len())))
+}
+
+fn corrupted() -> Error {
+    Error::Corrupt("corrupted page".to_string())
+}
@@ -1,176 +1,540 @@
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
 
 use super::page::PageType;
-use super::{page, Page};
+use super::storage::{FileStorage, MemoryStorage, Storage, MEMORY_FILENAME};
+use super::wal::{self, Wal};
+use super::{key, page, upgrade, Page};
+use crate::Error;
 
-pub(crate) struct Pager {
-    file_descriptor: File,
+/// The page cache over a database and its log, whatever storage they are kept in
+pub(crate) struct Pager<S: Storage = Box<dyn Storage>> {
+    storage: S,
     file_length: usize,
+    wal: Wal<S>,
     pub(super) num_pages: usize,
-    pages: Vec<Option<Page>>,
+    frames: Vec<Frame>,
+    page_table: HashMap<usize, usize>, // page number -> frame index
+    clock_hand: usize,
+    capacity: usize,
+    hits: usize,
+    misses: usize,
+    savepoint: Option<Savepoint>,
 }
 
-const MAX_PAGES: usize = 100;
+/// Where the statement running in a transaction started from
+struct Savepoint {
+    num_pages: usize,
+    wal: wal::Savepoint,
+    /// The pages the statement changed or logged, as they were before it
+    pages: HashMap<usize, Page>,
+}
+
+/// A cached page
+struct Frame {
+    page_num: usize,
+    page: Page,
+    dirty: bool,
+    referenced: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CacheStats {
+    pub capacity: usize,
+    pub cached: usize,
+    pub hits: usize,
+    pub misses: usize,
+}
+
+pub(crate) const DEFAULT_CACHE_CAPACITY: usize = 1024;
+pub(super) const HEADER_PAGE_NUM: usize = 0;
+const CHECKPOINT_THRESHOLD: usize = 1000; // frames
 
 impl Pager {
-    pub(super) fn new(filename: &str) -> Self {
-        let file_descriptor = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(filename)
-            .unwrap_or_else(move |_| {
-                crate::error(format!("can't open '{filename}'").as_str());
-                std::process::exit(1);
-            });
-
-        let file_length = file_descriptor
-            .metadata()
-            .unwrap_or_else(move |_| {
-                crate::error(format!("can't fetch the metadata of '{filename}'").as_str());
-                std::process::exit(1);
-            })
-            .len() as usize;
-
-        let num_pages = file_length / page::PAGE_SIZE;
-        if file_length % page::PAGE_SIZE != 0 {
-            crate::error(format!("corrupted file '{filename}'").as_str());
-            std::process::exit(1);
-        }
-
-        let mut pages = Vec::with_capacity(MAX_PAGES);
-        for _ in 0..MAX_PAGES {
-            pages.push(None);
-        }
-
-        Self {
-            file_descriptor,
+    /// Open the database file `filename`, or a database in memory for `:memory:`
+    pub(super) fn new(filename: &str, capacity: usize) -> Result<Self, Error> {
+        if filename == MEMORY_FILENAME {
+            let storage = Box::new(MemoryStorage::new());
+            return Self::with_storage(filename, storage, Box::new(MemoryStorage::new()), capacity);
+        }
+
+        let storage = Box::new(FileStorage::open(filename)?);
+        let wal_filename = format!("{filename}-wal");
+        let created = !Path::new(&wal_filename).exists();
+        let wal = Box::new(FileStorage::open(&wal_filename)?);
+
+        Self::with_storage(filename, storage, wal, capacity).inspect_err(|_| {
+            // leave no log behind for a file that did not open
+            if created {
+                let _ = fs::remove_file(&wal_filename);
+            }
+        })
+    }
+
+    /// Open the database kept in `storage`, logging to `wal`. `name` is what errors call it.
+    pub(super) fn with_storage(
+        name: &str,
+        mut storage: Box<dyn Storage>,
+        wal: Box<dyn Storage>,
+        capacity: usize,
+    ) -> Result<Self, Error> {
+        // nobody else may replay or checkpoint the log meanwhile
+        storage.lock()?;
+        // a file that is no database must not have a log reset or checkpointed into it
+        let file_length = Self::check_file(name, &mut storage)?;
+        let wal = Wal::new(wal)?;
+
+        let capacity = capacity.max(1);
+        let mut pager = Self {
+            storage,
             file_length,
-            num_pages,
-            pages,
+            wal,
+            num_pages: 0,
+            frames: Vec::with_capacity(capacity),
+            page_table: HashMap::with_capacity(capacity),
+            clock_hand: 0,
+            capacity,
+            hits: 0,
+            misses: 0,
+            savepoint: None,
+        };
+
+        // finish the work of whoever crashed after committing
+        pager.checkpoint()?;
+
+        if pager.file_length == 0 {
+            // New database file
+            pager.get_page_mut(HEADER_PAGE_NUM)?.init_header();
+            pager.num_pages = 1;
+        } else {
+            pager.num_pages = pager.validate_header(name)?;
         }
+
+        Ok(pager)
     }
 
-    pub(super) fn get_unused_page_num(&self) -> usize {
-        // Until we start recycling free pages, new pages will always
-        // go onto the end of the database file
-        self.num_pages
+    /// Check that the file is made of pages, the first of them a header or the
+    /// root the original version wrote, returning its length
+    fn check_file(name: &str, storage: &mut Box<dyn Storage>) -> Result<usize, Error> {
+        let file_length = storage.size()?;
+        if !file_length.is_multiple_of(page::PAGE_SIZE) {
+            return Err(Error::Corrupt(format!("corrupted file '{name}'")));
+        }
+
+        if file_length > 0 {
+            let mut header = page::Page(vec![0; page::PAGE_SIZE]);
+            storage.read_page(HEADER_PAGE_NUM, &mut header.0)?;
+            if header.get_magic() != page::MAGIC && !upgrade::is_baseline(&header) {
+                return Err(Error::Unsupported(format!(
+                    "'{name}' is not a resql database"
+                )));
+            }
+        }
+
+        Ok(file_length)
     }
 
-    pub(super) fn copy_page(&self, page_num: usize) -> Option<Page> {
-        if page_num > MAX_PAGES {
-            crate::error(format!("page number '{page_num}' is out of bound").as_str());
-            std::process::exit(1);
+    /// Check that the file is a database this version can read, returning its page count
+    fn validate_header(&mut self, filename: &str) -> Result<usize, Error> {
+        let file_pages = self.file_length / page::PAGE_SIZE;
+        let header = self.get_page(HEADER_PAGE_NUM)?;
+
+        if header.get_magic() != page::MAGIC {
+            if !upgrade::is_baseline(header) {
+                return Err(Error::Unsupported(format!(
+                    "'{filename}' is not a resql database"
+                )));
+            }
+            upgrade::upgrade_baseline(self, file_pages, filename)?;
+            return Ok(self.num_pages);
+        }
+
+        let format_version = header.get_format_version();
+        if format_version != page::FORMAT_VERSION {
+            return Err(Error::Unsupported(format!(
+                "'{filename}' has unsupported format version '{format_version}'"
+            )));
         }
 
-        self.pages[page_num].clone()
+        let page_size = header.get_page_size()?;
+        if page_size != page::PAGE_SIZE {
+            return Err(Error::Unsupported(format!(
+                "'{filename}' has page size '{page_size}', expected '{}'",
+                page::PAGE_SIZE
+            )));
+        }
+
+        let num_pages = header.get_num_pages()?;
+        let schema_root = header.get_schema_root()?;
+        let first_free_page = header.get_first_free_page()?;
+        if num_pages == 0
+            || num_pages > file_pages
+            || schema_root >= num_pages
+            || first_free_page >= num_pages
+        {
+            return Err(Error::Corrupt(format!("corrupted header in '{filename}'")));
+        }
+
+        Ok(num_pages)
+    }
+}
+
+impl<S: Storage> Pager<S> {
+    /// Take a page from the free list, or append one to the end of the file if it is empty
+    pub(super) fn get_unused_page_num(&mut self) -> Result<usize, Error> {
+        let page_num = self.get_page(HEADER_PAGE_NUM)?.get_first_free_page()?;
+        if page_num == 0 {
+            self.num_pages += 1;
+            let num_pages = self.num_pages;
+            self.get_page_mut(HEADER_PAGE_NUM)?.set_num_pages(num_pages);
+            return Ok(num_pages - 1);
+        }
+
+        let next_page_num = self.get_page(page_num)?.get_next_free_page()?;
+        let header = self.get_page_mut(HEADER_PAGE_NUM)?;
+        header.set_first_free_page(next_page_num);
+        header.set_num_free_pages(header.get_num_free_pages()? - 1);
+
+        Ok(page_num)
     }
 
-    pub(super) fn get_page(&mut self, page_num: usize) -> &mut Page {
-        if page_num > MAX_PAGES {
-            crate::error(format!("page number '{page_num}' is out of bound").as_str());
-            std::process::exit(1);
+    /// Hand a page no longer referenced by the tree over to the free list
+    pub(super) fn free_page(&mut self, page_num: usize) -> Result<(), Error> {
+        let header = self.get_page_mut(HEADER_PAGE_NUM)?;
+        let next_page_num = header.get_first_free_page()?;
+        header.set_first_free_page(page_num);
+        header.set_num_free_pages(header.get_num_free_pages()? + 1);
+
+        self.get_page_mut(page_num)?.init_free(next_page_num);
+
+        Ok(())
+    }
+
+    pub(crate) fn get_num_free_pages(&mut self) -> Result<usize, Error> {
+        self.get_page(HEADER_PAGE_NUM)?.get_num_free_pages()
+    }
+
+    pub(super) fn get_schema_root(&mut self) -> Result<usize, Error> {
+        self.get_page(HEADER_PAGE_NUM)?.get_schema_root()
+    }
+
+    pub(super) fn set_schema_root(&mut self, page_num: usize) -> Result<(), Error> {
+        self.get_page_mut(HEADER_PAGE_NUM)?
+            .set_schema_root(page_num);
+
+        Ok(())
+    }
+
+    /// Read-only access to a page, loading it into the cache on a miss
+    pub(super) fn get_page(&mut self, page_num: usize) -> Result<&Page, Error> {
+        let frame_index = self.fetch(page_num)?;
+
+        Ok(&self.frames[frame_index].page)
+    }
+
+    /// Like `get_page`, but the page will be written back before it leaves the cache
+    pub(super) fn get_page_mut(&mut self, page_num: usize) -> Result<&mut Page, Error> {
+        let frame_index = self.fetch(page_num)?;
+        let frame = &mut self.frames[frame_index];
+        if let Some(savepoint) = &mut self.savepoint {
+            savepoint
+                .pages
+                .entry(page_num)
+                .or_insert_with(|| frame.page.clone());
+        }
+        frame.dirty = true;
+
+        Ok(&mut frame.page)
+    }
+
+    fn fetch(&mut self, page_num: usize) -> Result<usize, Error> {
+        if let Some(&frame_index) = self.page_table.get(&page_num) {
+            self.hits += 1;
+            self.frames[frame_index].referenced = true;
+            return Ok(frame_index);
+        }
+
+        // the header is read before anything tells how many pages there are
+        if page_num != HEADER_PAGE_NUM && page_num >= self.num_pages {
+            return Err(Error::OutOfBounds(format!(
+                "page '{page_num}' is out of bounds, there are '{}' pages",
+                self.num_pages
+            )));
         }
 
-        if self.pages[page_num].is_none() {
-            // cache miss
-            self.pages[page_num] = Some(Page {
-                0: vec![0; page::PAGE_SIZE],
-            }); // initialize empty page
-            let mut num_pages = self.file_length / page::PAGE_SIZE;
+        // cache miss
+        self.misses += 1;
+        let frame = Frame {
+            page_num,
+            page: self.read(page_num)?,
+            dirty: false,
+            referenced: true,
+        };
 
-            if self.file_length % page::PAGE_SIZE != 0 {
-                // partial page
-                num_pages += 1;
+        let frame_index = if self.frames.len() < self.capacity {
+            self.frames.push(frame);
+            self.frames.len() - 1
+        } else {
+            let frame_index = self.evict()?;
+            self.frames[frame_index] = frame;
+            frame_index
+        };
+        self.page_table.insert(page_num, frame_index);
+
+        Ok(frame_index)
+    }
+
+    /// Pick a victim with the clock algorithm, logging it if it is dirty
+    fn evict(&mut self) -> Result<usize, Error> {
+        loop {
+            let frame_index = self.clock_hand;
+            self.clock_hand = (self.clock_hand + 1) % self.frames.len();
+
+            let frame = &mut self.frames[frame_index];
+            if frame.referenced {
+                // second chance
+                frame.referenced = false;
+                continue;
             }
 
-            if page_num <= num_pages {
-                self.file_descriptor
-                    .seek(SeekFrom::Start((page_num * page::PAGE_SIZE) as u64))
-                    .unwrap_or_else(|e| {
-                        crate::error(format!("failed to seek file. {e}").as_str());
-                        std::process::exit(1);
-                    });
-
-                let mut buf = [0u8; page::PAGE_SIZE];
-                let read_amount = self.file_descriptor.read(&mut buf).unwrap_or_else(|e| {
-                    crate::error(format!("failed to read file. {e}").as_str());
-                    std::process::exit(1);
-                });
-                if read_amount > 0 && read_amount < page::PAGE_SIZE {
-                    crate::error("partial database file.")
-                }
+            if frame.dirty {
+                self.write(frame_index, None)?;
+            }
+            let page_num = self.frames[frame_index].page_num;
+            self.page_table.remove(&page_num);
+
+            return Ok(frame_index);
+        }
+    }
 
-                self.pages[page_num] = Some(Page { 0: Vec::from(buf) });
+    fn read(&mut self, page_num: usize) -> Result<Page, Error> {
+        if let Some(frame_num) = self.wal.find(page_num) {
+            return self.wal.read(frame_num);
+        }
 
-                if page_num >= self.num_pages {
-                    self.num_pages = page_num + 1;
-                }
+        let mut page = page::Page(vec![0; page::PAGE_SIZE]);
+        if page_num >= self.file_length / page::PAGE_SIZE {
+            // not written yet
+            return Ok(page);
+        }
+
+        self.storage.read_page(page_num, &mut page.0)?;
+
+        Ok(page)
+    }
+
+    /// Append a page to the log, committing the transaction if `db_size` is given
+    fn write(&mut self, frame_index: usize, db_size: Option<usize>) -> Result<(), Error> {
+        let frame = &mut self.frames[frame_index];
+        if let Some(savepoint) = &mut self.savepoint {
+            // a change from before the statement, which rolling back the log would lose
+            (savepoint.pages)
+                .entry(frame.page_num)
+                .or_insert_with(|| frame.page.clone());
+        }
+        self.wal.append(frame.page_num, &frame.page, db_size)?;
+        frame.dirty = false;
+
+        Ok(())
+    }
+
+    /// Make every change since the last commit durable, as one transaction
+    pub(super) fn commit(&mut self) -> Result<(), Error> {
+        self.savepoint = None;
+        let mut dirty: Vec<(usize, usize)> = self
+            .frames
+            .iter()
+            .enumerate()
+            .filter(|(_, frame)| frame.dirty)
+            .map(|(frame_index, frame)| (frame.page_num, frame_index))
+            .collect();
+        dirty.sort_unstable();
+
+        if dirty.is_empty() {
+            if !self.wal.has_pending() {
+                return Ok(());
             }
+            // every change was evicted already, the header carries the commit
+            dirty.push((HEADER_PAGE_NUM, self.fetch(HEADER_PAGE_NUM)?));
         }
 
-        let page = self.pages[page_num].as_mut().unwrap();
+        let (_, last_frame_index) = dirty.pop().unwrap();
+        for (_, frame_index) in dirty {
+            self.write(frame_index, None)?;
+        }
+        self.write(last_frame_index, Some(self.num_pages))?;
+
+        if self.wal.get_num_frames() >= CHECKPOINT_THRESHOLD {
+            self.checkpoint()?;
+        }
+
+        Ok(())
+    }
+
+    /// Drop every change since the last commit, from the cache and from the log
+    pub(super) fn rollback(&mut self) -> Result<(), Error> {
+        self.savepoint = None;
+        // a page read back from the log, or logged by a commit that failed, is clean but uncommitted
+        let wal = &self.wal;
+        self.frames
+            .retain(|frame| !frame.dirty && !wal.is_pending(frame.page_num));
+        self.page_table = self
+            .frames
+            .iter()
+            .enumerate()
+            .map(|(frame_index, frame)| (frame.page_num, frame_index))
+            .collect();
+        self.clock_hand = 0;
+
+        self.wal.rollback()?;
+        self.num_pages = self.get_page(HEADER_PAGE_NUM)?.get_num_pages()?;
 
-        page
+        Ok(())
     }
 
-    pub(super) fn flush(&mut self, page_num: usize) {
-        if self.pages[page_num].is_none() {
-            return;
+    /// Start the next statement of a transaction, which `rollback_to_savepoint` undoes alone
+    pub(super) fn savepoint(&mut self) {
+        self.savepoint = Some(Savepoint {
+            num_pages: self.num_pages,
+            wal: self.wal.savepoint(),
+            pages: HashMap::new(),
+        });
+    }
+
+    /// Drop every change since `savepoint`, keeping those of the transaction before it
+    pub(super) fn rollback_to_savepoint(&mut self) -> Result<(), Error> {
+        let Some(savepoint) = self.savepoint.take() else {
+            return self.rollback();
+        };
+        self.wal.rollback_to(savepoint.wal)?;
+
+        // the pages the statement took are gone again
+        self.num_pages = savepoint.num_pages;
+        let num_pages = self.num_pages;
+        self.frames.retain(|frame| frame.page_num < num_pages);
+        self.page_table = self
+            .frames
+            .iter()
+            .enumerate()
+            .map(|(frame_index, frame)| (frame.page_num, frame_index))
+            .collect();
+        self.clock_hand = 0;
+
+        for (page_num, page) in savepoint.pages {
+            if page_num >= num_pages {
+                continue;
+            }
+            let frame_index = self.fetch(page_num)?;
+            let frame = &mut self.frames[frame_index];
+            frame.page = page;
+            frame.dirty = true;
+        }
+        self.savepoint();
+
+        Ok(())
+    }
+
+    /// Copy the committed pages of the log into the database file and empty the log
+    pub(super) fn checkpoint(&mut self) -> Result<(), Error> {
+        if self.wal.has_pending() {
+            // the log still holds part of a transaction
+            return Ok(());
         }
 
-        self.file_descriptor
-            .seek(SeekFrom::Start((page_num * page::PAGE_SIZE) as u64))
-            .unwrap_or_else(|e| {
-                crate::error(format!("failed to seek file. {e}").as_str());
-                std::process::exit(1);
-            });
+        for (page_num, frame_num) in self.wal.committed_frames() {
+            let page = self.wal.read(frame_num)?;
 
-        self.file_descriptor
-            .write_all(&self.pages[page_num].as_ref().unwrap().0[0..page::PAGE_SIZE])
-            .unwrap_or_else(|e| {
-                crate::error(format!("failed to write to file. {e}").as_str());
-                std::process::exit(1);
-            });
+            self.storage
+                .write_page(page_num, &page.0[0..page::PAGE_SIZE])?;
+
+            self.file_length = self.file_length.max((page_num + 1) * page::PAGE_SIZE);
+        }
 
-        self.pages[page_num] = None;
+        let db_length = self.wal.get_db_size() * page::PAGE_SIZE;
+        if db_length > self.file_length {
+            self.storage.truncate(db_length)?;
+            self.file_length = db_length;
+        }
+
+        if self.wal.get_num_frames() > 0 {
+            self.storage.sync()?;
+            self.wal.reset()?;
+        }
+
+        Ok(())
+    }
+
+    /// Commit and checkpoint, leaving everything in the database file
+    pub(super) fn close(&mut self) -> Result<(), Error> {
+        self.commit()?;
+        self.checkpoint()?;
+        self.wal.remove();
+
+        Ok(())
+    }
+
+    pub(crate) fn get_cache_stats(&self) -> CacheStats {
+        CacheStats {
+            capacity: self.capacity,
+            cached: self.frames.len(),
+            hits: self.hits,
+            misses: self.misses,
+        }
     }
 
-    pub(crate) fn print(&mut self, page_num: usize, indentation_level: usize) {
+    /// Resize the cache, evicting pages until it fits
+    pub(crate) fn set_cache_capacity(&mut self, capacity: usize) -> Result<(), Error> {
+        let capacity = capacity.max(1);
+        while self.frames.len() > capacity {
+            let frame_index = self.evict()?;
+            self.frames.swap_remove(frame_index);
+            if let Some(frame) = self.frames.get(frame_index) {
+                self.page_table.insert(frame.page_num, frame_index);
+            }
+            self.clock_hand %= self.frames.len();
+        }
+        self.capacity = capacity;
+
+        Ok(())
+    }
+
+    pub(crate) fn print(&mut self, page_num: usize, indentation_level: usize) -> Result<(), Error> {
         fn indent(level: usize) {
             for _ in 0..level {
                 print!("  ");
             }
         }
 
-        let page = self.get_page(page_num).clone();
+        let page = self.get_page(page_num)?.clone();
 
-        match page.get_type() {
+        match page.get_type()? {
             PageType::Leaf => {
-                let num_cells = page.get_leaf_num_cells();
+                let num_cells = page.get_leaf_num_cells()?;
                 indent(indentation_level);
                 println!("- leaf (size {num_cells})");
                 for i in 0..num_cells {
                     indent(indentation_level + 1);
-                    println!("- key {}", page.get_leaf_key(i));
+                    println!("- key {}", key::to_string(page.get_leaf_key(i)?));
                 }
             }
             PageType::Internal => {
-                let num_keys = page.get_internal_num_keys();
+                let num_keys = page.get_internal_num_keys()?;
                 indent(indentation_level);
                 println!("- internal (size {num_keys})");
                 for i in 0..num_keys {
-                    let child = page.get_internal_child(i);
+                    let child = page.get_internal_child(i)?;
 
-                    self.print(child, indentation_level + 1);
+                    self.print(child, indentation_level + 1)?;
 
                     indent(indentation_level + 1);
-                    println!("- key {}", page.get_internal_key(i));
+                    println!("- key {}", key::to_string(page.get_internal_key(i)?));
                 }
-                let right_child = page.get_internal_right_child();
-                self.print(right_child, indentation_level + 1);
+                let right_child = page.get_internal_right_child()?;
+                self.print(right_child, indentation_level + 1)?;
             }
         }
+
+        Ok(())
     }
 }
@@ -1,60 +1,308 @@
-use std::cmp;
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::mem;
 
-#[derive(Clone, Default)]
-pub struct Row {
-    pub(crate) id: u32,
-    pub(crate) username: String,
-    pub(crate) email: String,
+use super::schema::Schema;
+
+/// The values of a row, in the order of the columns of its table or of a query
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Row(pub(crate) Vec<Value>);
+
+#[derive(Clone, Debug)]
+pub enum Value {
+    Null,
+    Boolean(bool),
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
 }
 
-pub(crate) const MAX_USERNAME: usize = 31;
-pub(crate) const MAX_EMAIL: usize = 255;
-const ID_SIZE: usize = mem::size_of::<u32>();
-const USERNAME_SIZE: usize = MAX_USERNAME + 1;
-const EMAIL_SIZE: usize = MAX_EMAIL + 1;
-const USERNAME_OFFSET: usize = ID_SIZE;
-const EMAIL_OFFSET: usize = USERNAME_OFFSET + USERNAME_SIZE;
-pub(super) const ROW_SIZE: usize = ID_SIZE + USERNAME_SIZE + EMAIL_SIZE;
+// A value is stored behind a tag giving its type, the same way in records as in keys
+pub(super) const INTEGER_TAG: u8 = 0;
+pub(super) const TEXT_TAG: u8 = 1;
+pub(super) const BLOB_TAG: u8 = 2;
+pub(super) const NULL_TAG: u8 = 3;
+pub(super) const REAL_TAG: u8 = 4;
+pub(super) const BOOLEAN_TAG: u8 = 5;
+
+const NUMBER_SIZE: usize = mem::size_of::<i64>();
+const LENGTH_SIZE: usize = mem::size_of::<u32>();
 
 impl Row {
+    /// The value of the column at `index`, counting from 0
+    pub fn get(&self, index: usize) -> Option<&Value> {
+        self.0.get(index)
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.0
+    }
+
+    pub fn into_values(self) -> Vec<Value> {
+        self.0
+    }
+
+    /// Lay the values out one after another, each behind its type tag
     pub(super) fn serialize(&self) -> Vec<u8> {
-        let mut buf = vec![0; ROW_SIZE];
-        buf[0..ID_SIZE].clone_from_slice(&self.id.to_ne_bytes());
-        Self::write_string(&mut buf, USERNAME_OFFSET, &self.username, USERNAME_SIZE);
-        Self::write_string(&mut buf, EMAIL_OFFSET, &self.email, EMAIL_SIZE);
+        let mut buf = Vec::new();
+        for value in &self.0 {
+            value.encode(&mut buf);
+        }
 
         buf
     }
 
-    pub(super) fn deserialize(buf: &[u8]) -> Self {
-        let mut bytes = vec![0; ROW_SIZE];
-        bytes.clone_from_slice(&buf[0..ROW_SIZE]);
+    /// Read back the values of a row of `schema`, or `None` if `buf` holds something else
+    pub(super) fn deserialize(buf: &[u8], schema: &Schema) -> Option<Self> {
+        let mut pos = 0;
+        let mut values = Vec::with_capacity(schema.columns.len());
+        while pos < buf.len() {
+            let (value, size) = Value::decode(&buf[pos..])?;
+            values.push(value);
+            pos += size;
+        }
 
-        let id = u32::from_ne_bytes(bytes[0..ID_SIZE].try_into().unwrap());
-        let username = Self::read_string(&bytes, USERNAME_OFFSET, USERNAME_SIZE);
-        let email = Self::read_string(&bytes, EMAIL_OFFSET, EMAIL_SIZE);
+        (values.len() == schema.columns.len()).then_some(Self(values))
+    }
+}
 
-        Self {
-            id,
-            username,
-            email,
+impl Value {
+    /// Append the value to `buf` behind its type tag: integers and reals as their
+    /// 8 little-endian bytes, booleans as a byte, text and byte strings as their
+    /// little-endian u32 length followed by their bytes, NULL as the tag alone
+    pub(super) fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Value::Null => buf.push(NULL_TAG),
+            Value::Boolean(b) => buf.extend_from_slice(&[BOOLEAN_TAG, *b as u8]),
+            Value::Integer(n) => {
+                buf.push(INTEGER_TAG);
+                buf.extend_from_slice(&n.to_le_bytes());
+            }
+            Value::Real(n) => {
+                buf.push(REAL_TAG);
+                buf.extend_from_slice(&n.to_le_bytes());
+            }
+            Value::Text(s) => {
+                buf.push(TEXT_TAG);
+                buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+                buf.extend_from_slice(s.as_bytes());
+            }
+            Value::Blob(bytes) => {
+                buf.push(BLOB_TAG);
+                buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+                buf.extend_from_slice(bytes);
+            }
         }
     }
 
-    fn write_string(buf: &mut [u8], pos: usize, s: &str, length: usize) {
-        let bytes = s.as_bytes();
-        let len = bytes.len();
-        buf[pos..pos + len].copy_from_slice(bytes);
-        buf[pos + len..pos + length].copy_from_slice(&vec![0; length - len]);
+    /// The bytes `encode` appends
+    pub(super) fn encoded_size(&self) -> usize {
+        1 + match self {
+            Value::Null => 0,
+            Value::Boolean(_) => 1,
+            Value::Integer(_) | Value::Real(_) => NUMBER_SIZE,
+            Value::Text(s) => LENGTH_SIZE + s.len(),
+            Value::Blob(bytes) => LENGTH_SIZE + bytes.len(),
+        }
     }
 
-    fn read_string(buf: &Vec<u8>, pos: usize, length: usize) -> String {
-        // buf.len() MUST be greater than pos
-        let len = cmp::min(length, buf.len() - pos);
-        let mut bytes = vec![0; len];
-        bytes.clone_from_slice(&buf[pos..pos + len]);
+    /// Read back the value `buf` starts with, along with the bytes it took
+    pub(super) fn decode(buf: &[u8]) -> Option<(Self, usize)> {
+        let (&tag, rest) = buf.split_first()?;
+        let (start, len) = match payload_size(tag)? {
+            Some(size) => (0, size),
+            None => {
+                let len = rest.get(..LENGTH_SIZE)?;
+                (
+                    LENGTH_SIZE,
+                    u32::from_le_bytes(len.try_into().unwrap()) as usize,
+                )
+            }
+        };
+        let payload = rest.get(start..start + len)?;
+
+        Some((Self::from_payload(tag, payload)?, 1 + start + len))
+    }
+
+    /// The value of type `tag` whose bytes, past any length, are `payload`
+    pub(super) fn from_payload(tag: u8, payload: &[u8]) -> Option<Self> {
+        let value = match tag {
+            NULL_TAG => Value::Null,
+            BOOLEAN_TAG => Value::Boolean(*payload.first()? != 0),
+            INTEGER_TAG => Value::Integer(i64::from_le_bytes(payload.try_into().ok()?)),
+            REAL_TAG => Value::Real(f64::from_le_bytes(payload.try_into().ok()?)),
+            TEXT_TAG => Value::Text(String::from_utf8(payload.to_vec()).ok()?),
+            BLOB_TAG => Value::Blob(payload.to_vec()),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    pub(crate) fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /// Where values of this type sort among those of other types
+    fn rank(&self) -> u8 {
+        match self {
+            Value::Null => 0,
+            Value::Boolean(_) => 1,
+            Value::Integer(_) | Value::Real(_) => 2,
+            Value::Text(_) => 3,
+            Value::Blob(_) => 4,
+        }
+    }
+}
+
+/// How many bytes of a value of type `tag` follow it, `None` inside when they
+/// follow their length instead, or `None` for a tag of no type
+pub(super) fn payload_size(tag: u8) -> Option<Option<usize>> {
+    match tag {
+        NULL_TAG => Some(Some(0)),
+        BOOLEAN_TAG => Some(Some(1)),
+        INTEGER_TAG | REAL_TAG => Some(Some(NUMBER_SIZE)),
+        TEXT_TAG | BLOB_TAG => Some(None),
+        _ => None,
+    }
+}
+
+/// Order an integer and a real by the numbers they stand for, a NaN after any integer
+pub(super) fn compare_numbers(a: i64, b: f64) -> Ordering {
+    match (a as f64).partial_cmp(&b) {
+        // the integer may have lost precision on the way
+        Some(Ordering::Equal) => (a as i128).cmp(&(b as i128)),
+        Some(ordering) => ordering,
+        None => Ordering::Less,
+    }
+}
+
+/// Order two reals, NaN after every other number
+pub(super) fn compare_reals(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b)
+        .unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()))
+}
+
+/// The order rows sort and keys compare in: NULL, then booleans, then numbers,
+/// integers and reals by value, then text, then byte strings
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Boolean(a), Value::Boolean(b)) => a.cmp(b),
+            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+            (Value::Integer(a), Value::Real(b)) => compare_numbers(*a, *b),
+            (Value::Real(a), Value::Integer(b)) => compare_numbers(*b, *a).reverse(),
+            (Value::Real(a), Value::Real(b)) => compare_reals(*a, *b),
+            (Value::Text(a), Value::Text(b)) => a.cmp(b),
+            (Value::Blob(a), Value::Blob(b)) => a.cmp(b),
+            (a, b) => a.rank().cmp(&b.rank()),
+        }
+    }
+}
 
-        String::from_utf8(bytes).unwrap()
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Values are equal when they sort the same, so 1 and 1.0 fall in the same group
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Value {}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rank().hash(state);
+        match self {
+            Value::Null => {}
+            Value::Boolean(b) => b.hash(state),
+            Value::Integer(n) => n.hash(state),
+            // a real equal to an integer hashes like it
+            Value::Real(n)
+                if n.fract() == 0.0 && (i64::MIN as f64..i64::MAX as f64).contains(n) =>
+            {
+                (*n as i64).hash(state)
+            }
+            Value::Real(n) if n.is_nan() => f64::NAN.to_bits().hash(state),
+            Value::Real(n) => n.to_bits().hash(state),
+            Value::Text(s) => s.hash(state),
+            Value::Blob(bytes) => bytes.hash(state),
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Integer(n)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Real(n)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Boolean(b)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Text(s.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Text(s)
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(bytes: &[u8]) -> Self {
+        Value::Blob(bytes.into())
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(bytes: Vec<u8>) -> Self {
+        Value::Blob(bytes)
+    }
+}
+
+/// `None` is NULL
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "NULL"),
+            Value::Boolean(b) => write!(f, "{b}"),
+            Value::Integer(n) => write!(f, "{n}"),
+            Value::Real(n) => write!(f, "{n:?}"),
+            Value::Text(s) => write!(f, "{s}"),
+            Value::Blob(bytes) => {
+                write!(f, "X'")?;
+                for byte in bytes {
+                    write!(f, "{byte:02X}")?;
+                }
+                write!(f, "'")
+            }
+        }
     }
 }
@@ -0,0 +1,264 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use super::key::Comparator;
+
+/// The definition of a table, as recorded in the catalog
+#[derive(Clone, Debug)]
+pub struct Schema {
+    pub(crate) name: String,
+    pub(crate) columns: Vec<Column>,
+    /// The columns whose values key the rows, in key order. Without any,
+    /// rows are keyed by a hidden row id.
+    pub(crate) primary_key: Vec<usize>,
+    pub(crate) root_page_num: usize,
+    pub(crate) indexes: Vec<Index>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Column {
+    pub(crate) name: String,
+    pub(crate) column_type: ColumnType,
+    pub(crate) collation: Collation,
+    pub(crate) not_null: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColumnType {
+    Integer,
+    Real,
+    Text { max_length: Option<usize> },
+    Blob,
+    Boolean,
+}
+
+/// How the text of a column compares, in keys as in conditions
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Collation {
+    /// Byte by byte
+    #[default]
+    Binary,
+    /// Byte by byte, ASCII letters folded to lower case
+    NoCase,
+    /// Byte by byte, trailing spaces left out
+    RTrim,
+}
+
+/// An index on columns of a table, a B-tree keyed by the values of the columns
+/// followed by the key of the row
+#[derive(Clone, Debug)]
+pub struct Index {
+    pub(crate) name: String,
+    pub(crate) table: String,
+    pub(crate) columns: Vec<String>,
+    pub(crate) unique: bool,
+    pub(crate) root_page_num: usize,
+}
+
+/// The catalog lists every other table, and itself is keyed by row id
+pub(crate) const CATALOG_NAME: &str = "resql_schema";
+
+impl Schema {
+    pub(super) fn catalog(root_page_num: usize) -> Self {
+        let column = |name: &str, column_type| Column {
+            name: name.into(),
+            column_type,
+            collation: Collation::Binary,
+            not_null: true,
+        };
+
+        Self {
+            name: CATALOG_NAME.into(),
+            columns: vec![
+                column("type", ColumnType::Text { max_length: None }),
+                column("name", ColumnType::Text { max_length: None }),
+                column("root_page", ColumnType::Integer),
+                column("sql", ColumnType::Text { max_length: None }),
+            ],
+            primary_key: Vec::new(),
+            root_page_num,
+            indexes: Vec::new(),
+        }
+    }
+
+    /// Whether rows are keyed by a single integer, which is then their row id
+    pub(crate) fn has_row_id(&self) -> bool {
+        match self.primary_key[..] {
+            [] => true,
+            [column] => self.columns[column].column_type == ColumnType::Integer,
+            _ => false,
+        }
+    }
+
+    /// The order of the keys of the rows
+    pub(super) fn comparator(&self) -> Comparator {
+        Comparator::new(self.collations(&self.primary_key))
+    }
+
+    /// The order of the entries of `index`, its columns followed by the key of the row
+    pub(super) fn index_comparator(&self, index: &Index) -> Comparator {
+        let mut columns = self.index_columns(index);
+        columns.extend(&self.primary_key);
+
+        Comparator::new(self.collations(&columns))
+    }
+
+    /// The positions of the columns `index` is on
+    pub(crate) fn index_columns(&self, index: &Index) -> Vec<usize> {
+        (index.columns.iter())
+            .map(|name| self.column_index(name).unwrap())
+            .collect()
+    }
+
+    fn collations(&self, columns: &[usize]) -> Vec<Collation> {
+        (columns.iter())
+            .map(|&column| self.columns[column].collation)
+            .collect()
+    }
+
+    pub(crate) fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns
+            .iter()
+            .position(|column| column.name.eq_ignore_ascii_case(name))
+    }
+
+    /// The statement creating the table, which the catalog keeps. A key of
+    /// a single column is declared along with it.
+    pub(crate) fn sql(&self) -> String {
+        let mut columns: Vec<String> = self.columns.iter().map(Column::to_string).collect();
+        match self.primary_key[..] {
+            [] => {}
+            [column] => columns[column] = self.columns[column].definition(true),
+            _ => {
+                let names: Vec<&str> = (self.primary_key.iter())
+                    .map(|&column| self.columns[column].name.as_str())
+                    .collect();
+                columns.push(format!("PRIMARY KEY ({})", names.join(", ")));
+            }
+        }
+
+        format!("CREATE TABLE {} ({})", self.name, columns.join(", "))
+    }
+}
+
+impl Index {
+    /// The statement creating the index, which the catalog keeps
+    pub(crate) fn sql(&self) -> String {
+        let unique = if self.unique { "UNIQUE " } else { "" };
+
+        format!(
+            "CREATE {unique}INDEX {} ON {} ({})",
+            self.name,
+            self.table,
+            self.columns.join(", ")
+        )
+    }
+}
+
+impl Column {
+    fn definition(&self, primary_key: bool) -> String {
+        let mut definition = format!("{} {}", self.name, self.column_type);
+        if primary_key {
+            definition.push_str(" PRIMARY KEY");
+        }
+        if self.collation != Collation::Binary {
+            definition.push_str(&format!(" COLLATE {}", self.collation));
+        }
+        if self.not_null {
+            definition.push_str(" NOT NULL");
+        }
+
+        definition
+    }
+}
+
+impl ColumnType {
+    /// The type a name like 'INT' or 'VARCHAR(32)' stands for
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_uppercase();
+        let (base, size) = match name.split_once('(') {
+            Some((base, size)) => (base, Some(size.strip_suffix(')')?.parse().ok()?)),
+            None => (name.as_str(), None),
+        };
+
+        match (base, size) {
+            ("INTEGER" | "INT", None) => Some(ColumnType::Integer),
+            ("REAL" | "FLOAT" | "DOUBLE", None) => Some(ColumnType::Real),
+            ("TEXT", None) => Some(ColumnType::Text { max_length: None }),
+            ("VARCHAR" | "CHAR", Some(n)) => Some(ColumnType::Text {
+                max_length: Some(n),
+            }),
+            ("BLOB", None) => Some(ColumnType::Blob),
+            ("BOOLEAN" | "BOOL", None) => Some(ColumnType::Boolean),
+            _ => None,
+        }
+    }
+}
+
+impl Collation {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "BINARY" => Some(Collation::Binary),
+            "NOCASE" => Some(Collation::NoCase),
+            "RTRIM" => Some(Collation::RTrim),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn compare(self, a: &[u8], b: &[u8]) -> Ordering {
+        match self {
+            Collation::Binary => a.cmp(b),
+            Collation::NoCase => {
+                (a.iter().map(u8::to_ascii_lowercase)).cmp(b.iter().map(u8::to_ascii_lowercase))
+            }
+            Collation::RTrim => trim_end_spaces(a).cmp(trim_end_spaces(b)),
+        }
+    }
+
+    /// The one text standing for every text comparing equal to `s`
+    pub(crate) fn normalize(self, s: &str) -> String {
+        match self {
+            Collation::Binary => s.to_string(),
+            Collation::NoCase => s.to_ascii_lowercase(),
+            Collation::RTrim => s.trim_end_matches(' ').to_string(),
+        }
+    }
+}
+
+fn trim_end_spaces(s: &[u8]) -> &[u8] {
+    let len = s.iter().rposition(|&c| c != b' ').map_or(0, |i| i + 1);
+
+    &s[..len]
+}
+
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.definition(false))
+    }
+}
+
+impl fmt::Display for Collation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Collation::Binary => "BINARY",
+            Collation::NoCase => "NOCASE",
+            Collation::RTrim => "RTRIM",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColumnType::Integer => write!(f, "INTEGER"),
+            ColumnType::Real => write!(f, "REAL"),
+            ColumnType::Text { max_length: None } => write!(f, "TEXT"),
+            ColumnType::Text {
+                max_length: Some(n),
+            } => write!(f, "VARCHAR({n})"),
+            ColumnType::Blob => write!(f, "BLOB"),
+            ColumnType::Boolean => write!(f, "BOOLEAN"),
+        }
+    }
+}
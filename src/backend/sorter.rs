@@ -0,0 +1,190 @@
+use std::cmp::Ordering;
+use std::mem;
+
+use super::row::{self, Value};
+use super::storage::{FileStorage, Storage};
+use super::{page, Pager};
+use crate::Error;
+
+/// Sorts records of values, spilling sorted runs to a temporary file once
+/// they outgrow the page cache, and merging the runs back
+pub(crate) struct Sorter<F> {
+    compare: F,
+    memory: usize, // bytes
+    records: Vec<Vec<Value>>,
+    size: usize,
+    /// Where the runs go, created along with the first of them
+    file: Option<FileStorage>,
+    runs: Vec<Run>,
+}
+
+/// A sorted run spilled to the temporary file, read back a page at a time
+struct Run {
+    offset: usize,
+    end: usize,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<F: Fn(&[Value], &[Value]) -> Ordering> Sorter<F> {
+    /// A sorter holding as many bytes of records as the page cache of `pager` holds pages
+    pub(crate) fn new(pager: &Pager, compare: F) -> Self {
+        Self {
+            compare,
+            memory: pager.get_cache_stats().capacity * page::PAGE_SIZE,
+            records: Vec::new(),
+            size: 0,
+            file: None,
+            runs: Vec::new(),
+        }
+    }
+
+    /// How many bytes of records are held before they spill
+    pub(crate) fn memory(&self) -> usize {
+        self.memory
+    }
+
+    pub(crate) fn push(&mut self, record: Vec<Value>) -> Result<(), Error> {
+        self.size += record_size(&record);
+        self.records.push(record);
+        if self.size > self.memory {
+            self.spill()?;
+        }
+
+        Ok(())
+    }
+
+    /// Hand the records over in order until `visit` returns false
+    pub(crate) fn finish(
+        mut self,
+        mut visit: impl FnMut(Vec<Value>) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
+        self.records.sort_by(|a, b| (self.compare)(a, b));
+        self.merge(&mut visit)
+    }
+
+    fn spill(&mut self) -> Result<(), Error> {
+        self.records.sort_by(|a, b| (self.compare)(a, b));
+        let mut data = Vec::with_capacity(self.size);
+        for record in mem::take(&mut self.records) {
+            encode(&record, &mut data);
+        }
+        self.size = 0;
+
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => self.file.insert(FileStorage::temporary()?),
+        };
+        let offset = self.runs.last().map_or(0, |run| run.end);
+        file.write_at(offset, &data)?;
+        self.runs.push(Run {
+            offset,
+            end: offset + data.len(),
+            buf: Vec::new(),
+            pos: 0,
+        });
+
+        Ok(())
+    }
+
+    /// Merge the runs with the records left in memory, which come after them on ties
+    fn merge(
+        &mut self,
+        visit: &mut impl FnMut(Vec<Value>) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
+        let mut memory = mem::take(&mut self.records).into_iter();
+        let mut heads = Vec::with_capacity(self.runs.len() + 1);
+        if let Some(file) = &mut self.file {
+            for run in &mut self.runs {
+                heads.push(run.next(file)?);
+            }
+        }
+        heads.push(memory.next());
+
+        loop {
+            let mut min: Option<usize> = None;
+            for (i, head) in heads.iter().enumerate() {
+                let Some(record) = head else {
+                    continue;
+                };
+                if min
+                    .is_none_or(|min| (self.compare)(record, heads[min].as_ref().unwrap()).is_lt())
+                {
+                    min = Some(i);
+                }
+            }
+            let Some(min) = min else {
+                return Ok(());
+            };
+
+            let next = match (self.runs.get_mut(min), &mut self.file) {
+                (Some(run), Some(file)) => run.next(file)?,
+                _ => memory.next(),
+            };
+            let record = mem::replace(&mut heads[min], next).unwrap();
+            if !visit(record)? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<F> Drop for Sorter<F> {
+    fn drop(&mut self) {
+        if let Some(file) = &mut self.file {
+            file.remove();
+        }
+    }
+}
+
+impl Run {
+    fn next(&mut self, file: &mut FileStorage) -> Result<Option<Vec<Value>>, Error> {
+        if self.pos == self.buf.len() && self.offset == self.end {
+            return Ok(None);
+        }
+
+        let num_values = u32::from_le_bytes(self.read(file, 4)?.try_into().unwrap());
+        let mut record = Vec::with_capacity(num_values as usize);
+        for _ in 0..num_values {
+            let tag = self.read(file, 1)?[0];
+            let len = match row::payload_size(tag).unwrap() {
+                Some(size) => size,
+                None => u32::from_le_bytes(self.read(file, 4)?.try_into().unwrap()) as usize,
+            };
+            record.push(Value::from_payload(tag, self.read(file, len)?).unwrap());
+        }
+
+        Ok(Some(record))
+    }
+
+    /// The next `n` bytes of the run, reading in as many pages as they span
+    fn read(&mut self, file: &mut FileStorage, n: usize) -> Result<&[u8], Error> {
+        if self.buf.len() - self.pos < n {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+            while self.buf.len() < n {
+                let chunk_size = (self.end - self.offset).min(page::PAGE_SIZE);
+                let start = self.buf.len();
+                self.buf.resize(start + chunk_size, 0);
+                file.read_at(self.offset, &mut self.buf[start..])?;
+                self.offset += chunk_size;
+            }
+        }
+
+        self.pos += n;
+        Ok(&self.buf[self.pos - n..self.pos])
+    }
+}
+
+/// Append a record to `buf` as its number of values, then each value behind a type tag
+fn encode(record: &[Value], buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&(record.len() as u32).to_le_bytes());
+    for value in record {
+        value.encode(buf);
+    }
+}
+
+/// The bytes a record takes once spilled
+pub(crate) fn record_size(record: &[Value]) -> usize {
+    4 + record.iter().map(Value::encoded_size).sum::<usize>()
+}
@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::{env, process};
+
+use super::page::PAGE_SIZE;
+use crate::Error;
+
+/// The name that opens a database kept in memory, along with its log
+pub(super) const MEMORY_FILENAME: &str = ":memory:";
+
+/// Where the bytes of a database or of its log live
+pub trait Storage: Send {
+    /// How many bytes there are
+    fn size(&mut self) -> Result<usize, Error>;
+
+    /// Fill `buf` with the bytes starting at `offset`, which must all be there
+    fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Error>;
+
+    /// Write `buf` at `offset`, growing the storage if it ends past its end
+    fn write_at(&mut self, offset: usize, buf: &[u8]) -> Result<(), Error>;
+
+    /// Make what was written durable
+    fn sync(&mut self) -> Result<(), Error>;
+
+    /// Cut off or extend with zeroes up to `size` bytes
+    fn truncate(&mut self, size: usize) -> Result<(), Error>;
+
+    /// Keep any other connection from opening the storage while this one has it
+    fn lock(&mut self) -> Result<(), Error>;
+
+    /// Leave nothing behind once emptied, as a log is when its database closes
+    fn remove(&mut self) {}
+
+    fn read_page(&mut self, page_num: usize, page: &mut [u8]) -> Result<(), Error> {
+        self.read_at(page_num * PAGE_SIZE, page)
+    }
+
+    fn write_page(&mut self, page_num: usize, page: &[u8]) -> Result<(), Error> {
+        self.write_at(page_num * PAGE_SIZE, page)
+    }
+}
+
+impl<S: Storage + ?Sized> Storage for Box<S> {
+    fn size(&mut self) -> Result<usize, Error> {
+        (**self).size()
+    }
+
+    fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        (**self).read_at(offset, buf)
+    }
+
+    fn write_at(&mut self, offset: usize, buf: &[u8]) -> Result<(), Error> {
+        (**self).write_at(offset, buf)
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        (**self).sync()
+    }
+
+    fn truncate(&mut self, size: usize) -> Result<(), Error> {
+        (**self).truncate(size)
+    }
+
+    fn lock(&mut self) -> Result<(), Error> {
+        (**self).lock()
+    }
+
+    fn remove(&mut self) {
+        (**self).remove()
+    }
+}
+
+/// A file on disk
+pub struct FileStorage {
+    file: File,
+    filename: String,
+}
+
+impl FileStorage {
+    /// Open `filename`, creating it if it doesn't exist
+    pub fn open(filename: &str) -> Result<Self, Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(filename)
+            .map_err(|e| Error::io(format!("can't open '{filename}'"), e))?;
+
+        Ok(Self {
+            file,
+            filename: filename.into(),
+        })
+    }
+
+    /// A new file in the temporary directory, for whatever outgrows memory
+    pub(super) fn temporary() -> Result<Self, Error> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, atomic::Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("resql-{}-{n}", process::id()));
+        let mut storage = Self::open(&path.to_string_lossy())?;
+        storage.truncate(0)?;
+
+        Ok(storage)
+    }
+}
+
+impl Storage for FileStorage {
+    fn size(&mut self) -> Result<usize, Error> {
+        let filename = &self.filename;
+        self.file
+            .metadata()
+            .map(|metadata| metadata.len() as usize)
+            .map_err(|e| Error::io(format!("can't fetch the metadata of '{filename}'"), e))
+    }
+
+    fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        let filename = &self.filename;
+        self.file
+            .seek(SeekFrom::Start(offset as u64))
+            .map_err(|e| Error::io(format!("failed to seek '{filename}'. {e}"), e))?;
+        self.file
+            .read_exact(buf)
+            .map_err(|e| Error::io(format!("failed to read '{filename}'. {e}"), e))
+    }
+
+    fn write_at(&mut self, offset: usize, buf: &[u8]) -> Result<(), Error> {
+        let filename = &self.filename;
+        self.file
+            .seek(SeekFrom::Start(offset as u64))
+            .map_err(|e| Error::io(format!("failed to seek '{filename}'. {e}"), e))?;
+        self.file
+            .write_all(buf)
+            .map_err(|e| Error::io(format!("failed to write to '{filename}'. {e}"), e))
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        let filename = &self.filename;
+        self.file
+            .sync_data()
+            .map_err(|e| Error::io(format!("failed to sync '{filename}'. {e}"), e))
+    }
+
+    fn truncate(&mut self, size: usize) -> Result<(), Error> {
+        let filename = &self.filename;
+        self.file
+            .set_len(size as u64)
+            .map_err(|e| Error::io(format!("failed to resize '{filename}'. {e}"), e))
+    }
+
+    /// The lock goes with the file, so a process that dies never keeps it
+    fn lock(&mut self) -> Result<(), Error> {
+        let filename = &self.filename;
+        self.file.try_lock().map_err(|e| match e {
+            TryLockError::WouldBlock => Error::io(
+                format!("'{filename}' is locked by another connection"),
+                io::ErrorKind::WouldBlock.into(),
+            ),
+            TryLockError::Error(e) => Error::io(format!("failed to lock '{filename}'. {e}"), e),
+        })
+    }
+
+    fn remove(&mut self) {
+        let _ = fs::remove_file(&self.filename);
+    }
+}
+
+/// A buffer growing as it is written. Clones share it, so a database can be
+/// opened again on what one dropped without closing left behind.
+#[derive(Clone, Default)]
+pub struct MemoryStorage(Arc<Mutex<Vec<u8>>>);
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn bytes(&self) -> MutexGuard<'_, Vec<u8>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn size(&mut self) -> Result<usize, Error> {
+        Ok(self.bytes().len())
+    }
+
+    fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        let bytes = self.bytes();
+        let Some(bytes) = bytes.get(offset..offset + buf.len()) else {
+            return Err(Error::OutOfBounds(format!(
+                "failed to read {} bytes at '{offset}' of memory holding {}",
+                buf.len(),
+                bytes.len()
+            )));
+        };
+        buf.copy_from_slice(bytes);
+
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: usize, buf: &[u8]) -> Result<(), Error> {
+        let mut bytes = self.bytes();
+        if bytes.len() < offset + buf.len() {
+            bytes.resize(offset + buf.len(), 0);
+        }
+        bytes[offset..offset + buf.len()].copy_from_slice(buf);
+
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn truncate(&mut self, size: usize) -> Result<(), Error> {
+        self.bytes().resize(size, 0);
+
+        Ok(())
+    }
+
+    fn lock(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// How a write picked to go wrong does
+#[derive(Clone, Copy, Debug)]
+pub enum Fault {
+    /// The write fails, leaving the storage as it was
+    Fail,
+    /// Only the first bytes of the write make it, and every call after it
+    /// fails, as if the machine lost power halfway through
+    Tear(usize),
+}
+
+/// A storage whose writes go wrong on the calls picked for it, to test how
+/// the database copes with I/O errors and crashes. Clones share the picks, so
+/// they can be made after the storage was handed to a database.
+#[derive(Clone)]
+pub struct FaultyStorage<S> {
+    inner: S,
+    faults: Arc<Mutex<Faults>>,
+}
+
+#[derive(Default)]
+struct Faults {
+    num_writes: usize,
+    picked: HashMap<usize, Fault>,
+    crashed: bool,
+}
+
+impl<S: Storage> FaultyStorage<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            faults: Arc::default(),
+        }
+    }
+
+    /// How many writes were made so far, those that went wrong included
+    pub fn num_writes(&self) -> usize {
+        self.faults().num_writes
+    }
+
+    /// Make write number `n`, counting from 0, go wrong
+    pub fn fail_write(&self, n: usize, fault: Fault) {
+        self.faults().picked.insert(n, fault);
+    }
+
+    fn faults(&self) -> MutexGuard<'_, Faults> {
+        self.faults.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn check_crashed(&self) -> Result<(), Error> {
+        if self.faults().crashed {
+            return Err(injected("the storage crashed".into()));
+        }
+
+        Ok(())
+    }
+}
+
+impl<S: Storage> Storage for FaultyStorage<S> {
+    fn size(&mut self) -> Result<usize, Error> {
+        self.check_crashed()?;
+        self.inner.size()
+    }
+
+    fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        self.check_crashed()?;
+        self.inner.read_at(offset, buf)
+    }
+
+    fn write_at(&mut self, offset: usize, buf: &[u8]) -> Result<(), Error> {
+        self.check_crashed()?;
+        let (n, fault) = {
+            let mut faults = self.faults();
+            let n = faults.num_writes;
+            faults.num_writes += 1;
+            (n, faults.picked.remove(&n))
+        };
+
+        match fault {
+            None => self.inner.write_at(offset, buf),
+            Some(Fault::Fail) => Err(injected(format!("write {n} failed"))),
+            Some(Fault::Tear(len)) => {
+                self.inner.write_at(offset, &buf[..len.min(buf.len())])?;
+                self.faults().crashed = true;
+                Err(injected(format!("write {n} was torn")))
+            }
+        }
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        self.check_crashed()?;
+        self.inner.sync()
+    }
+
+    fn truncate(&mut self, size: usize) -> Result<(), Error> {
+        self.check_crashed()?;
+        self.inner.truncate(size)
+    }
+
+    fn lock(&mut self) -> Result<(), Error> {
+        self.check_crashed()?;
+        self.inner.lock()
+    }
+
+    fn remove(&mut self) {
+        self.inner.remove()
+    }
+}
+
+fn injected(message: String) -> Error {
+    Error::io(
+        format!("injected fault: {message}"),
+        io::Error::other("injected fault"),
+    )
+}
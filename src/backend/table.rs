@@ -1,147 +1,425 @@
-use super::page::PageType;
-use super::{page, row, Cursor, Pager, Row};
+use std::ops::Bound;
 
-pub struct Table {
+use super::btree::BTree;
+use super::row::Value;
+use super::schema::Index;
+use super::{key, page, Cursor, Pager, Row, Schema};
+use crate::Error;
+
+/// The keys between a start and an end bound. A bound made of the first values
+/// of a key takes in or leaves out every key starting with them.
+pub(crate) type KeyRange = (Bound<Vec<Value>>, Bound<Vec<Value>>);
+
+/// One B-tree of rows, reached through its `Database`, along with the B-trees of its indexes
+pub struct Table<'a> {
     pub(crate) root_page_num: usize,
-    pub(crate) pager: Pager,
+    pub(crate) pager: &'a mut Pager,
+    pub(crate) schema: &'a Schema,
 }
 
-impl Table {
-    pub fn open(filename: &str) -> Self {
-        let mut pager = Pager::new(filename);
-        if pager.num_pages == 0 {
-            // New database file
-            let page = pager.get_page(0);
-            page.init_leaf();
-            page.set_is_root(true);
-            pager.num_pages = 1;
-        }
-
+impl<'a> Table<'a> {
+    pub(super) fn new(pager: &'a mut Pager, schema: &'a Schema) -> Self {
         Self {
-            root_page_num: 0,
+            root_page_num: schema.root_page_num,
             pager,
+            schema,
         }
     }
 
-    pub(crate) fn close(&mut self) {
-        let num_pages = self.pager.num_pages;
-        for page_num in 0..num_pages {
-            self.pager.flush(page_num);
+    fn tree(&mut self) -> BTree<'_> {
+        BTree::new(self.pager, self.root_page_num, self.schema.comparator())
+    }
+
+    fn index_tree(&mut self, index: &Index) -> BTree<'_> {
+        let comparator = self.schema.index_comparator(index);
+        BTree::new(self.pager, index.root_page_num, comparator)
+    }
+
+    pub(crate) fn insert(&mut self, row: &Row) -> Result<(), Error> {
+        let key_values = if self.schema.primary_key.is_empty() {
+            match self.get_max_row_id()? {
+                Some(i64::MAX) => {
+                    return Err(Error::Constraint(format!(
+                        "table '{}' is full",
+                        self.schema.name
+                    )));
+                }
+                Some(row_id) => vec![Value::Integer(row_id + 1)],
+                None => vec![Value::Integer(1)],
+            }
+        } else {
+            (self.schema.primary_key.iter())
+                .map(|&column| row.0[column].clone())
+                .collect()
+        };
+        let key = key::encode(&key_values);
+        if key.len() > page::MAX_KEY_SIZE {
+            return Err(Error::Constraint(format!(
+                "primary key of table '{}' too long",
+                self.schema.name
+            )));
+        }
+        if self.tree().position(&key)?.is_some() {
+            return Err(Error::Constraint(format!(
+                "duplicate key '{}'",
+                key::to_string(&key)
+            )));
         }
+
+        // check the entries of every index before changing any tree
+        let mut entries = Vec::with_capacity(self.schema.indexes.len());
+        for index in &self.schema.indexes {
+            let entry = self.check_entry(index, self.index_values(index, row), &key_values)?;
+            entries.push((index, entry));
+        }
+
+        self.tree().insert(&key, &row.serialize())?;
+        for (index, entry) in entries {
+            self.index_tree(index).insert(&entry, &[])?;
+        }
+
+        Ok(())
     }
 
-    pub(crate) fn insert(&mut self, row: &Row) {
-        let key_to_insert = row.id as usize;
+    /// Overwrite the given columns of the row keyed by `key`, returning how many rows changed
+    pub(crate) fn update(
+        &mut self,
+        key: &[Value],
+        assignments: &[(usize, Value)],
+    ) -> Result<usize, Error> {
+        let Some((page_num, cell_num)) = self.tree().position(&key::encode(key))? else {
+            return Ok(0);
+        };
 
-        let (page_num, cell_num) = self.find(key_to_insert, self.root_page_num);
+        let value = self.tree().load(page_num, cell_num)?;
+        let old_row = self.decode(&value)?;
+        let mut row = old_row.clone();
+        for (column, value) in assignments {
+            row.0[*column] = value.clone();
+        }
 
-        let page = self.pager.get_page(page_num);
-        let num_cells = page.get_leaf_num_cells();
-        if cell_num < num_cells {
-            let key_at_index = page.get_leaf_key(cell_num);
-            if key_at_index == key_to_insert {
-                crate::error(format!("duplicate key '{key_to_insert}'").as_str());
-                return;
+        // the entries of the indexes on changed columns move
+        let mut moves = Vec::new();
+        for index in &self.schema.indexes {
+            let old_values = self.index_values(index, &old_row);
+            let values = self.index_values(index, &row);
+            if values == old_values {
+                continue;
             }
+            let entry = self.check_entry(index, values, key)?;
+            moves.push((index, index_key(old_values, key), entry));
         }
 
-        let mut cursor = Cursor::from_pos(self, page_num, cell_num);
-        cursor.leaf_insert(key_to_insert, row);
+        self.tree().replace(page_num, cell_num, &row.serialize())?;
+        for (index, old_entry, entry) in moves {
+            let mut tree = self.index_tree(index);
+            if let Some((page_num, cell_num)) = tree.position(&old_entry)? {
+                tree.remove(page_num, cell_num)?;
+            }
+            tree.insert(&entry, &[])?;
+        }
+
+        Ok(1)
     }
 
-    pub(crate) fn select(&mut self) {
-        let mut cursor = Cursor::from_start(self);
+    /// Remove the row keyed by `key` along with its index entries, returning how many rows were removed
+    pub(crate) fn delete(&mut self, key: &[Value]) -> Result<usize, Error> {
+        let Some((page_num, cell_num)) = self.tree().position(&key::encode(key))? else {
+            return Ok(0);
+        };
 
-        while !cursor.end_of_table {
-            let row = cursor.get_row();
-            println!(
-                "{}: {} {}",
-                row.id,
-                row.username.trim_matches('\0'),
-                row.email.trim_matches('\0')
-            );
-            cursor.advance();
+        if !self.schema.indexes.is_empty() {
+            let value = self.tree().load(page_num, cell_num)?;
+            let row = self.decode(&value)?;
+            for index in &self.schema.indexes {
+                let entry = index_key(self.index_values(index, &row), key);
+                let mut tree = self.index_tree(index);
+                if let Some((page_num, cell_num)) = tree.position(&entry)? {
+                    tree.remove(page_num, cell_num)?;
+                }
+            }
         }
+        self.tree().remove(page_num, cell_num)?;
+
+        Ok(1)
     }
 
-    pub(super) fn new_root(&mut self, right_child_page_num: usize) {
-        let root_copy = self.pager.copy_page(self.root_page_num).unwrap();
-        let left_child_page_num = self.pager.get_unused_page_num();
-        let left_child = self.pager.get_page(left_child_page_num);
-        left_child.clone_from(&root_copy);
-        left_child.set_is_root(false);
-        left_child.set_parent(self.root_page_num);
-        let left_child_max_key = left_child.get_max_key();
-        self.pager.num_pages += 1;
+    /// Hand `visit` the key and row of every row whose key falls in `range`,
+    /// in key order, until it returns false
+    pub(crate) fn scan(
+        &mut self,
+        range: KeyRange,
+        visit: impl FnMut(&mut Pager, &[Value], Row) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
+        Table::new(self.pager, self.schema)
+            .into_scan(range)?
+            .visit(visit)
+    }
 
-        let right_child = self.pager.get_page(right_child_page_num);
-        right_child.set_parent(self.root_page_num);
+    /// Hand `visit` the key and row of every row whose values of the columns of
+    /// `index` fall in `range`, in the order of the index, until it returns false
+    pub(crate) fn scan_index(
+        &mut self,
+        index: &Index,
+        range: KeyRange,
+        visit: impl FnMut(&mut Pager, &[Value], Row) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
+        Table::new(self.pager, self.schema)
+            .into_index_scan(index, range)?
+            .visit(visit)
+    }
 
-        let root = self.pager.get_page(self.root_page_num);
-        root.init_internal();
-        root.set_is_root(true);
-        root.set_internal_num_keys(1);
-        root.set_internal_child(0, left_child_page_num);
-        root.set_internal_key(0, left_child_max_key);
-        root.set_internal_right_child(right_child_page_num);
+    /// The rows whose key falls in `range`, read as the scan moves on
+    pub(crate) fn into_scan(self, range: KeyRange) -> Result<Scan<'a>, Error> {
+        let (start, end) = encode_range(range);
+        let tree = BTree::new(self.pager, self.root_page_num, self.schema.comparator());
+        let cursor = Cursor::new(tree, start, end)?;
+
+        Ok(Scan::new(cursor, self.schema, self.root_page_num, None))
     }
 
-    pub(crate) fn print_constants() {
-        println!("Constants:\n");
-        println!("ROW_SIZE: {}", row::ROW_SIZE);
-        println!("COMMON_HEADER_SIZE: {}", page::COMMON_HEADER_SIZE);
-        println!("LEAF_HEADER_SIZE: {}", page::LEAF_HEADER_SIZE);
-        println!("LEAF_CELL_SIZE: {}", page::LEAF_CELL_SIZE);
-        println!("LEAF_MAX_CELLS: {}", page::LEAF_MAX_CELLS);
+    /// The rows whose values of the columns of `index` fall in `range`, read as the scan moves on
+    pub(crate) fn into_index_scan(self, index: &Index, range: KeyRange) -> Result<Scan<'a>, Error> {
+        // the values alone are a prefix of the entries holding them
+        let (start, end) = encode_range(range);
+        let comparator = self.schema.index_comparator(index);
+        let tree = BTree::new(self.pager, index.root_page_num, comparator);
+        let cursor = Cursor::new(tree, start, end)?;
+
+        Ok(Scan::new(
+            cursor,
+            self.schema,
+            self.root_page_num,
+            Some(index),
+        ))
     }
 
-    pub(super) fn find(&mut self, key: usize, start_page_num: usize) -> (usize, usize) {
-        let start_page = self.pager.get_page(start_page_num);
+    /// Make sure every row can go into `index`, before it is filled
+    pub(crate) fn check_index(&mut self, index: &Index) -> Result<(), Error> {
+        let schema = self.schema;
+        let mut prefixes = Vec::new();
+        self.scan((Bound::Unbounded, Bound::Unbounded), |_, key, row| {
+            let values = index_values(schema, index, &row);
+            check_key_size(index, &index_key(values.clone(), key))?;
+            // NULL is never equal to anything, so it can repeat
+            if !values.iter().any(Value::is_null) {
+                prefixes.push(key::encode(&values));
+            }
+            Ok(true)
+        })?;
 
-        match start_page.get_type() {
-            PageType::Leaf => (start_page_num, start_page.leaf_find(key)),
-            PageType::Internal => {
-                let child_num = start_page.internal_find(key);
-                let child_page_num = start_page.get_internal_child(child_num);
-                self.find(key, child_page_num)
+        if index.unique {
+            // equal values end up side by side
+            let comparator = schema.index_comparator(index);
+            prefixes.sort_by(|a, b| comparator.compare(a, b));
+            if let Some(pair) =
+                (prefixes.windows(2)).find(|pair| comparator.compare(&pair[0], &pair[1]).is_eq())
+            {
+                return Err(duplicate_value(index, &key::decode(&pair[1])));
             }
         }
+
+        Ok(())
     }
 
-    pub(super) fn internal_insert(&mut self, parent_page_num: usize, child_page_num: usize) {
-        let child = self.pager.get_page(child_page_num);
-        let child_max_key = child.get_max_key();
+    /// Give the empty tree of a new index an entry for every row
+    pub(crate) fn fill_index(&mut self, index: &Index) -> Result<(), Error> {
+        let schema = self.schema;
+        self.scan((Bound::Unbounded, Bound::Unbounded), |pager, key, row| {
+            let entry = index_key(index_values(schema, index, &row), key);
+            let comparator = schema.index_comparator(index);
+            BTree::new(pager, index.root_page_num, comparator).insert(&entry, &[])?;
+            Ok(true)
+        })
+    }
+
+    /// The number of rows, added up from the leaves without reading any row
+    pub(crate) fn count(&mut self) -> Result<usize, Error> {
+        self.tree().count()
+    }
+
+    /// Every row of the table, in key order
+    pub(crate) fn rows(&mut self) -> Result<Vec<Row>, Error> {
+        let mut rows = Vec::new();
+        self.scan((Bound::Unbounded, Bound::Unbounded), |_, _, row| {
+            rows.push(row);
+            Ok(true)
+        })?;
+
+        Ok(rows)
+    }
 
-        let parent = self.pager.get_page(parent_page_num);
-        let index = parent.internal_find(child_max_key);
+    fn decode(&self, value: &[u8]) -> Result<Row, Error> {
+        decode(self.schema, value)
+    }
 
-        let original_num_keys = parent.get_internal_num_keys();
-        parent.set_internal_num_keys(original_num_keys + 1);
+    fn index_values(&self, index: &Index, row: &Row) -> Vec<Value> {
+        index_values(self.schema, index, row)
+    }
 
-        if original_num_keys > page::INTERNAL_MAX_CELLS {
-            unimplemented!("need to implement splitting internal node");
+    /// The entry of `values` for the row keyed by `key`, if `index` can take it.
+    /// The row itself may hold values equal to them, as when an update changes
+    /// their case, and values with NULL among them are never equal to others.
+    fn check_entry(
+        &mut self,
+        index: &Index,
+        values: Vec<Value>,
+        key: &[Value],
+    ) -> Result<Vec<u8>, Error> {
+        let prefix = key::encode(&values);
+        let has_null = values.iter().any(Value::is_null);
+        let entry = index_key(values, key);
+        check_key_size(index, &entry)?;
+        if index.unique && !has_null {
+            let bounds = (Bound::Included(prefix.clone()), Bound::Included(prefix));
+            let mut cursor = Cursor::new(self.index_tree(index), bounds.0, bounds.1)?;
+            while !cursor.end_of_table {
+                let other = key::decode(cursor.get_key()?);
+                let (other_values, other_key) = other.split_at(index.columns.len());
+                if other_key != key {
+                    return Err(duplicate_value(index, other_values));
+                }
+                cursor.advance()?;
+            }
         }
 
-        let right_child_page_num = parent.get_internal_right_child();
-        let right_child = self.pager.get_page(right_child_page_num);
-        let right_child_max_key = right_child.get_max_key();
+        Ok(entry)
+    }
 
-        let parent = self.pager.get_page(parent_page_num); // the same as 'parent' above
-        if child_max_key > right_child_max_key {
-            // Replace right child
-            parent.set_internal_child(original_num_keys, right_child_page_num);
-            parent.set_internal_key(original_num_keys, right_child_max_key);
-            parent.set_internal_right_child(child_page_num);
-        } else {
-            // Make room for the new cell
-            for i in (index + 1..=original_num_keys).rev() {
-                let key = parent.get_internal_key(i);
-                let child = parent.get_internal_child(i);
-                parent.set_internal_key(i - 1, key);
-                parent.set_internal_child(i - 1, child);
+    /// The largest row id in use, which is the last one handed out
+    fn get_max_row_id(&mut self) -> Result<Option<i64>, Error> {
+        Ok(self.tree().last_key()?.map(|key| key::decode_integer(&key)))
+    }
+
+    pub(crate) fn print_constants() {
+        println!("Constants:\n");
+        println!("COMMON_HEADER_SIZE: {}", page::COMMON_HEADER_SIZE);
+        println!("TREE_HEADER_SIZE: {}", page::TREE_HEADER_SIZE);
+        println!("CELL_SPACE: {}", page::CELL_SPACE);
+        println!("MAX_CELL_SPACE: {}", page::MAX_CELL_SPACE);
+        println!("MAX_KEY_SIZE: {}", page::MAX_KEY_SIZE);
+        println!("OVERFLOW_DATA_SIZE: {}", page::OVERFLOW_DATA_SIZE);
+    }
+}
+
+/// A cursor over the rows of a table, or over the entries of one of its
+/// indexes, handing out the key and row of each in turn
+pub(crate) struct Scan<'a> {
+    cursor: Cursor<'a>,
+    schema: &'a Schema,
+    root_page_num: usize,
+    /// The name of the index the cursor goes through, and how many columns it has
+    index: Option<(String, usize)>,
+}
+
+impl<'a> Scan<'a> {
+    fn new(
+        cursor: Cursor<'a>,
+        schema: &'a Schema,
+        root_page_num: usize,
+        index: Option<&Index>,
+    ) -> Self {
+        Self {
+            cursor,
+            schema,
+            root_page_num,
+            index: index.map(|index| (index.name.clone(), index.columns.len())),
+        }
+    }
+
+    /// The key and row the cursor is on, moving it to the next one
+    pub(crate) fn next(&mut self) -> Result<Option<(Vec<Value>, Row)>, Error> {
+        if self.cursor.end_of_table {
+            return Ok(None);
+        }
+
+        let entry = key::decode(self.cursor.get_key()?);
+        let (key, value) = match &self.index {
+            None => (entry, self.cursor.get_value()?),
+            Some((name, num_columns)) => {
+                // an entry of an index ends with the key of its row
+                let key = entry[*num_columns..].to_vec();
+                let mut tree = BTree::new(
+                    self.cursor.pager(),
+                    self.root_page_num,
+                    self.schema.comparator(),
+                );
+                let Some((page_num, cell_num)) = tree.position(&key::encode(&key))? else {
+                    return Err(Error::Corrupt(format!("corrupted index '{name}'")));
+                };
+                let value = tree.load(page_num, cell_num)?;
+                (key, value)
+            }
+        };
+        let row = decode(self.schema, &value)?;
+        self.cursor.advance()?;
+
+        Ok(Some((key, row)))
+    }
+
+    /// The pager of the table, for work done alongside the scan that leaves the table alone
+    pub(crate) fn pager(&mut self) -> &mut Pager {
+        self.cursor.pager()
+    }
+
+    fn visit(
+        mut self,
+        mut visit: impl FnMut(&mut Pager, &[Value], Row) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
+        while let Some((key, row)) = self.next()? {
+            if !visit(self.pager(), &key, row)? {
+                break;
             }
-            parent.set_internal_child(index, child_page_num);
-            parent.set_internal_key(index, child_max_key);
         }
+
+        Ok(())
+    }
+}
+
+fn decode(schema: &Schema, value: &[u8]) -> Result<Row, Error> {
+    Row::deserialize(value, schema)
+        .ok_or_else(|| Error::Corrupt(format!("corrupted row in table '{}'", schema.name)))
+}
+
+fn encode_range((start, end): KeyRange) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    (
+        start.map(|values| key::encode(&values)),
+        end.map(|values| key::encode(&values)),
+    )
+}
+
+/// The values of the columns of `index` in `row`
+fn index_values(schema: &Schema, index: &Index, row: &Row) -> Vec<Value> {
+    (schema.index_columns(index).into_iter())
+        .map(|column| row.0[column].clone())
+        .collect()
+}
+
+/// The key of the entry of an index for the row keyed by `key`, whose indexed columns hold `values`
+fn index_key(mut values: Vec<Value>, key: &[Value]) -> Vec<u8> {
+    values.extend_from_slice(key);
+
+    key::encode(&values)
+}
+
+fn check_key_size(index: &Index, entry: &[u8]) -> Result<(), Error> {
+    if entry.len() > page::MAX_KEY_SIZE {
+        return Err(Error::Constraint(format!(
+            "value of '{}' too long for index '{}'",
+            index.columns.join(", "),
+            index.name
+        )));
     }
+
+    Ok(())
+}
+
+fn duplicate_value(index: &Index, values: &[Value]) -> Error {
+    let values: Vec<String> = values.iter().map(Value::to_string).collect();
+
+    Error::Constraint(format!(
+        "duplicate value '{}' in unique index '{}'",
+        values.join(", "),
+        index.name
+    ))
 }
@@ -0,0 +1,278 @@
+use std::mem;
+
+use super::database::DEFAULT_TABLE_SQL;
+use super::page::PageType;
+use super::pager::HEADER_PAGE_NUM;
+use super::row::{self, Value};
+use super::{key, page, Page, Pager, Schema, Table};
+use crate::Error;
+
+// The original version stored every integer as a native-endian usize
+const USIZE_SIZE: usize = mem::size_of::<usize>();
+const PARENT_OFFSET: usize = 2;
+const NUM_CELLS_OFFSET: usize = PARENT_OFFSET + USIZE_SIZE;
+const NEXT_OR_RIGHT_CHILD_OFFSET: usize = NUM_CELLS_OFFSET + USIZE_SIZE;
+const HEADER_SIZE: usize = NEXT_OR_RIGHT_CHILD_OFFSET + USIZE_SIZE;
+const INTERNAL_CELL_SIZE: usize = 2 * USIZE_SIZE;
+const INTERNAL_MAX_KEYS: usize = (page::PAGE_SIZE - HEADER_SIZE) / INTERNAL_CELL_SIZE;
+
+// It only held the tutorial's users table, with fixed-width rows
+const ID_SIZE: usize = mem::size_of::<u32>();
+const USERNAME_OFFSET: usize = ID_SIZE;
+const USERNAME_SIZE: usize = 32;
+const EMAIL_OFFSET: usize = USERNAME_OFFSET + USERNAME_SIZE;
+const EMAIL_SIZE: usize = 256;
+const FIXED_ROW_SIZE: usize = ID_SIZE + USERNAME_SIZE + EMAIL_SIZE;
+const LEAF_CELL_SIZE: usize = USIZE_SIZE + FIXED_ROW_SIZE;
+const LEAF_MAX_CELLS: usize = (page::PAGE_SIZE - HEADER_SIZE) / LEAF_CELL_SIZE;
+
+/// The key and cell body of every row of a leaf
+type Cells = Vec<(usize, Vec<u8>)>;
+
+fn read_usize(page: &Page, offset: usize) -> usize {
+    usize::from_ne_bytes(page.0[offset..offset + USIZE_SIZE].try_into().unwrap())
+}
+
+/// Whether `page`, the first of a file without the magic, looks like the root
+/// of the users tree the original version wrote there, before there was a header
+pub(super) fn is_baseline(page: &Page) -> bool {
+    let parent = &page.0[PARENT_OFFSET..PARENT_OFFSET + USIZE_SIZE];
+    page.0[0] <= 1 && page.0[1] == 1 && parent.iter().all(|&b| b == 0)
+}
+
+/// Rewrite a file of the original version, produced on a host like this one,
+/// into the current format: its users tree moves to the end of the file so
+/// the header can take the first page. The converted pages reach the disk
+/// with the next commit.
+pub(super) fn upgrade_baseline(
+    pager: &mut Pager,
+    file_pages: usize,
+    filename: &str,
+) -> Result<(), Error> {
+    let old_root = pager.get_page(HEADER_PAGE_NUM)?.clone();
+    let root_page_num = file_pages;
+    pager.num_pages = file_pages + 1;
+    pager.get_page_mut(root_page_num)?.clone_from(&old_root);
+
+    let header = pager.get_page_mut(HEADER_PAGE_NUM)?;
+    header.init_header();
+    header.set_num_pages(file_pages + 1);
+    header.set_schema_root(0);
+
+    upgrade_users(pager, root_page_num, filename)
+}
+
+/// Convert the users tree rooted at `root_page_num` and list it in a new catalog
+fn upgrade_users(pager: &mut Pager, root_page_num: usize, filename: &str) -> Result<(), Error> {
+    upgrade_tree(pager, root_page_num, filename)?;
+
+    let catalog_root = pager.get_unused_page_num()?;
+    let page = pager.get_page_mut(catalog_root)?;
+    page.init_leaf();
+    page.set_is_root(true);
+    pager.set_schema_root(catalog_root)?;
+
+    let entry = row::Row(vec![
+        Value::Text("table".into()),
+        Value::Text(super::DEFAULT_TABLE.into()),
+        Value::Integer(root_page_num as i64),
+        Value::Text(DEFAULT_TABLE_SQL.into()),
+    ]);
+    Table::new(pager, &Schema::catalog(catalog_root)).insert(&entry)
+}
+
+/// Rebuild the tree rooted at `root_page_num` in the current format. Its leaves
+/// are converted where they are, taking more pages if their cells no longer fit;
+/// the internal levels are then built again from the bottom, reusing the old
+/// internal pages.
+fn upgrade_tree(pager: &mut Pager, root_page_num: usize, filename: &str) -> Result<(), Error> {
+    let mut leaves = Vec::new();
+    let mut internals = Vec::new();
+    walk_tree(pager, root_page_num, &mut leaves, &mut internals, filename)?;
+
+    // every leaf, with the largest key it holds
+    let mut level: Vec<(usize, Vec<u8>)> = Vec::new();
+    for page_num in leaves {
+        let old_page = pager.get_page(page_num)?.clone();
+        let cells: Vec<(Vec<u8>, Vec<u8>)> = fixed_row_cells(&old_page)
+            .ok_or_else(|| corrupted(filename))?
+            .into_iter()
+            .map(|(key, body)| (key::encode_integer(key), body))
+            .collect();
+        if cells.is_empty() && page_num != root_page_num {
+            pager.free_page(page_num)?;
+            continue;
+        }
+
+        let mut rest = &cells[..];
+        let mut page_num = page_num;
+        loop {
+            let mut num_cells = 0;
+            let mut space = 0;
+            while let Some((key, body)) = rest.get(num_cells) {
+                space += page::leaf_cell_space(key.len(), body.len());
+                if space > page::CELL_SPACE {
+                    break;
+                }
+                num_cells += 1;
+            }
+            let (page_cells, tail) = rest.split_at(num_cells);
+
+            let page = pager.get_page_mut(page_num)?;
+            page.init_leaf();
+            page.set_leaf_cells(page_cells)?;
+            let max_key = page_cells
+                .last()
+                .map_or_else(Vec::new, |(key, _)| key.clone());
+            level.push((page_num, max_key));
+
+            rest = tail;
+            if rest.is_empty() {
+                break;
+            }
+            page_num = pager.get_unused_page_num()?;
+        }
+    }
+
+    if level.is_empty() {
+        // the leaves were all empty
+        pager.get_page_mut(root_page_num)?.init_leaf();
+        level.push((root_page_num, Vec::new()));
+    } else if level.len() > 1 && level[0].0 == root_page_num {
+        // the root leaf split, the root page is to hold the internal page above
+        let page_num = pager.get_unused_page_num()?;
+        let root = pager.get_page(root_page_num)?.clone();
+        pager.get_page_mut(page_num)?.clone_from(&root);
+        level[0].0 = page_num;
+    } else if level.len() == 1 && level[0].0 != root_page_num {
+        let page_num = level[0].0;
+        let leaf = pager.get_page(page_num)?.clone();
+        pager.get_page_mut(root_page_num)?.clone_from(&leaf);
+        pager.free_page(page_num)?;
+        level[0].0 = root_page_num;
+    }
+    for i in 0..level.len() {
+        let next_page_num = level.get(i + 1).map_or(0, |&(page_num, _)| page_num);
+        pager
+            .get_page_mut(level[i].0)?
+            .set_leaf_next_leaf(next_page_num);
+    }
+
+    let mut spare_pages: Vec<usize> = internals
+        .into_iter()
+        .filter(|&page_num| page_num != root_page_num)
+        .collect();
+    while level.len() > 1 {
+        let mut groups = vec![Vec::new()];
+        for child in level {
+            let group = groups.last_mut().unwrap();
+            group.push(child);
+            if page::internal_cells_space(group) > page::CELL_SPACE {
+                let child = group.pop().unwrap();
+                groups.push(vec![child]);
+            }
+        }
+
+        let is_top = groups.len() == 1;
+        level = Vec::with_capacity(groups.len());
+        for cells in groups {
+            let page_num = if is_top {
+                root_page_num
+            } else if let Some(page_num) = spare_pages.pop() {
+                page_num
+            } else {
+                pager.get_unused_page_num()?
+            };
+            let page = pager.get_page_mut(page_num)?;
+            page.init_internal();
+            page.set_internal_cells(&cells)?;
+            for (child, _) in &cells {
+                pager.get_page_mut(*child)?.set_parent(page_num);
+            }
+            level.push((page_num, cells[cells.len() - 1].1.clone()));
+        }
+    }
+    for page_num in spare_pages {
+        pager.free_page(page_num)?;
+    }
+
+    let root = pager.get_page_mut(root_page_num)?;
+    root.set_is_root(true);
+    root.set_parent(0);
+
+    Ok(())
+}
+
+/// List the leaves of an old tree in key order, and its internal pages
+fn walk_tree(
+    pager: &mut Pager,
+    page_num: usize,
+    leaves: &mut Vec<usize>,
+    internals: &mut Vec<usize>,
+    filename: &str,
+) -> Result<(), Error> {
+    let num_pages = pager.num_pages;
+    if page_num == HEADER_PAGE_NUM || page_num >= num_pages {
+        return Err(corrupted(filename));
+    }
+
+    let page = pager.get_page(page_num)?;
+    match page.get_type()? {
+        PageType::Leaf => leaves.push(page_num),
+        PageType::Internal => {
+            let num_keys = read_usize(page, NUM_CELLS_OFFSET);
+            if num_keys > INTERNAL_MAX_KEYS || internals.len() >= num_pages {
+                return Err(corrupted(filename));
+            }
+            let mut children: Vec<usize> = (0..num_keys)
+                .map(|i| read_usize(page, HEADER_SIZE + i * INTERNAL_CELL_SIZE))
+                .collect();
+            children.push(read_usize(page, NEXT_OR_RIGHT_CHILD_OFFSET));
+
+            internals.push(page_num);
+            for child in children {
+                walk_tree(pager, child, leaves, internals, filename)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The cells of a leaf of the users table as the original version stored them
+fn fixed_row_cells(page: &Page) -> Option<Cells> {
+    let num_cells = read_usize(page, NUM_CELLS_OFFSET);
+    if num_cells > LEAF_MAX_CELLS {
+        return None;
+    }
+
+    (0..num_cells)
+        .map(|i| {
+            let start = HEADER_SIZE + i * LEAF_CELL_SIZE;
+            let key = read_usize(page, start);
+            let row = fixed_row(&page.0[start + USIZE_SIZE..start + LEAF_CELL_SIZE])?;
+            // the widest rows of the original version fit in a cell
+            Some((key, page::leaf_cell_body(&row.serialize(), None)))
+        })
+        .collect()
+}
+
+/// Read a row of the users table as the original version stored it, with NUL-padded strings
+fn fixed_row(value: &[u8]) -> Option<row::Row> {
+    let text = |offset: usize, size: usize| {
+        let bytes = &value[offset..offset + size];
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(size);
+        String::from_utf8(bytes[..len].to_vec()).ok()
+    };
+
+    let id = u32::from_ne_bytes(value[0..ID_SIZE].try_into().unwrap());
+    Some(row::Row(vec![
+        Value::Integer(id as i64),
+        Value::Text(text(USERNAME_OFFSET, USERNAME_SIZE)?),
+        Value::Text(text(EMAIL_OFFSET, EMAIL_SIZE)?),
+    ]))
+}
+
+fn corrupted(filename: &str) -> Error {
+    Error::Corrupt(format!("corrupted file '{filename}'"))
+}
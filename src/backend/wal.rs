@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::mem;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::storage::Storage;
+use super::{page, Page};
+use crate::Error;
+
+// Log header layout
+const MAGIC: &[u8; MAGIC_SIZE] = b"resql wal\0\0\0\0\0\0\0";
+const MAGIC_OFFSET: usize = 0;
+const MAGIC_SIZE: usize = 16;
+const PAGE_SIZE_OFFSET: usize = MAGIC_OFFSET + MAGIC_SIZE;
+const PAGE_SIZE_SIZE: usize = mem::size_of::<u32>();
+const SALT_OFFSET: usize = PAGE_SIZE_OFFSET + PAGE_SIZE_SIZE;
+const SALT_SIZE: usize = mem::size_of::<u32>();
+const HEADER_SIZE: usize = MAGIC_SIZE + PAGE_SIZE_SIZE + SALT_SIZE;
+
+// Frame header layout, followed by the page image
+const FRAME_PAGE_NUM_OFFSET: usize = 0;
+const FRAME_PAGE_NUM_SIZE: usize = mem::size_of::<u32>();
+const FRAME_DB_SIZE_OFFSET: usize = FRAME_PAGE_NUM_OFFSET + FRAME_PAGE_NUM_SIZE;
+const FRAME_DB_SIZE_SIZE: usize = mem::size_of::<u32>();
+const FRAME_SALT_OFFSET: usize = FRAME_DB_SIZE_OFFSET + FRAME_DB_SIZE_SIZE;
+const FRAME_SALT_SIZE: usize = mem::size_of::<u32>();
+const FRAME_CHECKSUM_OFFSET: usize = FRAME_SALT_OFFSET + FRAME_SALT_SIZE;
+const FRAME_CHECKSUM_SIZE: usize = mem::size_of::<u32>();
+const FRAME_HEADER_SIZE: usize =
+    FRAME_PAGE_NUM_SIZE + FRAME_DB_SIZE_SIZE + FRAME_SALT_SIZE + FRAME_CHECKSUM_SIZE;
+const FRAME_SIZE: usize = FRAME_HEADER_SIZE + page::PAGE_SIZE;
+
+/// Write-ahead log of page images kept next to the database file, or in memory along with it.
+///
+/// Every frame holds one page. A frame carrying the database size ends a
+/// transaction, so only the frames up to the last such frame are committed.
+/// Each checksum covers the frame and the checksum of the frame before it,
+/// which lets recovery stop at the first torn or stale frame.
+pub(super) struct Wal<S: Storage> {
+    storage: S,
+    salt: u32,
+    checksum: u32,
+    num_frames: usize,
+    committed: Committed,
+    index: HashMap<usize, usize>, // page number -> last committed frame
+    pending: HashMap<usize, usize>, // page number -> last frame not committed yet
+}
+
+/// The state of the log before a statement of a transaction, which is undone
+/// by rolling back to it
+pub(super) struct Savepoint {
+    checksum: u32,
+    num_frames: usize,
+    pending: HashMap<usize, usize>,
+}
+
+/// The state of the log as of its last commit frame
+#[derive(Clone, Copy)]
+struct Committed {
+    checksum: u32,
+    num_frames: usize,
+    db_size: usize,
+}
+
+impl<S: Storage> Wal<S> {
+    /// Open the log kept in `storage`, keeping only its committed frames
+    pub(super) fn new(mut storage: S) -> Result<Self, Error> {
+        let mut header = [0u8; HEADER_SIZE];
+        let has_header = storage.size()? >= HEADER_SIZE
+            && storage.read_at(0, &mut header).is_ok()
+            && header[MAGIC_OFFSET..MAGIC_OFFSET + MAGIC_SIZE] == *MAGIC
+            && read_u32(&header, PAGE_SIZE_OFFSET) == page::PAGE_SIZE as u32;
+
+        let mut wal = Self {
+            storage,
+            salt: read_u32(&header, SALT_OFFSET),
+            checksum: 0,
+            num_frames: 0,
+            committed: Committed {
+                checksum: 0,
+                num_frames: 0,
+                db_size: 0,
+            },
+            index: HashMap::new(),
+            pending: HashMap::new(),
+        };
+
+        if has_header {
+            wal.recover()?;
+        } else {
+            // missing or unusable, nothing in it can be trusted
+            wal.reset()?;
+        }
+
+        Ok(wal)
+    }
+
+    /// Replay the frames of the log, stopping at the first one that does not check out
+    fn recover(&mut self) -> Result<(), Error> {
+        self.checksum = self.salt;
+        self.committed.checksum = self.salt;
+
+        let length = self.storage.size()?;
+        let mut frame = vec![0u8; FRAME_SIZE];
+        loop {
+            let offset = HEADER_SIZE + self.num_frames * FRAME_SIZE;
+            if offset + FRAME_SIZE > length || self.storage.read_at(offset, &mut frame).is_err() {
+                // end of the log, or a torn frame
+                break;
+            }
+
+            let checksum = checksum(self.checksum, &frame[..FRAME_CHECKSUM_OFFSET]);
+            let checksum = checksum_continue(checksum, &frame[FRAME_HEADER_SIZE..]);
+            if read_u32(&frame, FRAME_SALT_OFFSET) != self.salt
+                || read_u32(&frame, FRAME_CHECKSUM_OFFSET) != checksum
+            {
+                break;
+            }
+
+            let page_num = read_u32(&frame, FRAME_PAGE_NUM_OFFSET) as usize;
+            let db_size = read_u32(&frame, FRAME_DB_SIZE_OFFSET) as usize;
+            self.checksum = checksum;
+            self.pending.insert(page_num, self.num_frames);
+            self.num_frames += 1;
+
+            if db_size != 0 {
+                self.index.extend(self.pending.drain());
+                self.committed = Committed {
+                    checksum,
+                    num_frames: self.num_frames,
+                    db_size,
+                };
+            }
+        }
+
+        // whatever follows the last commit never happened
+        self.rollback()
+    }
+
+    /// The frame holding the latest image of a page, committed or not
+    pub(super) fn find(&self, page_num: usize) -> Option<usize> {
+        self.pending
+            .get(&page_num)
+            .or_else(|| self.index.get(&page_num))
+            .copied()
+    }
+
+    pub(super) fn read(&mut self, frame_num: usize) -> Result<Page, Error> {
+        let offset = HEADER_SIZE + frame_num * FRAME_SIZE + FRAME_HEADER_SIZE;
+        let mut page = page::Page(vec![0; page::PAGE_SIZE]);
+        self.storage.read_at(offset, &mut page.0)?;
+
+        Ok(page)
+    }
+
+    /// Append a page image. Passing the number of pages of the database
+    /// commits it along with every frame appended before it.
+    pub(super) fn append(
+        &mut self,
+        page_num: usize,
+        page: &Page,
+        db_size: Option<usize>,
+    ) -> Result<(), Error> {
+        let mut frame = vec![0u8; FRAME_SIZE];
+        write_u32(&mut frame, FRAME_PAGE_NUM_OFFSET, page_num as u32);
+        write_u32(
+            &mut frame,
+            FRAME_DB_SIZE_OFFSET,
+            db_size.unwrap_or(0) as u32,
+        );
+        write_u32(&mut frame, FRAME_SALT_OFFSET, self.salt);
+        frame[FRAME_HEADER_SIZE..].copy_from_slice(&page.0[0..page::PAGE_SIZE]);
+        let checksum = checksum(self.checksum, &frame[..FRAME_CHECKSUM_OFFSET]);
+        let checksum = checksum_continue(checksum, &frame[FRAME_HEADER_SIZE..]);
+        write_u32(&mut frame, FRAME_CHECKSUM_OFFSET, checksum);
+
+        let offset = HEADER_SIZE + self.num_frames * FRAME_SIZE;
+        self.storage.write_at(offset, &frame)?;
+        self.checksum = checksum;
+        self.pending.insert(page_num, self.num_frames);
+        self.num_frames += 1;
+
+        if let Some(db_size) = db_size {
+            self.storage.sync()?;
+            self.index.extend(self.pending.drain());
+            self.committed = Committed {
+                checksum,
+                num_frames: self.num_frames,
+                db_size,
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Forget the frames appended since the last commit
+    pub(super) fn rollback(&mut self) -> Result<(), Error> {
+        self.pending.clear();
+        self.checksum = self.committed.checksum;
+        self.num_frames = self.committed.num_frames;
+
+        self.storage
+            .truncate(HEADER_SIZE + self.num_frames * FRAME_SIZE)
+    }
+
+    pub(super) fn savepoint(&self) -> Savepoint {
+        Savepoint {
+            checksum: self.checksum,
+            num_frames: self.num_frames,
+            pending: self.pending.clone(),
+        }
+    }
+
+    /// Forget the frames appended since `savepoint`, which no commit may have followed
+    pub(super) fn rollback_to(&mut self, savepoint: Savepoint) -> Result<(), Error> {
+        self.pending = savepoint.pending;
+        self.checksum = savepoint.checksum;
+        self.num_frames = savepoint.num_frames;
+
+        self.storage
+            .truncate(HEADER_SIZE + self.num_frames * FRAME_SIZE)
+    }
+
+    pub(super) fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Whether the page was appended since the last commit
+    pub(super) fn is_pending(&self, page_num: usize) -> bool {
+        self.pending.contains_key(&page_num)
+    }
+
+    pub(super) fn get_num_frames(&self) -> usize {
+        self.num_frames
+    }
+
+    pub(super) fn get_db_size(&self) -> usize {
+        self.committed.db_size
+    }
+
+    /// The committed pages and the frames holding their latest image, in page order
+    pub(super) fn committed_frames(&self) -> Vec<(usize, usize)> {
+        let mut frames: Vec<(usize, usize)> = self.index.iter().map(|(&p, &f)| (p, f)).collect();
+        frames.sort_unstable();
+
+        frames
+    }
+
+    /// Empty the log once its pages are safely in the database file.
+    /// A fresh salt keeps frames of the old log from ever checking out again.
+    pub(super) fn reset(&mut self) -> Result<(), Error> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        self.salt = self.salt.wrapping_add(1) ^ nanos;
+        self.checksum = self.salt;
+        self.num_frames = 0;
+        self.committed = Committed {
+            checksum: self.salt,
+            num_frames: 0,
+            db_size: 0,
+        };
+        self.index.clear();
+        self.pending.clear();
+
+        let mut header = [0u8; HEADER_SIZE];
+        header[MAGIC_OFFSET..MAGIC_OFFSET + MAGIC_SIZE].copy_from_slice(MAGIC);
+        write_u32(&mut header, PAGE_SIZE_OFFSET, page::PAGE_SIZE as u32);
+        write_u32(&mut header, SALT_OFFSET, self.salt);
+        self.storage.write_at(0, &header)?;
+        self.storage.truncate(HEADER_SIZE)?;
+        self.storage.sync()
+    }
+
+    /// Remove the log file, which must have been reset
+    pub(super) fn remove(&mut self) {
+        self.storage.remove();
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// FNV-1a, chained through `seed`
+fn checksum(seed: u32, bytes: &[u8]) -> u32 {
+    checksum_continue(seed ^ 0x811c_9dc5, bytes)
+}
+
+fn checksum_continue(hash: u32, bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .fold(hash, |hash, &b| (hash ^ b as u32).wrapping_mul(0x0100_0193))
+}
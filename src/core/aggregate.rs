@@ -0,0 +1,414 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::mem;
+
+use super::ast::Expr;
+use super::eval;
+use crate::backend::row::Row;
+use crate::backend::{record_size, Collation, Pager, Schema, Sorter, Value};
+use crate::Error;
+
+const AGGREGATES: &[(&str, Function)] = &[
+    ("avg", Function::Avg),
+    ("count", Function::Count),
+    ("max", Function::Max),
+    ("min", Function::Min),
+    ("sum", Function::Sum),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Function {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+/// A call of an aggregate function in a query, its state kept as values
+/// so that groups can spill to disk: a count or a sum for `count` and
+/// `sum`, both for `avg`, and the value so far for `min` and `max`
+#[derive(Clone, Debug)]
+pub(super) struct Aggregate {
+    /// The call, which the result stands for when the query is evaluated
+    pub(super) call: Expr,
+    function: Function,
+    /// What is aggregated, none for `count(*)`
+    arg: Option<Expr>,
+}
+
+pub(super) fn is_aggregate(name: &str) -> bool {
+    AGGREGATES
+        .iter()
+        .any(|(aggregate, _)| aggregate.eq_ignore_ascii_case(name))
+}
+
+impl Aggregate {
+    /// The aggregate `expr` calls, if it is a call of one
+    fn of(expr: &Expr) -> Option<Result<Self, Error>> {
+        let Expr::Function { name, args } = expr else {
+            return None;
+        };
+        let &(_, function) = AGGREGATES
+            .iter()
+            .find(|(aggregate, _)| aggregate.eq_ignore_ascii_case(name))?;
+
+        let arg = match (function, &args[..]) {
+            (Function::Count, [Expr::Wildcard] | []) => None,
+            (_, [Expr::Wildcard]) => {
+                return Some(Err(Error::Invalid(
+                    "'*' may only be counted, as in count(*)".into(),
+                )))
+            }
+            (_, [arg]) => Some(arg.clone()),
+            _ => {
+                return Some(Err(Error::Invalid(format!(
+                    "wrong number of arguments to function {name}()"
+                ))))
+            }
+        };
+
+        Some(Ok(Self {
+            call: expr.clone(),
+            function,
+            arg,
+        }))
+    }
+
+    pub(super) fn is_count_all(&self) -> bool {
+        self.function == Function::Count && self.arg.is_none()
+    }
+
+    /// The value aggregated for a row
+    pub(super) fn arg(&self, schema: &Schema, key: &[Value], row: &Row) -> Result<Value, Error> {
+        match &self.arg {
+            Some(arg) => eval::eval(arg, schema, key, row),
+            None => Ok(Value::Integer(1)),
+        }
+    }
+
+    fn state_size(&self) -> usize {
+        match self.function {
+            Function::Avg => 2,
+            _ => 1,
+        }
+    }
+
+    /// Start the state of a group with its first row
+    fn init(&self, value: Value, state: &mut Vec<Value>) {
+        // NULL counts for nothing
+        let sum = || match value {
+            Value::Null => Value::Null,
+            _ => eval::number(&value).unwrap_or(Value::Integer(0)),
+        };
+        let count = Value::Integer(!value.is_null() as i64);
+
+        match self.function {
+            Function::Count => state.push(count),
+            Function::Sum => state.push(sum()),
+            Function::Avg => {
+                state.push(sum());
+                state.push(count);
+            }
+            Function::Min | Function::Max => state.push(value),
+        }
+    }
+
+    fn update(&self, value: Value, state: &mut [Value]) -> Result<(), Error> {
+        let mut other = Vec::with_capacity(self.state_size());
+        self.init(value, &mut other);
+        self.merge(state, &other)
+    }
+
+    /// Fold the state of part of a group into the state of another part
+    fn merge(&self, state: &mut [Value], other: &[Value]) -> Result<(), Error> {
+        let add = |a: &Value, b: &Value| match (a, b) {
+            (Value::Null, sum) | (sum, Value::Null) => Ok(sum.clone()),
+            (Value::Integer(a), Value::Integer(b)) => a
+                .checked_add(*b)
+                .map(Value::Integer)
+                .ok_or_else(|| Error::Invalid("integer overflow".into())),
+            (a, b) => Ok(Value::Real(eval::real(a) + eval::real(b))),
+        };
+        let replaces = |ordering: Ordering| {
+            !other[0].is_null()
+                && (state[0].is_null()
+                    || eval::sort_order(&other[0], &state[0], Collation::Binary) == ordering)
+        };
+
+        match self.function {
+            Function::Count | Function::Sum => state[0] = add(&state[0], &other[0])?,
+            Function::Avg => {
+                state[0] = add(&state[0], &other[0])?;
+                state[1] = add(&state[1], &other[1])?;
+            }
+            Function::Min if replaces(Ordering::Less) => state[0] = other[0].clone(),
+            Function::Max if replaces(Ordering::Greater) => state[0] = other[0].clone(),
+            Function::Min | Function::Max => {}
+        }
+
+        Ok(())
+    }
+
+    /// The value of the aggregate for a group, or for no rows at all without a
+    /// state: a count of 0, or else NULL, as for a group of NULL values
+    fn result(&self, state: Option<&[Value]>) -> Value {
+        let Some(state) = state else {
+            return match self.function {
+                Function::Count => Value::Integer(0),
+                _ => Value::Null,
+            };
+        };
+
+        match self.function {
+            Function::Avg => match eval::integer(&state[1]) {
+                0 => Value::Null,
+                count => Value::Real(eval::real(&state[0]) / count as f64),
+            },
+            _ => state[0].clone(),
+        }
+    }
+}
+
+/// Every aggregate called in `exprs`, each once
+pub(super) fn collect<'a>(
+    exprs: impl IntoIterator<Item = &'a Expr>,
+) -> Result<Vec<Aggregate>, Error> {
+    fn walk(expr: &Expr, aggregates: &mut Vec<Aggregate>) -> Result<(), Error> {
+        match Aggregate::of(expr) {
+            Some(aggregate) => {
+                let aggregate = aggregate?;
+                if !aggregates.iter().any(|a| a.call == aggregate.call) {
+                    aggregates.push(aggregate);
+                }
+                Ok(())
+            }
+            None => expr
+                .children()
+                .into_iter()
+                .try_for_each(|child| walk(child, aggregates)),
+        }
+    }
+
+    let mut aggregates = Vec::new();
+    for expr in exprs {
+        walk(expr, &mut aggregates)?;
+    }
+
+    Ok(aggregates)
+}
+
+/// Make sure `expr` only reads the rows of a group through its GROUP BY terms and aggregates
+pub(super) fn check_grouped(expr: &Expr, group_by: &[Expr], schema: &Schema) -> Result<(), Error> {
+    if group_by.contains(expr) {
+        return Ok(());
+    }
+
+    match (expr, Aggregate::of(expr)) {
+        (_, Some(aggregate)) => match aggregate?.arg {
+            // no aggregate within another
+            Some(arg) => eval::check(&arg, schema),
+            None => Ok(()),
+        },
+        (Expr::Column(name), _) => Err(Error::Invalid(format!(
+            "column '{name}' must appear in GROUP BY or be used in an aggregate function"
+        ))),
+        (Expr::Function { name, args }, _) => {
+            eval::check_function(name, args.len())?;
+            args.iter()
+                .try_for_each(|arg| check_grouped(arg, group_by, schema))
+        }
+        (expr, _) => expr
+            .children()
+            .into_iter()
+            .try_for_each(|child| check_grouped(child, group_by, schema)),
+    }
+}
+
+/// The value of `expr` for a group, given its GROUP BY values and the results of `aggregates`
+pub(super) fn eval_grouped(
+    expr: &Expr,
+    schema: &Schema,
+    group_by: &[Expr],
+    keys: &[Value],
+    aggregates: &[Aggregate],
+    results: &[Value],
+) -> Result<Value, Error> {
+    eval::evaluate(expr, schema, &|expr| {
+        if let Some(i) = group_by.iter().position(|term| term == expr) {
+            return Some(keys[i].clone());
+        }
+        let i = aggregates.iter().position(|a| a.call == *expr)?;
+        Some(results[i].clone())
+    })
+}
+
+type Compare = Box<dyn Fn(&[Value], &[Value]) -> Ordering>;
+
+/// Hash aggregation: groups are gathered in a hash table, which moves to a
+/// `Sorter` whenever it outgrows memory. The sorter then hands back the parts
+/// of each group one after another, to be merged.
+pub(super) struct Grouper<'a> {
+    aggregates: &'a [Aggregate],
+    num_keys: usize,
+    /// The collation of each GROUP BY value, text comparing equal by it being in the same group
+    collations: Vec<Collation>,
+    /// The GROUP BY values of the first row of each group and its state, by `group_key`
+    groups: HashMap<Vec<Value>, (Vec<Value>, Vec<Value>)>,
+    size: usize,
+    sorter: Sorter<Compare>,
+    spilled: bool,
+}
+
+impl<'a> Grouper<'a> {
+    pub(super) fn new(
+        pager: &Pager,
+        aggregates: &'a [Aggregate],
+        collations: Vec<Collation>,
+    ) -> Self {
+        let num_keys = collations.len();
+        let sort_collations = collations.clone();
+        let compare: Compare =
+            Box::new(move |a, b| compare_keys(&a[..num_keys], &b[..num_keys], &sort_collations));
+
+        Self {
+            aggregates,
+            num_keys,
+            collations,
+            groups: HashMap::new(),
+            size: 0,
+            sorter: Sorter::new(pager, compare),
+            spilled: false,
+        }
+    }
+
+    /// Add a row to the group of `keys`, `values` holding what each aggregate takes from it
+    pub(super) fn push(&mut self, keys: Vec<Value>, values: Vec<Value>) -> Result<(), Error> {
+        let group_key = group_key(&keys, &self.collations);
+        if let Some((_, state)) = self.groups.get_mut(&group_key) {
+            let mut offset = 0;
+            for (aggregate, value) in self.aggregates.iter().zip(values) {
+                let state_size = aggregate.state_size();
+                aggregate.update(value, &mut state[offset..offset + state_size])?;
+                offset += state_size;
+            }
+            return Ok(());
+        }
+
+        let mut state = Vec::new();
+        for (aggregate, value) in self.aggregates.iter().zip(values) {
+            aggregate.init(value, &mut state);
+        }
+        self.size += record_size(&keys) + record_size(&state);
+        self.groups.insert(group_key, (keys, state));
+        if self.size > self.sorter.memory() {
+            self.spill()?;
+        }
+
+        Ok(())
+    }
+
+    /// Hand over the GROUP BY values and aggregate results of every group, in the
+    /// order of their values, until `visit` returns false. Without GROUP BY there
+    /// is a single group, even of no rows.
+    pub(super) fn finish(
+        mut self,
+        mut visit: impl FnMut(&[Value], &[Value]) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
+        let aggregates = self.aggregates;
+        let num_keys = self.num_keys;
+        let results = |state: &[Value]| -> Vec<Value> {
+            let mut offset = 0;
+            let mut results = Vec::with_capacity(aggregates.len());
+            for aggregate in aggregates {
+                let state_size = aggregate.state_size();
+                results.push(aggregate.result(Some(&state[offset..offset + state_size])));
+                offset += state_size;
+            }
+            results
+        };
+
+        if !self.spilled {
+            if self.groups.is_empty() && num_keys == 0 {
+                let results: Vec<Value> = aggregates.iter().map(|a| a.result(None)).collect();
+                return visit(&[], &results).map(|_| ());
+            }
+
+            let mut groups: Vec<(Vec<Value>, Vec<Value>)> = self.groups.into_values().collect();
+            groups.sort_by(|(a, _), (b, _)| compare_keys(a, b, &self.collations));
+            for (keys, state) in groups {
+                if !visit(&keys, &results(&state))? {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+
+        self.spill()?;
+        let merge = |state: &mut [Value], other: &[Value]| -> Result<(), Error> {
+            let mut offset = 0;
+            for aggregate in aggregates {
+                let state_size = aggregate.state_size();
+                let range = offset..offset + state_size;
+                aggregate.merge(&mut state[range.clone()], &other[range])?;
+                offset += state_size;
+            }
+            Ok(())
+        };
+
+        // the parts of a group come one after another, a group is done when the next one starts
+        let collations = &self.collations;
+        let mut group: Option<Vec<Value>> = None;
+        let mut stopped = false;
+        self.sorter.finish(|record| {
+            let Some(current) = &mut group else {
+                group = Some(record);
+                return Ok(true);
+            };
+            if compare_keys(&current[..num_keys], &record[..num_keys], collations).is_eq() {
+                merge(&mut current[num_keys..], &record[num_keys..])?;
+                return Ok(true);
+            }
+
+            let done = mem::replace(current, record);
+            stopped = !visit(&done[..num_keys], &results(&done[num_keys..]))?;
+            Ok(!stopped)
+        })?;
+
+        match group {
+            Some(last) if !stopped => {
+                visit(&last[..num_keys], &results(&last[num_keys..])).map(|_| ())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn spill(&mut self) -> Result<(), Error> {
+        for (_, (mut keys, state)) in self.groups.drain() {
+            keys.extend(state);
+            self.sorter.push(keys)?;
+        }
+        self.size = 0;
+        self.spilled = true;
+
+        Ok(())
+    }
+}
+
+/// How two sets of GROUP BY values sort, text by the collation of each
+fn compare_keys(a: &[Value], b: &[Value], collations: &[Collation]) -> Ordering {
+    (a.iter().zip(b).zip(collations))
+        .map(|((a, b), collation)| eval::sort_order(a, b, *collation))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// What GROUP BY values are hashed by, the same for all those comparing equal
+fn group_key(keys: &[Value], collations: &[Collation]) -> Vec<Value> {
+    (keys.iter().zip(collations))
+        .map(|(key, collation)| match key {
+            Value::Text(s) => Value::Text(collation.normalize(s)),
+            key => key.clone(),
+        })
+        .collect()
+}
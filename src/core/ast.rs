@@ -0,0 +1,314 @@
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Statement {
+    Select(Select),
+    Insert(Insert),
+    Update(Update),
+    Delete(Delete),
+    CreateTable(CreateTable),
+    CreateIndex(CreateIndex),
+    Begin,
+    Commit,
+    Rollback,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Select {
+    pub columns: Vec<SelectColumn>,
+    pub from: Option<String>,
+    pub filter: Option<Expr>,
+    pub group_by: Vec<Expr>,
+    pub having: Option<Expr>,
+    pub order_by: Vec<OrderBy>,
+    pub limit: Option<Expr>,
+    pub offset: Option<Expr>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SelectColumn {
+    All,
+    Expr(Expr),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderBy {
+    pub expr: Expr,
+    pub descending: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Insert {
+    pub table: Option<String>,
+    pub columns: Option<Vec<String>>,
+    pub rows: Vec<Vec<Expr>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Update {
+    pub table: Option<String>,
+    pub assignments: Vec<(String, Expr)>,
+    pub filter: Option<Expr>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Delete {
+    pub table: Option<String>,
+    pub filter: Option<Expr>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CreateTable {
+    pub name: String,
+    pub columns: Vec<ColumnDef>,
+    /// The columns of a `PRIMARY KEY (...)` constraint, if the table has one
+    pub primary_key: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CreateIndex {
+    pub name: String,
+    pub table: String,
+    pub columns: Vec<String>,
+    pub unique: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnDef {
+    pub name: String,
+    pub type_name: Option<String>,
+    pub primary_key: bool,
+    pub collation: Option<String>,
+    pub not_null: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Literal(Literal),
+    Column(String),
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+    },
+    Binary {
+        left: Box<Expr>,
+        op: BinaryOp,
+        right: Box<Expr>,
+    },
+    Between {
+        expr: Box<Expr>,
+        negated: bool,
+        low: Box<Expr>,
+        high: Box<Expr>,
+    },
+    /// `expr IS NULL`, or `expr IS NOT NULL` when negated
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+    Cast {
+        expr: Box<Expr>,
+        type_name: String,
+    },
+    Function {
+        name: String,
+        args: Vec<Expr>,
+    },
+    /// The `*` of `count(*)`
+    Wildcard,
+    /// A `?` placeholder, numbered from 0 in the order they appear
+    Parameter(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Literal {
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Blob(Vec<u8>),
+    Boolean(bool),
+    Null,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnaryOp {
+    Plus,
+    Minus,
+    Not,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Concat,
+}
+
+impl Statement {
+    /// The expressions the statement is made of, leaving out those within them
+    pub fn exprs_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Statement::Select(select) => {
+                let mut exprs: Vec<&mut Expr> = (select.columns.iter_mut())
+                    .filter_map(|column| match column {
+                        SelectColumn::All => None,
+                        SelectColumn::Expr(expr) => Some(expr),
+                    })
+                    .collect();
+                exprs.extend(&mut select.filter);
+                exprs.extend(&mut select.group_by);
+                exprs.extend(&mut select.having);
+                exprs.extend(select.order_by.iter_mut().map(|term| &mut term.expr));
+                exprs.extend(&mut select.limit);
+                exprs.extend(&mut select.offset);
+                exprs
+            }
+            Statement::Insert(insert) => insert.rows.iter_mut().flatten().collect(),
+            Statement::Update(update) => {
+                let mut exprs: Vec<&mut Expr> = update
+                    .assignments
+                    .iter_mut()
+                    .map(|(_, expr)| expr)
+                    .collect();
+                exprs.extend(&mut update.filter);
+                exprs
+            }
+            Statement::Delete(delete) => delete.filter.iter_mut().collect(),
+            Statement::CreateTable(_)
+            | Statement::CreateIndex(_)
+            | Statement::Begin
+            | Statement::Commit
+            | Statement::Rollback => vec![],
+        }
+    }
+}
+
+impl Expr {
+    /// The expressions this one is made of
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Literal(_) | Expr::Column(_) | Expr::Wildcard | Expr::Parameter(_) => vec![],
+            Expr::Unary { expr, .. } | Expr::IsNull { expr, .. } | Expr::Cast { expr, .. } => {
+                vec![expr]
+            }
+            Expr::Binary { left, right, .. } => vec![left, right],
+            Expr::Between {
+                expr, low, high, ..
+            } => vec![expr, low, high],
+            Expr::Function { args, .. } => args.iter().collect(),
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Expr::Literal(_) | Expr::Column(_) | Expr::Wildcard | Expr::Parameter(_) => vec![],
+            Expr::Unary { expr, .. } | Expr::IsNull { expr, .. } | Expr::Cast { expr, .. } => {
+                vec![expr]
+            }
+            Expr::Binary { left, right, .. } => vec![left, right],
+            Expr::Between {
+                expr, low, high, ..
+            } => vec![expr, low, high],
+            Expr::Function { args, .. } => args.iter_mut().collect(),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Literal(literal) => write!(f, "{literal}"),
+            Expr::Column(name) => write!(f, "{name}"),
+            Expr::Unary {
+                op: UnaryOp::Not,
+                expr,
+            } => write!(f, "NOT {expr}"),
+            Expr::Unary { op, expr } => write!(f, "{op}{expr}"),
+            Expr::Binary { left, op, right } => write!(f, "({left} {op} {right})"),
+            Expr::Between {
+                expr,
+                negated,
+                low,
+                high,
+            } => {
+                let not = if *negated { "NOT " } else { "" };
+                write!(f, "({expr} {not}BETWEEN {low} AND {high})")
+            }
+            Expr::IsNull { expr, negated } => {
+                let not = if *negated { "NOT " } else { "" };
+                write!(f, "({expr} IS {not}NULL)")
+            }
+            Expr::Cast { expr, type_name } => write!(f, "CAST({expr} AS {type_name})"),
+            Expr::Function { name, args } => {
+                let args: Vec<String> = args.iter().map(Expr::to_string).collect();
+                write!(f, "{name}({})", args.join(", "))
+            }
+            Expr::Wildcard => write!(f, "*"),
+            Expr::Parameter(_) => write!(f, "?"),
+        }
+    }
+}
+
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Literal::Integer(n) => write!(f, "{n}"),
+            Literal::Float(n) => write!(f, "{n:?}"),
+            Literal::String(s) => write!(f, "'{}'", s.replace('\'', "''")),
+            Literal::Blob(bytes) => {
+                write!(f, "X'")?;
+                for byte in bytes {
+                    write!(f, "{byte:02X}")?;
+                }
+                write!(f, "'")
+            }
+            Literal::Boolean(true) => write!(f, "TRUE"),
+            Literal::Boolean(false) => write!(f, "FALSE"),
+            Literal::Null => write!(f, "NULL"),
+        }
+    }
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            UnaryOp::Plus => "+",
+            UnaryOp::Minus => "-",
+            UnaryOp::Not => "NOT",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            BinaryOp::Or => "OR",
+            BinaryOp::And => "AND",
+            BinaryOp::Eq => "=",
+            BinaryOp::NotEq => "<>",
+            BinaryOp::Lt => "<",
+            BinaryOp::LtEq => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::GtEq => ">=",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Concat => "||",
+        };
+        write!(f, "{s}")
+    }
+}
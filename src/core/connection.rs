@@ -0,0 +1,82 @@
+use super::ast;
+use super::parser;
+use super::query::{Query, Rows};
+use super::statement::{self, Statement};
+use crate::backend::{Database, Storage, Value};
+use crate::Error;
+
+/// A database used from Rust rather than through the REPL: statements run with
+/// values bound to their `?` placeholders, and queries hand back their rows
+pub struct Connection {
+    database: Database,
+}
+
+/// A statement parsed once, to be run any number of times with different values
+pub struct PreparedStatement {
+    statement: ast::Statement,
+}
+
+impl Connection {
+    pub fn open(filename: &str) -> Result<Self, Error> {
+        Ok(Self {
+            database: Database::open(filename)?,
+        })
+    }
+
+    /// Open the database kept in `storage`, logging to `wal`. `name` is what errors call it.
+    pub fn open_with_storage(
+        name: &str,
+        storage: impl Storage + 'static,
+        wal: impl Storage + 'static,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            database: Database::open_with_storage(name, storage, wal)?,
+        })
+    }
+
+    /// Run a statement, returning how many rows it inserted, updated or deleted
+    pub fn execute(&mut self, sql: &str, params: &[Value]) -> Result<usize, Error> {
+        self.prepare(sql)?.execute(self, params)
+    }
+
+    pub fn prepare(&self, sql: &str) -> Result<PreparedStatement, Error> {
+        let statement = parser::parse(sql).map_err(|e| Error::Syntax(e.to_string()))?;
+
+        Ok(PreparedStatement { statement })
+    }
+
+    /// Run a SELECT, returning its rows
+    pub fn query(&mut self, sql: &str, params: &[Value]) -> Result<Rows<'_>, Error> {
+        self.prepare(sql)?.query(self, params)
+    }
+
+    /// Close the database, an unfinished transaction is rolled back
+    pub fn close(mut self) -> Result<(), Error> {
+        self.database.close()
+    }
+}
+
+impl PreparedStatement {
+    /// Run the statement with `params` bound to its placeholders in order,
+    /// returning how many rows it inserted, updated or deleted
+    pub fn execute(&self, connection: &mut Connection, params: &[Value]) -> Result<usize, Error> {
+        let database = &mut connection.database;
+        let result = statement::bind(&self.statement, params)
+            .and_then(|statement| Statement::run(&statement, database));
+
+        statement::finish(database, result)
+    }
+
+    /// Run the SELECT with `params` bound to its placeholders in order, returning its rows
+    pub fn query<'a>(
+        &self,
+        connection: &'a mut Connection,
+        params: &[Value],
+    ) -> Result<Rows<'a>, Error> {
+        let ast::Statement::Select(select) = statement::bind(&self.statement, params)? else {
+            return Err(Error::Invalid("only a SELECT returns rows".into()));
+        };
+
+        Query::new(&select, &connection.database)?.rows(&mut connection.database)
+    }
+}
@@ -0,0 +1,426 @@
+use std::cmp::Ordering;
+use std::ops::RangeInclusive;
+
+use super::aggregate;
+use super::ast::{BinaryOp, Expr, Literal, UnaryOp};
+use crate::backend::row::Row;
+use crate::backend::{Collation, ColumnType, Schema, Value};
+use crate::Error;
+
+/// The scalar functions, with how many arguments each takes
+const FUNCTIONS: &[(&str, RangeInclusive<usize>)] = &[
+    ("instr", 2..=2),
+    ("length", 1..=1),
+    ("lower", 1..=1),
+    ("substr", 2..=3),
+    ("upper", 1..=1),
+];
+
+/// Make sure every column `expr` names belongs to the table, before any row is read
+pub(super) fn check(expr: &Expr, schema: &Schema) -> Result<(), Error> {
+    match expr {
+        Expr::Column(name) => match schema.column_index(name) {
+            Some(_) => Ok(()),
+            None if name.eq_ignore_ascii_case("rowid") && schema.has_row_id() => Ok(()),
+            None => Err(Error::Invalid(format!("no such column: '{name}'"))),
+        },
+        Expr::Function { name, .. } if aggregate::is_aggregate(name) => Err(Error::Invalid(
+            format!("misuse of aggregate function {name}()"),
+        )),
+        Expr::Function { name, args } => {
+            check_function(name, args.len())?;
+            args.iter().try_for_each(|arg| check(arg, schema))
+        }
+        Expr::Cast { expr, type_name } => {
+            column_type(type_name)?;
+            check(expr, schema)
+        }
+        Expr::Wildcard => Err(Error::Invalid(
+            "'*' may only be counted, as in count(*)".into(),
+        )),
+        expr => expr
+            .children()
+            .into_iter()
+            .try_for_each(|child| check(child, schema)),
+    }
+}
+
+/// Make sure a scalar function exists and is given as many arguments as it takes
+pub(super) fn check_function(name: &str, num_args: usize) -> Result<(), Error> {
+    match FUNCTIONS
+        .iter()
+        .find(|(function, _)| function.eq_ignore_ascii_case(name))
+    {
+        Some((_, arity)) if arity.contains(&num_args) => Ok(()),
+        Some(_) => Err(Error::Invalid(format!(
+            "wrong number of arguments to function {name}()"
+        ))),
+        None => Err(Error::Invalid(format!("no such function: '{name}'"))),
+    }
+}
+
+/// The value of `expr` for the row keyed by `key`
+pub(super) fn eval(expr: &Expr, schema: &Schema, key: &[Value], row: &Row) -> Result<Value, Error> {
+    evaluate(expr, schema, &|expr| {
+        let Expr::Column(name) = expr else {
+            return None;
+        };
+        match schema.column_index(name) {
+            Some(column) => Some(row.0[column].clone()),
+            // a table without an integer primary key still has its row id
+            None => Some(key[0].clone()),
+        }
+    })
+}
+
+/// The value of `expr`, `leaf` giving the values of its columns and of whatever
+/// else was worked out beforehand, like aggregates. Text compares with the
+/// collation of the column of `schema` on the left of a comparison, or else on its right.
+/// Conditions are true, false or, when they involve NULL, unknown, which is NULL.
+pub(super) fn evaluate(
+    expr: &Expr,
+    schema: &Schema,
+    leaf: &dyn Fn(&Expr) -> Option<Value>,
+) -> Result<Value, Error> {
+    if let Some(value) = leaf(expr) {
+        return Ok(value);
+    }
+    let eval = |expr: &Expr| evaluate(expr, schema, leaf);
+
+    match expr {
+        Expr::Literal(Literal::Integer(n)) => Ok(Value::Integer(*n)),
+        Expr::Literal(Literal::Float(n)) => Ok(Value::Real(*n)),
+        Expr::Literal(Literal::String(s)) => Ok(Value::Text(s.clone())),
+        Expr::Literal(Literal::Blob(bytes)) => Ok(Value::Blob(bytes.clone())),
+        Expr::Literal(Literal::Boolean(b)) => Ok(Value::Boolean(*b)),
+        Expr::Literal(Literal::Null) => Ok(Value::Null),
+        Expr::Column(name) => Err(Error::Invalid(format!("no such column: '{name}'"))),
+        Expr::Unary { op, expr } => {
+            let value = eval(expr)?;
+            match op {
+                UnaryOp::Plus => Ok(value),
+                UnaryOp::Minus => negate(&value),
+                UnaryOp::Not => Ok(truth_value(truth(&value).map(|b| !b))),
+            }
+        }
+        Expr::Binary {
+            left,
+            op: BinaryOp::And,
+            right,
+        } => {
+            // false wins over unknown
+            let left = truth(&eval(left)?);
+            if left == Some(false) {
+                return Ok(Value::Boolean(false));
+            }
+            Ok(match (left, truth(&eval(right)?)) {
+                (_, Some(false)) => Value::Boolean(false),
+                (Some(true), Some(true)) => Value::Boolean(true),
+                _ => Value::Null,
+            })
+        }
+        Expr::Binary {
+            left,
+            op: BinaryOp::Or,
+            right,
+        } => {
+            // true wins over unknown
+            let left = truth(&eval(left)?);
+            if left == Some(true) {
+                return Ok(Value::Boolean(true));
+            }
+            Ok(match (left, truth(&eval(right)?)) {
+                (_, Some(true)) => Value::Boolean(true),
+                (Some(false), Some(false)) => Value::Boolean(false),
+                _ => Value::Null,
+            })
+        }
+        Expr::Binary { left, op, right } => {
+            let collation = collation(left, schema)
+                .or_else(|| collation(right, schema))
+                .unwrap_or_default();
+            binary(*op, &eval(left)?, &eval(right)?, collation)
+        }
+        Expr::Between {
+            expr,
+            negated,
+            low,
+            high,
+        } => {
+            let collation = collation(expr, schema).unwrap_or_default();
+            let value = eval(expr)?;
+            let low = binary(BinaryOp::GtEq, &value, &eval(low)?, collation)?;
+            let high = binary(BinaryOp::LtEq, &value, &eval(high)?, collation)?;
+            let between = match (truth(&low), truth(&high)) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            };
+            Ok(truth_value(between.map(|b| b != *negated)))
+        }
+        Expr::IsNull { expr, negated } => Ok(Value::Boolean(eval(expr)?.is_null() != *negated)),
+        Expr::Cast { expr, type_name } => cast(eval(expr)?, column_type(type_name)?),
+        Expr::Function { name, .. } if aggregate::is_aggregate(name) => Err(Error::Invalid(
+            format!("misuse of aggregate function {name}()"),
+        )),
+        Expr::Function { name, args } => {
+            check_function(name, args.len())?;
+            let args = args.iter().map(eval).collect::<Result<Vec<_>, _>>()?;
+            // every function of NULL is NULL
+            if args.iter().any(Value::is_null) {
+                return Ok(Value::Null);
+            }
+            Ok(function(name, &args))
+        }
+        Expr::Wildcard => Err(Error::Invalid(
+            "'*' may only be counted, as in count(*)".into(),
+        )),
+        Expr::Parameter(n) => Err(Error::Invalid(format!(
+            "no value bound to parameter {}",
+            n + 1
+        ))),
+    }
+}
+
+/// The collation of the column `expr` names, if it names one of `schema`
+pub(super) fn collation(expr: &Expr, schema: &Schema) -> Option<Collation> {
+    match expr {
+        Expr::Column(name) => Some(schema.columns[schema.column_index(name)?].collation),
+        _ => None,
+    }
+}
+
+/// The type a CAST converts to
+pub(super) fn column_type(type_name: &str) -> Result<ColumnType, Error> {
+    ColumnType::from_name(type_name)
+        .ok_or_else(|| Error::Invalid(format!("unknown type '{}'", type_name.to_ascii_uppercase())))
+}
+
+/// Convert a value to a type, as CAST does and as values are stored in columns
+/// of the type. NULL stays NULL, text is parsed, and reals lose their fraction
+/// on their way to integers, which only CAST lets them do.
+pub(super) fn cast(value: Value, column_type: ColumnType) -> Result<Value, Error> {
+    let fail = |value: &Value| {
+        let type_name = column_type.to_string().to_ascii_lowercase();
+        Err(Error::Invalid(format!(
+            "can't parse '{value}' to {type_name}"
+        )))
+    };
+
+    match (column_type, value) {
+        (_, Value::Null) => Ok(Value::Null),
+        (ColumnType::Integer, Value::Integer(n)) => Ok(Value::Integer(n)),
+        (ColumnType::Integer, Value::Boolean(b)) => Ok(Value::Integer(b as i64)),
+        (ColumnType::Integer, value @ (Value::Real(_) | Value::Text(_))) => {
+            match number(&value) {
+                Some(Value::Integer(n)) => Ok(Value::Integer(n)),
+                // the range of i64 is half-open as a real
+                Some(Value::Real(n)) if n >= i64::MIN as f64 && n < i64::MAX as f64 => {
+                    Ok(Value::Integer(n as i64))
+                }
+                _ => fail(&value),
+            }
+        }
+        (ColumnType::Real, value @ (Value::Boolean(_) | Value::Integer(_) | Value::Real(_))) => {
+            Ok(Value::Real(real(&value)))
+        }
+        (ColumnType::Real, value @ Value::Text(_)) => match number(&value) {
+            Some(n) => Ok(Value::Real(real(&n))),
+            None => fail(&value),
+        },
+        (ColumnType::Text { .. }, Value::Blob(bytes)) => match String::from_utf8(bytes) {
+            Ok(s) => Ok(Value::Text(s)),
+            Err(e) => fail(&Value::Blob(e.into_bytes())),
+        },
+        (ColumnType::Text { .. }, value) => Ok(Value::Text(value.to_string())),
+        (ColumnType::Blob, Value::Blob(bytes)) => Ok(Value::Blob(bytes)),
+        (ColumnType::Blob, Value::Text(s)) => Ok(Value::Blob(s.into_bytes())),
+        (ColumnType::Blob, value) => Ok(Value::Blob(value.to_string().into_bytes())),
+        (ColumnType::Boolean, Value::Boolean(b)) => Ok(Value::Boolean(b)),
+        (ColumnType::Boolean, Value::Integer(n)) => Ok(Value::Boolean(n != 0)),
+        (ColumnType::Boolean, Value::Real(n)) => Ok(Value::Boolean(n != 0.0)),
+        (ColumnType::Boolean, Value::Text(s)) => match s.trim().to_ascii_lowercase().as_str() {
+            "true" | "1" => Ok(Value::Boolean(true)),
+            "false" | "0" => Ok(Value::Boolean(false)),
+            _ => fail(&Value::Text(s)),
+        },
+        (_, value) => fail(&value),
+    }
+}
+
+/// Whether a value counts as true in a condition, where unknown doesn't
+pub(super) fn is_true(value: &Value) -> bool {
+    truth(value) == Some(true)
+}
+
+/// Whether a value is true or false, or unknown for NULL
+fn truth(value: &Value) -> Option<bool> {
+    match value {
+        Value::Null => None,
+        Value::Boolean(b) => Some(*b),
+        value => Some(real(value) != 0.0),
+    }
+}
+
+fn truth_value(truth: Option<bool>) -> Value {
+    truth.map_or(Value::Null, Value::Boolean)
+}
+
+fn binary(op: BinaryOp, left: &Value, right: &Value, collation: Collation) -> Result<Value, Error> {
+    if left.is_null() || right.is_null() {
+        return Ok(Value::Null);
+    }
+    let comparison =
+        |holds: fn(Ordering) -> bool| Ok(Value::Boolean(holds(compare(left, right, collation))));
+
+    match op {
+        BinaryOp::Eq => comparison(Ordering::is_eq),
+        BinaryOp::NotEq => comparison(Ordering::is_ne),
+        BinaryOp::Lt => comparison(Ordering::is_lt),
+        BinaryOp::LtEq => comparison(Ordering::is_le),
+        BinaryOp::Gt => comparison(Ordering::is_gt),
+        BinaryOp::GtEq => comparison(Ordering::is_ge),
+        BinaryOp::Concat => Ok(Value::Text(format!("{left}{right}"))),
+        BinaryOp::And | BinaryOp::Or => unreachable!("evaluated lazily"),
+        op => arithmetic(op, left, right),
+    }
+}
+
+/// Integers stay integers, anything else makes a real
+fn arithmetic(op: BinaryOp, left: &Value, right: &Value) -> Result<Value, Error> {
+    let left = number(left).unwrap_or(Value::Integer(0));
+    let right = number(right).unwrap_or(Value::Integer(0));
+    if matches!(op, BinaryOp::Div | BinaryOp::Rem) && real(&right) == 0.0 {
+        return Err(Error::Invalid("division by zero".into()));
+    }
+
+    if let (Value::Integer(a), Value::Integer(b)) = (&left, &right) {
+        let result = match op {
+            BinaryOp::Add => a.checked_add(*b),
+            BinaryOp::Sub => a.checked_sub(*b),
+            BinaryOp::Mul => a.checked_mul(*b),
+            BinaryOp::Div => a.checked_div(*b),
+            BinaryOp::Rem => a.checked_rem(*b),
+            _ => unreachable!("not arithmetic"),
+        };
+        return result
+            .map(Value::Integer)
+            .ok_or_else(|| Error::Invalid("integer overflow".into()));
+    }
+
+    let (a, b) = (real(&left), real(&right));
+    Ok(Value::Real(match op {
+        BinaryOp::Add => a + b,
+        BinaryOp::Sub => a - b,
+        BinaryOp::Mul => a * b,
+        BinaryOp::Div => a / b,
+        BinaryOp::Rem => a % b,
+        _ => unreachable!("not arithmetic"),
+    }))
+}
+
+fn negate(value: &Value) -> Result<Value, Error> {
+    match number(value) {
+        Some(Value::Real(n)) => Ok(Value::Real(-n)),
+        _ if value.is_null() => Ok(Value::Null),
+        _ => integer(value)
+            .checked_neg()
+            .map(Value::Integer)
+            .ok_or_else(|| Error::Invalid("integer overflow".into())),
+    }
+}
+
+/// Apply a scalar function whose arguments were checked, counting characters from 1
+fn function(name: &str, args: &[Value]) -> Value {
+    let text = |i: usize| args[i].to_string();
+
+    match name.to_ascii_lowercase().as_str() {
+        "length" => match &args[0] {
+            Value::Blob(bytes) => Value::Integer(bytes.len() as i64),
+            value => Value::Integer(value.to_string().chars().count() as i64),
+        },
+        "lower" => Value::Text(text(0).to_lowercase()),
+        "upper" => Value::Text(text(0).to_uppercase()),
+        "instr" => {
+            let (haystack, needle) = (text(0), text(1));
+            let position = haystack
+                .find(&needle)
+                .map_or(0, |i| haystack[..i].chars().count() + 1);
+            Value::Integer(position as i64)
+        }
+        "substr" => {
+            let chars: Vec<char> = text(0).chars().collect();
+            let len = chars.len() as i64;
+            // a negative start counts from the end
+            let start = match integer(&args[1]) {
+                n if n < 0 => (len + n).max(0),
+                n => (n - 1).max(0),
+            };
+            let end = match args.get(2) {
+                Some(count) => start.saturating_add(integer(count).max(0)),
+                None => len,
+            };
+            let (start, end) = (start.min(len) as usize, end.min(len) as usize);
+            Value::Text(chars[start..end].iter().collect())
+        }
+        _ => unreachable!("checked function '{name}'"),
+    }
+}
+
+/// Order two values other than NULL, text by `collation`. Numbers compare by
+/// value, booleans as 0 and 1. Text that reads as a number compares as one,
+/// other text sorts after every number and before byte strings.
+pub(super) fn compare(left: &Value, right: &Value, collation: Collation) -> Ordering {
+    match (number(left), number(right)) {
+        (Some(a), Some(b)) if !matches!((left, right), (Value::Text(_), Value::Text(_))) => {
+            a.cmp(&b)
+        }
+        (Some(_), None) if matches!(right, Value::Text(_)) => Ordering::Less,
+        (None, Some(_)) if matches!(left, Value::Text(_)) => Ordering::Greater,
+        _ => sort_order(left, right, collation),
+    }
+}
+
+/// The order rows sort in, that of `Value` with text by `collation`
+pub(super) fn sort_order(left: &Value, right: &Value, collation: Collation) -> Ordering {
+    match (left, right) {
+        (Value::Text(a), Value::Text(b)) => collation.compare(a.as_bytes(), b.as_bytes()),
+        _ => left.cmp(right),
+    }
+}
+
+/// The number a value stands for: an integer or a real, booleans as 0 or 1,
+/// text if it reads as one
+pub(super) fn number(value: &Value) -> Option<Value> {
+    match value {
+        Value::Integer(_) | Value::Real(_) => Some(value.clone()),
+        Value::Boolean(b) => Some(Value::Integer(*b as i64)),
+        Value::Text(s) => {
+            let s = s.trim();
+            match s.parse() {
+                Ok(n) => Some(Value::Integer(n)),
+                Err(_) => s.parse().ok().map(Value::Real),
+            }
+        }
+        Value::Null | Value::Blob(_) => None,
+    }
+}
+
+/// The integer a value stands for in arithmetic, reals cut to their whole
+/// part, and anything that isn't a number counting as 0
+pub(super) fn integer(value: &Value) -> i64 {
+    match number(value) {
+        Some(Value::Integer(n)) => n,
+        Some(Value::Real(n)) => n as i64,
+        _ => 0,
+    }
+}
+
+/// The real a value stands for in arithmetic, anything that isn't a number counting as 0
+pub(super) fn real(value: &Value) -> f64 {
+    match number(value) {
+        Some(Value::Integer(n)) => n as f64,
+        Some(Value::Real(n)) => n,
+        _ => 0.0,
+    }
+}
@@ -1,33 +1,144 @@
-use std::io;
+use std::io::{self, BufRead};
 
-#[derive(Default)]
+use crate::Error;
+
+/// Lines read from the shell's input, gathered until `;` ends the statements they hold
 pub struct InputBuffer {
-    buffer: String,
-    input_length: usize,
+    reader: Box<dyn BufRead>,
+    line: String,
+    /// The start of a statement no `;` ended yet
+    pending: String,
+    at_end: bool,
 }
 
 impl InputBuffer {
+    /// Read what is typed, or piped, into stdin
     pub fn new() -> Self {
+        Self::from_reader(Box::new(io::stdin().lock()))
+    }
+
+    /// Read a script, or the statements given on the command line
+    pub fn from_reader(reader: Box<dyn BufRead>) -> Self {
         Self {
-            buffer: String::new(),
-            input_length: 0,
+            reader,
+            line: String::new(),
+            pending: String::new(),
+            at_end: false,
+        }
+    }
+
+    /// Read the next line, replacing the one read before. Returns false once
+    /// the input is over, the statement left without `;` then ends there.
+    pub fn read(&mut self) -> Result<bool, Error> {
+        self.line.clear();
+        let len = (self.reader.read_line(&mut self.line))
+            .map_err(|e| Error::io(format!("failed to read the input. {e}"), e))?;
+        self.at_end = len == 0;
+
+        Ok(!self.at_end)
+    }
+
+    /// The prompt for the next line, which tells whether a statement goes on
+    pub fn prompt(&self) -> &'static str {
+        if self.pending.is_empty() {
+            ">> "
+        } else {
+            "..> "
+        }
+    }
+
+    /// The line just read, if it is a metacommand. Those run as soon as they
+    /// are read, but only where a statement could start.
+    pub fn meta_command(&self) -> Option<&str> {
+        let line = self.line.trim();
+        (self.pending.is_empty() && line.starts_with('.')).then_some(line)
+    }
+
+    /// Add the line just read, and take every statement a `;` now ends
+    pub fn statements(&mut self) -> Vec<String> {
+        self.pending.push_str(&self.line);
+        self.line.clear();
+
+        let mut ends = statement_ends(&self.pending);
+        if self.at_end && !is_blank(&self.pending) {
+            ends.push(self.pending.len());
+        }
+        let mut statements = Vec::with_capacity(ends.len());
+        let mut start = 0;
+        for end in ends {
+            let statement = self.pending[start..end].trim();
+            if !statement.is_empty() {
+                statements.push(statement.to_string());
+            }
+            start = (end + 1).min(self.pending.len());
         }
+        self.pending.drain(..start);
+        if is_blank(&self.pending) {
+            self.pending.clear();
+        }
+
+        statements
     }
+}
 
-    pub fn read(&mut self) {
-        io::stdin()
-            .read_line(&mut self.buffer)
-            .expect("[ERROR]failed to read from stdin");
-        if !self.buffer.is_empty() {
-            self.input_length = self.buffer.len();
+impl Default for InputBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The offsets of the `;` in `input` that are outside of quotes and comments
+fn statement_ends(input: &str) -> Vec<usize> {
+    let mut ends = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            ';' => ends.push(i),
+            '\'' | '"' | '`' | '[' => {
+                // a doubled closing quote reads as the quote closing and opening again
+                let close = if c == '[' { ']' } else { c };
+                while let Some((_, c)) = chars.next() {
+                    if c == close {
+                        break;
+                    }
+                    if c == '\\' && close == '\'' {
+                        chars.next();
+                    }
+                }
+            }
+            '-' if chars.next_if(|&(_, c)| c == '-').is_some() => {
+                chars.find(|&(_, c)| c == '\n');
+            }
+            '/' if chars.next_if(|&(_, c)| c == '*').is_some() => {
+                while let Some((_, c)) = chars.next() {
+                    if c == '*' && chars.next_if(|&(_, c)| c == '/').is_some() {
+                        break;
+                    }
+                }
+            }
+            _ => {}
         }
     }
 
-    pub fn get_input(&self) -> Option<&str> {
-        if self.buffer.is_empty() {
-            None
+    ends
+}
+
+/// Whether `input` holds nothing but whitespace and comments
+fn is_blank(input: &str) -> bool {
+    let mut rest = input.trim_start();
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("--") {
+            rest = comment.split_once('\n').map_or("", |(_, rest)| rest);
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            let Some((_, after)) = comment.split_once("*/") else {
+                return false;
+            };
+            rest = after;
         } else {
-            Some(self.buffer.trim_end())
+            return false;
         }
+        rest = rest.trim_start();
     }
+
+    true
 }
@@ -0,0 +1,458 @@
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum Token {
+    Keyword(Keyword),
+    Ident(String),
+    String(String),
+    Blob(Vec<u8>),
+    Integer(i64),
+    Float(f64),
+    Symbol(Symbol),
+    Eof,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum Keyword {
+    And,
+    As,
+    Asc,
+    Begin,
+    Between,
+    By,
+    Cast,
+    Collate,
+    Commit,
+    Create,
+    Delete,
+    Desc,
+    False,
+    From,
+    Group,
+    Having,
+    Index,
+    Insert,
+    Into,
+    Is,
+    Key,
+    Limit,
+    Not,
+    Null,
+    Offset,
+    On,
+    Or,
+    Order,
+    Primary,
+    Rollback,
+    Select,
+    Set,
+    Table,
+    Transaction,
+    True,
+    Unique,
+    Update,
+    Values,
+    Where,
+}
+
+const KEYWORDS: &[(&str, Keyword)] = &[
+    ("AND", Keyword::And),
+    ("AS", Keyword::As),
+    ("ASC", Keyword::Asc),
+    ("BEGIN", Keyword::Begin),
+    ("BETWEEN", Keyword::Between),
+    ("BY", Keyword::By),
+    ("CAST", Keyword::Cast),
+    ("COLLATE", Keyword::Collate),
+    ("COMMIT", Keyword::Commit),
+    ("CREATE", Keyword::Create),
+    ("DELETE", Keyword::Delete),
+    ("DESC", Keyword::Desc),
+    ("FALSE", Keyword::False),
+    ("FROM", Keyword::From),
+    ("GROUP", Keyword::Group),
+    ("HAVING", Keyword::Having),
+    ("INDEX", Keyword::Index),
+    ("INSERT", Keyword::Insert),
+    ("INTO", Keyword::Into),
+    ("IS", Keyword::Is),
+    ("KEY", Keyword::Key),
+    ("LIMIT", Keyword::Limit),
+    ("NOT", Keyword::Not),
+    ("NULL", Keyword::Null),
+    ("OFFSET", Keyword::Offset),
+    ("ON", Keyword::On),
+    ("OR", Keyword::Or),
+    ("ORDER", Keyword::Order),
+    ("PRIMARY", Keyword::Primary),
+    ("ROLLBACK", Keyword::Rollback),
+    ("SELECT", Keyword::Select),
+    ("SET", Keyword::Set),
+    ("TABLE", Keyword::Table),
+    ("TRANSACTION", Keyword::Transaction),
+    ("TRUE", Keyword::True),
+    ("UNIQUE", Keyword::Unique),
+    ("UPDATE", Keyword::Update),
+    ("VALUES", Keyword::Values),
+    ("WHERE", Keyword::Where),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum Symbol {
+    LeftParen,
+    RightParen,
+    Comma,
+    Semicolon,
+    Dot,
+    Star,
+    Plus,
+    Minus,
+    Slash,
+    Percent,
+    Concat,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    Question,
+}
+
+/// Where a token starts, both counted from 1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) struct Position {
+    pub(super) line: usize,
+    pub(super) column: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyntaxError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Splits SQL text into tokens on demand, so the parser can also take raw words
+#[derive(Clone)]
+pub(super) struct Lexer<'a> {
+    input: &'a str,
+    offset: usize,
+    position: Position,
+}
+
+impl<'a> Lexer<'a> {
+    pub(super) fn new(input: &'a str) -> Self {
+        Self {
+            input,
+            offset: 0,
+            position: Position { line: 1, column: 1 },
+        }
+    }
+
+    pub(super) fn next_token(&mut self) -> Result<(Token, Position), SyntaxError> {
+        self.skip_whitespace_and_comments()?;
+
+        let start = self.position;
+        let Some(c) = self.peek_char() else {
+            return Ok((Token::Eof, start));
+        };
+
+        let token = match c {
+            '\'' => Token::String(self.quoted('\'')?),
+            'x' | 'X' if self.peek_char_at(1) == Some('\'') => self.blob()?,
+            '"' | '`' => Token::Ident(self.quoted(c)?),
+            '[' => Token::Ident(self.quoted(']')?),
+            c if c.is_ascii_digit() => self.number()?,
+            '.' if self.peek_char_at(1).is_some_and(|c| c.is_ascii_digit()) => self.number()?,
+            c if c.is_alphabetic() || c == '_' => {
+                let word = self.take_while(|c| c.is_alphanumeric() || c == '_');
+                match KEYWORDS
+                    .iter()
+                    .find(|(keyword, _)| keyword.eq_ignore_ascii_case(word))
+                {
+                    Some(&(_, keyword)) => Token::Keyword(keyword),
+                    None => Token::Ident(word.into()),
+                }
+            }
+            _ => Token::Symbol(self.symbol()?),
+        };
+
+        Ok((token, start))
+    }
+
+    /// Take everything up to the next whitespace or `,`, or a quoted string
+    pub(super) fn next_word(&mut self) -> Result<(Option<String>, Position), SyntaxError> {
+        self.skip_whitespace_and_comments()?;
+
+        let start = self.position;
+        if self.peek_char() == Some('\'') {
+            return Ok((Some(self.quoted('\'')?), start));
+        }
+
+        let word = self.take_while(|c| !c.is_whitespace() && c != ',' && c != ';');
+        if word.is_empty() {
+            Ok((None, start))
+        } else {
+            Ok((Some(word.into()), start))
+        }
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), SyntaxError> {
+        loop {
+            self.take_while(char::is_whitespace);
+
+            if self.input[self.offset..].starts_with("--") {
+                self.take_while(|c| c != '\n');
+            } else if self.input[self.offset..].starts_with("/*") {
+                let start = self.position;
+                self.bump();
+                self.bump();
+                while !self.input[self.offset..].starts_with("*/") {
+                    if self.bump().is_none() {
+                        return Err(start.error("unterminated comment"));
+                    }
+                }
+                self.bump();
+                self.bump();
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    /// A string or identifier between quotes. Doubling the closing quote
+    /// escapes it, and strings also take backslash escapes.
+    fn quoted(&mut self, open: char) -> Result<String, SyntaxError> {
+        let start = self.position;
+        let close = if open == '[' { ']' } else { open };
+        self.bump();
+
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                None => {
+                    let what = if open == '\'' { "string" } else { "identifier" };
+                    return Err(start.error(format!("unterminated {what}").as_str()));
+                }
+                Some(c) if c == close => {
+                    if self.peek_char() == Some(close) && close != ']' {
+                        self.bump();
+                        s.push(close);
+                    } else {
+                        return Ok(s);
+                    }
+                }
+                Some('\\') if open == '\'' => {
+                    let position = self.position;
+                    let escaped = match self.bump() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('0') => '\0',
+                        Some(c @ ('\\' | '\'' | '"')) => c,
+                        Some(c) => {
+                            return Err(position.error(format!("unknown escape '\\{c}'").as_str()))
+                        }
+                        None => return Err(start.error("unterminated string")),
+                    };
+                    s.push(escaped);
+                }
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    /// A byte string written as `X'0A1B'`, two hexadecimal digits a byte
+    fn blob(&mut self) -> Result<Token, SyntaxError> {
+        let start = self.position;
+        self.bump();
+        let digits = self.quoted('\'')?;
+
+        let malformed = || start.error("malformed blob literal");
+        if digits.len() % 2 != 0 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(malformed());
+        }
+        (0..digits.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| malformed()))
+            .collect::<Result<_, _>>()
+            .map(Token::Blob)
+    }
+
+    fn number(&mut self) -> Result<Token, SyntaxError> {
+        let start = self.position;
+        let begin = self.offset;
+        let mut is_float = false;
+
+        self.take_while(|c| c.is_ascii_digit());
+        if self.peek_char() == Some('.') {
+            is_float = true;
+            self.bump();
+            self.take_while(|c| c.is_ascii_digit());
+        }
+        if matches!(self.peek_char(), Some('e' | 'E')) {
+            let sign = matches!(self.peek_char_at(1), Some('+' | '-')) as usize;
+            if self
+                .peek_char_at(1 + sign)
+                .is_some_and(|c| c.is_ascii_digit())
+            {
+                is_float = true;
+                for _ in 0..=sign {
+                    self.bump();
+                }
+                self.take_while(|c| c.is_ascii_digit());
+            }
+        }
+        if self
+            .peek_char()
+            .is_some_and(|c| c.is_alphanumeric() || c == '_')
+        {
+            return Err(self.position.error("malformed number"));
+        }
+
+        let text = &self.input[begin..self.offset];
+        if is_float {
+            text.parse()
+                .map(Token::Float)
+                .map_err(|_| start.error("malformed number"))
+        } else {
+            text.parse()
+                .map(Token::Integer)
+                .map_err(|_| start.error(format!("'{text}' is too large for an integer").as_str()))
+        }
+    }
+
+    fn symbol(&mut self) -> Result<Symbol, SyntaxError> {
+        let start = self.position;
+        let c = self.bump().unwrap();
+        let next = self.peek_char();
+
+        let (symbol, two_chars) = match (c, next) {
+            ('(', _) => (Symbol::LeftParen, false),
+            (')', _) => (Symbol::RightParen, false),
+            (',', _) => (Symbol::Comma, false),
+            (';', _) => (Symbol::Semicolon, false),
+            ('.', _) => (Symbol::Dot, false),
+            ('*', _) => (Symbol::Star, false),
+            ('+', _) => (Symbol::Plus, false),
+            ('-', _) => (Symbol::Minus, false),
+            ('/', _) => (Symbol::Slash, false),
+            ('%', _) => (Symbol::Percent, false),
+            ('|', Some('|')) => (Symbol::Concat, true),
+            ('=', Some('=')) => (Symbol::Eq, true),
+            ('=', _) => (Symbol::Eq, false),
+            ('!', Some('=')) => (Symbol::NotEq, true),
+            ('<', Some('>')) => (Symbol::NotEq, true),
+            ('<', Some('=')) => (Symbol::LtEq, true),
+            ('<', _) => (Symbol::Lt, false),
+            ('>', Some('=')) => (Symbol::GtEq, true),
+            ('>', _) => (Symbol::Gt, false),
+            ('?', _) => (Symbol::Question, false),
+            _ => return Err(start.error(format!("unexpected character '{c}'").as_str())),
+        };
+        if two_chars {
+            self.bump();
+        }
+
+        Ok(symbol)
+    }
+
+    fn peek_char(&self) -> Option<char> {
+        self.input[self.offset..].chars().next()
+    }
+
+    fn peek_char_at(&self, n: usize) -> Option<char> {
+        self.input[self.offset..].chars().nth(n)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek_char()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+
+        Some(c)
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> &'a str {
+        let begin = self.offset;
+        while self.peek_char().is_some_and(&predicate) {
+            self.bump();
+        }
+
+        &self.input[begin..self.offset]
+    }
+}
+
+impl Position {
+    pub(super) fn error(self, message: &str) -> SyntaxError {
+        SyntaxError {
+            message: message.into(),
+            line: self.line,
+            column: self.column,
+        }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "syntax error at line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Keyword(keyword) => write!(f, "{keyword}"),
+            Token::Ident(name) => write!(f, "'{name}'"),
+            Token::String(s) => write!(f, "string '{s}'"),
+            Token::Blob(bytes) => write!(f, "blob of {} bytes", bytes.len()),
+            Token::Integer(n) => write!(f, "'{n}'"),
+            Token::Float(n) => write!(f, "'{n:?}'"),
+            Token::Symbol(symbol) => write!(f, "'{symbol}'"),
+            Token::Eof => write!(f, "end of input"),
+        }
+    }
+}
+
+impl fmt::Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, _) = KEYWORDS.iter().find(|(_, k)| k == self).unwrap();
+        write!(f, "{name}")
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Symbol::LeftParen => "(",
+            Symbol::RightParen => ")",
+            Symbol::Comma => ",",
+            Symbol::Semicolon => ";",
+            Symbol::Dot => ".",
+            Symbol::Star => "*",
+            Symbol::Plus => "+",
+            Symbol::Minus => "-",
+            Symbol::Slash => "/",
+            Symbol::Percent => "%",
+            Symbol::Concat => "||",
+            Symbol::Eq => "=",
+            Symbol::NotEq => "<>",
+            Symbol::Lt => "<",
+            Symbol::LtEq => "<=",
+            Symbol::Gt => ">",
+            Symbol::GtEq => ">=",
+            Symbol::Question => "?",
+        };
+        write!(f, "{s}")
+    }
+}
@@ -1,31 +1,102 @@
-use crate::backend::Table;
+use crate::backend::{Database, Table, DEFAULT_TABLE};
+use crate::Error;
 
 pub enum MetaCommand {
     Exit,
-    BTree,
+    BTree(Option<String>),
+    Tables,
+    Schema(Option<String>),
     Constants,
+    FreeList,
+    Cache(Option<usize>),
+    Checkpoint,
     Error(String),
 }
 
+/// What the shell does once a metacommand ran
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    /// Close the database and leave
+    Exit,
+}
+
 impl MetaCommand {
-    pub fn process(input: &str, table: &mut Table) {
+    pub fn process(input: &str, database: &mut Database) -> Result<Flow, Error> {
         let words: Vec<&str> = input.split_ascii_whitespace().collect();
         let result = match words[0] {
             ".exit" => Self::Exit,
-            ".btree" => Self::BTree,
+            ".btree" => Self::BTree(words.get(1).map(|&name| name.into())),
+            ".tables" => Self::Tables,
+            ".schema" => Self::Schema(words.get(1).map(|&name| name.into())),
             ".constants" => Self::Constants,
+            ".freelist" => Self::FreeList,
+            ".cache" => match words.get(1).map(|n| n.parse()) {
+                None => Self::Cache(None),
+                Some(Ok(capacity)) => Self::Cache(Some(capacity)),
+                Some(Err(_)) => Self::Error(format!("can't parse '{}' to a page count", words[1])),
+            },
+            ".checkpoint" => Self::Checkpoint,
             _ => Self::Error(format!("unknown metacommand: '{}'", words[0])),
         };
 
         match &result {
-            MetaCommand::Exit => {
-                table.close();
-                println!("exitting...");
-                std::process::exit(0);
+            MetaCommand::Exit => return Ok(Flow::Exit),
+            MetaCommand::BTree(name) => {
+                let name = name.as_deref().unwrap_or(DEFAULT_TABLE);
+                // an index shows its own tree
+                let root_page_num = database
+                    .schema(name)
+                    .map(|schema| schema.root_page_num)
+                    .or_else(|| {
+                        (database.schemas().iter().flat_map(|schema| &schema.indexes))
+                            .find(|index| index.name.eq_ignore_ascii_case(name))
+                            .map(|index| index.root_page_num)
+                    });
+                match root_page_num {
+                    Some(root_page_num) => database.pager.print(root_page_num, 0)?,
+                    None => return Err(Error::Invalid(format!("no such table: '{name}'"))),
+                }
+            }
+            MetaCommand::Tables => {
+                let names: Vec<&str> = database
+                    .schemas()
+                    .iter()
+                    .map(|schema| schema.name.as_str())
+                    .collect();
+                println!("{}", names.join(" "));
+            }
+            MetaCommand::Schema(name) => {
+                for schema in database.schemas() {
+                    if name
+                        .as_deref()
+                        .is_none_or(|name| schema.name.eq_ignore_ascii_case(name))
+                    {
+                        println!("{};", schema.sql());
+                        for index in &schema.indexes {
+                            println!("{};", index.sql());
+                        }
+                    }
+                }
             }
-            MetaCommand::BTree => table.pager.print(table.root_page_num, 0),
             MetaCommand::Constants => Table::print_constants(),
-            MetaCommand::Error(s) => crate::error(s),
+            MetaCommand::FreeList => {
+                println!("free pages: {}", database.pager.get_num_free_pages()?)
+            }
+            MetaCommand::Cache(capacity) => {
+                if let Some(capacity) = capacity {
+                    database.pager.set_cache_capacity(*capacity)?;
+                }
+                let stats = database.cache_stats();
+                println!(
+                    "cache: {}/{} pages, {} hits, {} misses",
+                    stats.cached, stats.capacity, stats.hits, stats.misses
+                );
+            }
+            MetaCommand::Checkpoint => database.checkpoint()?,
+            MetaCommand::Error(s) => return Err(Error::Invalid(s.clone())),
         }
+
+        Ok(Flow::Continue)
     }
 }
@@ -1,7 +1,20 @@
+mod aggregate;
+mod ast;
+mod connection;
+mod eval;
 mod input_buffer;
+mod lexer;
 mod meta_command;
+mod parser;
+mod query;
 mod statement;
 
+pub type Connection = connection::Connection;
+pub type Flow = meta_command::Flow;
 pub type InputBuffer = input_buffer::InputBuffer;
 pub type MetaCommand = meta_command::MetaCommand;
+pub type PreparedStatement = connection::PreparedStatement;
+pub type Rows<'a> = query::Rows<'a>;
 pub type Statement = statement::Statement;
+
+pub(crate) use statement::{index_from_sql, schema_from_sql};
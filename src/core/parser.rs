@@ -0,0 +1,598 @@
+use super::ast::{
+    BinaryOp, ColumnDef, CreateIndex, CreateTable, Delete, Expr, Insert, Literal, OrderBy, Select,
+    SelectColumn, Statement, UnaryOp, Update,
+};
+use super::lexer::{Keyword, Lexer, Position, Symbol, SyntaxError, Token};
+
+/// Parse a single statement, optionally terminated by `;`
+pub(super) fn parse(input: &str) -> Result<Statement, SyntaxError> {
+    let mut parser = Parser::new(input);
+    let statement = parser.statement()?;
+    parser.eat_symbol(Symbol::Semicolon)?;
+
+    let (token, position) = parser.next()?;
+    if token != Token::Eof {
+        return Err(unexpected(&token, position, "end of statement"));
+    }
+
+    Ok(statement)
+}
+
+/// Recursive-descent parser with one token of lookahead
+struct Parser<'a> {
+    lexer: Lexer<'a>,
+    // the lookahead, and the lexer as it was before reading it
+    peeked: Option<(Token, Position, Lexer<'a>)>,
+    /// How many `?` placeholders have been read so far
+    num_params: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            lexer: Lexer::new(input),
+            peeked: None,
+            num_params: 0,
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement, SyntaxError> {
+        let (token, position) = self.next()?;
+        match token {
+            Token::Keyword(Keyword::Select) => self.select(),
+            Token::Keyword(Keyword::Insert) => self.insert(),
+            Token::Keyword(Keyword::Update) => self.update(),
+            Token::Keyword(Keyword::Delete) => self.delete(),
+            Token::Keyword(Keyword::Create) => self.create(),
+            Token::Keyword(Keyword::Begin) => {
+                self.eat_keyword(Keyword::Transaction)?;
+                Ok(Statement::Begin)
+            }
+            Token::Keyword(Keyword::Commit) => {
+                self.eat_keyword(Keyword::Transaction)?;
+                Ok(Statement::Commit)
+            }
+            Token::Keyword(Keyword::Rollback) => {
+                self.eat_keyword(Keyword::Transaction)?;
+                Ok(Statement::Rollback)
+            }
+            token => Err(unexpected(&token, position, "a statement")),
+        }
+    }
+
+    fn select(&mut self) -> Result<Statement, SyntaxError> {
+        // a bare 'select' reads the whole table
+        let mut columns = vec![SelectColumn::All];
+        if !matches!(self.peek()?, Token::Eof | Token::Symbol(Symbol::Semicolon)) {
+            columns = self.comma_separated(|parser| {
+                if parser.eat_symbol(Symbol::Star)? {
+                    Ok(SelectColumn::All)
+                } else {
+                    Ok(SelectColumn::Expr(parser.expr()?))
+                }
+            })?;
+        }
+
+        let from = if self.eat_keyword(Keyword::From)? {
+            Some(self.ident("a table name")?)
+        } else {
+            None
+        };
+
+        let filter = self.filter()?;
+
+        let mut group_by = Vec::new();
+        let mut having = None;
+        if self.eat_keyword(Keyword::Group)? {
+            self.expect_keyword(Keyword::By)?;
+            group_by = self.comma_separated(Self::expr)?;
+            if self.eat_keyword(Keyword::Having)? {
+                having = Some(self.expr()?);
+            }
+        }
+
+        let mut order_by = Vec::new();
+        if self.eat_keyword(Keyword::Order)? {
+            self.expect_keyword(Keyword::By)?;
+            order_by = self.comma_separated(|parser| {
+                let expr = parser.expr()?;
+                let descending = if parser.eat_keyword(Keyword::Desc)? {
+                    true
+                } else {
+                    parser.eat_keyword(Keyword::Asc)?;
+                    false
+                };
+                Ok(OrderBy { expr, descending })
+            })?;
+        }
+
+        let (mut limit, mut offset) = (None, None);
+        if self.eat_keyword(Keyword::Limit)? {
+            limit = Some(self.expr()?);
+            if self.eat_keyword(Keyword::Offset)? {
+                offset = Some(self.expr()?);
+            }
+        }
+
+        Ok(Statement::Select(Select {
+            columns,
+            from,
+            filter,
+            group_by,
+            having,
+            order_by,
+            limit,
+            offset,
+        }))
+    }
+
+    fn insert(&mut self) -> Result<Statement, SyntaxError> {
+        if !self.eat_keyword(Keyword::Into)? {
+            // insert <id> <username> <email>
+            let row = vec![
+                Expr::Literal(Literal::String(self.word()?)),
+                Expr::Literal(Literal::String(self.word()?)),
+                Expr::Literal(Literal::String(self.word()?)),
+            ];
+
+            return Ok(Statement::Insert(Insert {
+                table: None,
+                columns: None,
+                rows: vec![row],
+            }));
+        }
+
+        let table = self.ident("a table name")?;
+        let columns = if self.eat_symbol(Symbol::LeftParen)? {
+            let columns = self.comma_separated(|parser| parser.ident("a column name"))?;
+            self.expect_symbol(Symbol::RightParen)?;
+            Some(columns)
+        } else {
+            None
+        };
+
+        self.expect_keyword(Keyword::Values)?;
+        let rows = self.comma_separated(|parser| {
+            parser.expect_symbol(Symbol::LeftParen)?;
+            let row = parser.comma_separated(Self::expr)?;
+            parser.expect_symbol(Symbol::RightParen)?;
+            Ok(row)
+        })?;
+
+        Ok(Statement::Insert(Insert {
+            table: Some(table),
+            columns,
+            rows,
+        }))
+    }
+
+    fn update(&mut self) -> Result<Statement, SyntaxError> {
+        if let Token::Integer(id) = *self.peek()? {
+            // update <id> set <column> = <value>, ...
+            self.next()?;
+            self.expect_keyword(Keyword::Set)?;
+            let assignments = self.comma_separated(|parser| {
+                let column = parser.ident("a column name")?;
+                parser.expect_symbol(Symbol::Eq)?;
+                Ok((column, Expr::Literal(Literal::String(parser.word()?))))
+            })?;
+
+            return Ok(Statement::Update(Update {
+                table: None,
+                assignments,
+                filter: Some(Expr::Binary {
+                    left: Box::new(Expr::Column("id".into())),
+                    op: BinaryOp::Eq,
+                    right: Box::new(Expr::Literal(Literal::Integer(id))),
+                }),
+            }));
+        }
+
+        let table = self.ident("a table name")?;
+        self.expect_keyword(Keyword::Set)?;
+        let assignments = self.comma_separated(|parser| {
+            let column = parser.ident("a column name")?;
+            parser.expect_symbol(Symbol::Eq)?;
+            Ok((column, parser.expr()?))
+        })?;
+
+        Ok(Statement::Update(Update {
+            table: Some(table),
+            assignments,
+            filter: self.filter()?,
+        }))
+    }
+
+    fn delete(&mut self) -> Result<Statement, SyntaxError> {
+        let table = if self.eat_keyword(Keyword::From)? {
+            Some(self.ident("a table name")?)
+        } else {
+            None
+        };
+
+        Ok(Statement::Delete(Delete {
+            table,
+            filter: self.filter()?,
+        }))
+    }
+
+    fn create(&mut self) -> Result<Statement, SyntaxError> {
+        let unique = self.eat_keyword(Keyword::Unique)?;
+        if unique || *self.peek()? == Token::Keyword(Keyword::Index) {
+            return self.create_index(unique);
+        }
+
+        self.create_table()
+    }
+
+    fn create_index(&mut self, unique: bool) -> Result<Statement, SyntaxError> {
+        self.expect_keyword(Keyword::Index)?;
+        let name = self.ident("an index name")?;
+        self.expect_keyword(Keyword::On)?;
+        let table = self.ident("a table name")?;
+
+        self.expect_symbol(Symbol::LeftParen)?;
+        let columns = self.comma_separated(|parser| parser.ident("a column name"))?;
+        self.expect_symbol(Symbol::RightParen)?;
+
+        Ok(Statement::CreateIndex(CreateIndex {
+            name,
+            table,
+            columns,
+            unique,
+        }))
+    }
+
+    fn create_table(&mut self) -> Result<Statement, SyntaxError> {
+        self.expect_keyword(Keyword::Table)?;
+        let name = self.ident("a table name")?;
+
+        self.expect_symbol(Symbol::LeftParen)?;
+        let mut columns = vec![self.column_def()?];
+        let mut primary_key = None;
+        while self.eat_symbol(Symbol::Comma)? {
+            // a key of several columns follows their definitions
+            if self.eat_keyword(Keyword::Primary)? {
+                self.expect_keyword(Keyword::Key)?;
+                self.expect_symbol(Symbol::LeftParen)?;
+                primary_key = Some(self.comma_separated(|parser| parser.ident("a column name"))?);
+                self.expect_symbol(Symbol::RightParen)?;
+                break;
+            }
+            columns.push(self.column_def()?);
+        }
+        self.expect_symbol(Symbol::RightParen)?;
+
+        Ok(Statement::CreateTable(CreateTable {
+            name,
+            columns,
+            primary_key,
+        }))
+    }
+
+    fn column_def(&mut self) -> Result<ColumnDef, SyntaxError> {
+        let name = self.ident("a column name")?;
+
+        let type_name = if let Token::Ident(_) = self.peek()? {
+            Some(self.type_name()?)
+        } else {
+            None
+        };
+
+        let mut column = ColumnDef {
+            name,
+            type_name,
+            primary_key: false,
+            collation: None,
+            not_null: false,
+        };
+        loop {
+            if self.eat_keyword(Keyword::Primary)? {
+                self.expect_keyword(Keyword::Key)?;
+                column.primary_key = true;
+            } else if self.eat_keyword(Keyword::Collate)? {
+                column.collation = Some(self.ident("a collation name")?);
+            } else if self.eat_keyword(Keyword::Not)? {
+                self.expect_keyword(Keyword::Null)?;
+                column.not_null = true;
+            } else {
+                return Ok(column);
+            }
+        }
+    }
+
+    fn type_name(&mut self) -> Result<String, SyntaxError> {
+        let mut type_name = self.ident("a type name")?;
+        if self.eat_symbol(Symbol::LeftParen)? {
+            // the size of a type like 'varchar(32)'
+            let (token, position) = self.next()?;
+            let Token::Integer(size) = token else {
+                return Err(unexpected(&token, position, "a size"));
+            };
+            self.expect_symbol(Symbol::RightParen)?;
+            type_name = format!("{type_name}({size})");
+        }
+
+        Ok(type_name)
+    }
+
+    fn filter(&mut self) -> Result<Option<Expr>, SyntaxError> {
+        if self.eat_keyword(Keyword::Where)? {
+            Ok(Some(self.expr()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Expressions, from the loosest binding operator to the tightest
+
+    fn expr(&mut self) -> Result<Expr, SyntaxError> {
+        self.or()
+    }
+
+    fn or(&mut self) -> Result<Expr, SyntaxError> {
+        let mut left = self.and()?;
+        while self.eat_keyword(Keyword::Or)? {
+            left = binary(left, BinaryOp::Or, self.and()?);
+        }
+
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, SyntaxError> {
+        let mut left = self.not()?;
+        while self.eat_keyword(Keyword::And)? {
+            left = binary(left, BinaryOp::And, self.not()?);
+        }
+
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expr, SyntaxError> {
+        if self.eat_keyword(Keyword::Not)? {
+            return Ok(Expr::Unary {
+                op: UnaryOp::Not,
+                expr: Box::new(self.not()?),
+            });
+        }
+
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, SyntaxError> {
+        let left = self.additive()?;
+
+        let op = match self.peek()? {
+            Token::Symbol(Symbol::Eq) => BinaryOp::Eq,
+            Token::Symbol(Symbol::NotEq) => BinaryOp::NotEq,
+            Token::Symbol(Symbol::Lt) => BinaryOp::Lt,
+            Token::Symbol(Symbol::LtEq) => BinaryOp::LtEq,
+            Token::Symbol(Symbol::Gt) => BinaryOp::Gt,
+            Token::Symbol(Symbol::GtEq) => BinaryOp::GtEq,
+            Token::Keyword(Keyword::Is) => {
+                self.next()?;
+                let negated = self.eat_keyword(Keyword::Not)?;
+                self.expect_keyword(Keyword::Null)?;
+
+                return Ok(Expr::IsNull {
+                    expr: Box::new(left),
+                    negated,
+                });
+            }
+            Token::Keyword(Keyword::Not | Keyword::Between) => {
+                let negated = self.eat_keyword(Keyword::Not)?;
+                self.expect_keyword(Keyword::Between)?;
+                // the bounds stop short of AND, which separates them
+                let low = self.additive()?;
+                self.expect_keyword(Keyword::And)?;
+                let high = self.additive()?;
+
+                return Ok(Expr::Between {
+                    expr: Box::new(left),
+                    negated,
+                    low: Box::new(low),
+                    high: Box::new(high),
+                });
+            }
+            _ => return Ok(left),
+        };
+        self.next()?;
+
+        Ok(binary(left, op, self.additive()?))
+    }
+
+    fn additive(&mut self) -> Result<Expr, SyntaxError> {
+        let mut left = self.multiplicative()?;
+        loop {
+            let op = match self.peek()? {
+                Token::Symbol(Symbol::Plus) => BinaryOp::Add,
+                Token::Symbol(Symbol::Minus) => BinaryOp::Sub,
+                _ => return Ok(left),
+            };
+            self.next()?;
+            left = binary(left, op, self.multiplicative()?);
+        }
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr, SyntaxError> {
+        let mut left = self.concat()?;
+        loop {
+            let op = match self.peek()? {
+                Token::Symbol(Symbol::Star) => BinaryOp::Mul,
+                Token::Symbol(Symbol::Slash) => BinaryOp::Div,
+                Token::Symbol(Symbol::Percent) => BinaryOp::Rem,
+                _ => return Ok(left),
+            };
+            self.next()?;
+            left = binary(left, op, self.concat()?);
+        }
+    }
+
+    fn concat(&mut self) -> Result<Expr, SyntaxError> {
+        let mut left = self.unary()?;
+        while self.eat_symbol(Symbol::Concat)? {
+            left = binary(left, BinaryOp::Concat, self.unary()?);
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, SyntaxError> {
+        let op = match self.peek()? {
+            Token::Symbol(Symbol::Plus) => UnaryOp::Plus,
+            Token::Symbol(Symbol::Minus) => UnaryOp::Minus,
+            _ => return self.primary(),
+        };
+        self.next()?;
+
+        Ok(Expr::Unary {
+            op,
+            expr: Box::new(self.unary()?),
+        })
+    }
+
+    fn primary(&mut self) -> Result<Expr, SyntaxError> {
+        let (token, position) = self.next()?;
+        match token {
+            Token::Integer(n) => Ok(Expr::Literal(Literal::Integer(n))),
+            Token::Float(n) => Ok(Expr::Literal(Literal::Float(n))),
+            Token::String(s) => Ok(Expr::Literal(Literal::String(s))),
+            Token::Blob(bytes) => Ok(Expr::Literal(Literal::Blob(bytes))),
+            Token::Keyword(Keyword::Null) => Ok(Expr::Literal(Literal::Null)),
+            Token::Keyword(Keyword::True) => Ok(Expr::Literal(Literal::Boolean(true))),
+            Token::Keyword(Keyword::False) => Ok(Expr::Literal(Literal::Boolean(false))),
+            Token::Keyword(Keyword::Cast) => {
+                self.expect_symbol(Symbol::LeftParen)?;
+                let expr = self.expr()?;
+                self.expect_keyword(Keyword::As)?;
+                let type_name = self.type_name()?;
+                self.expect_symbol(Symbol::RightParen)?;
+                Ok(Expr::Cast {
+                    expr: Box::new(expr),
+                    type_name,
+                })
+            }
+            Token::Ident(name) if *self.peek()? == Token::Symbol(Symbol::LeftParen) => {
+                self.next()?;
+                let args = if self.eat_symbol(Symbol::Star)? {
+                    vec![Expr::Wildcard]
+                } else if *self.peek()? == Token::Symbol(Symbol::RightParen) {
+                    vec![]
+                } else {
+                    self.comma_separated(Self::expr)?
+                };
+                self.expect_symbol(Symbol::RightParen)?;
+                Ok(Expr::Function { name, args })
+            }
+            Token::Ident(name) => Ok(Expr::Column(name)),
+            Token::Symbol(Symbol::Question) => {
+                self.num_params += 1;
+                Ok(Expr::Parameter(self.num_params - 1))
+            }
+            Token::Symbol(Symbol::LeftParen) => {
+                let expr = self.expr()?;
+                self.expect_symbol(Symbol::RightParen)?;
+                Ok(expr)
+            }
+            token => Err(unexpected(&token, position, "an expression")),
+        }
+    }
+
+    // Token helpers
+
+    fn peek(&mut self) -> Result<&Token, SyntaxError> {
+        if self.peeked.is_none() {
+            let before = self.lexer.clone();
+            let (token, position) = self.lexer.next_token()?;
+            self.peeked = Some((token, position, before));
+        }
+
+        Ok(&self.peeked.as_ref().unwrap().0)
+    }
+
+    fn next(&mut self) -> Result<(Token, Position), SyntaxError> {
+        match self.peeked.take() {
+            Some((token, position, _)) => Ok((token, position)),
+            None => self.lexer.next_token(),
+        }
+    }
+
+    /// A value of the short forms of insert and update, which are
+    /// taken verbatim up to the next whitespace or `,` unless quoted
+    fn word(&mut self) -> Result<String, SyntaxError> {
+        if let Some((_, _, before)) = self.peeked.take() {
+            self.lexer = before;
+        }
+
+        match self.lexer.next_word()? {
+            (Some(word), _) => Ok(word),
+            (None, position) => {
+                let (token, _) = self.lexer.next_token()?;
+                Err(unexpected(&token, position, "a value"))
+            }
+        }
+    }
+
+    fn ident(&mut self, what: &str) -> Result<String, SyntaxError> {
+        match self.next()? {
+            (Token::Ident(name), _) => Ok(name),
+            (token, position) => Err(unexpected(&token, position, what)),
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: Keyword) -> Result<bool, SyntaxError> {
+        if *self.peek()? == Token::Keyword(keyword) {
+            self.next()?;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    fn eat_symbol(&mut self, symbol: Symbol) -> Result<bool, SyntaxError> {
+        if *self.peek()? == Token::Symbol(symbol) {
+            self.next()?;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    fn expect_keyword(&mut self, keyword: Keyword) -> Result<(), SyntaxError> {
+        match self.next()? {
+            (Token::Keyword(k), _) if k == keyword => Ok(()),
+            (token, position) => Err(unexpected(&token, position, &keyword.to_string())),
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: Symbol) -> Result<(), SyntaxError> {
+        match self.next()? {
+            (Token::Symbol(s), _) if s == symbol => Ok(()),
+            (token, position) => Err(unexpected(&token, position, &format!("'{symbol}'"))),
+        }
+    }
+
+    fn comma_separated<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, SyntaxError>,
+    ) -> Result<Vec<T>, SyntaxError> {
+        let mut items = vec![item(self)?];
+        while self.eat_symbol(Symbol::Comma)? {
+            items.push(item(self)?);
+        }
+
+        Ok(items)
+    }
+}
+
+fn binary(left: Expr, op: BinaryOp, right: Expr) -> Expr {
+    Expr::Binary {
+        left: Box::new(left),
+        op,
+        right: Box::new(right),
+    }
+}
+
+fn unexpected(token: &Token, position: Position, expected: &str) -> SyntaxError {
+    position.error(format!("expected {expected}, found {token}").as_str())
+}
@@ -0,0 +1,408 @@
+use std::cmp::Ordering;
+use std::ops::Bound;
+use std::vec;
+
+use super::aggregate::{self, Aggregate, Grouper};
+use super::ast::{self, Expr, Literal, SelectColumn};
+use super::eval;
+use super::statement::{self, Access};
+use crate::backend::row::Row;
+use crate::backend::{Collation, Database, Scan, Schema, Sorter, Value, DEFAULT_TABLE};
+use crate::Error;
+
+/// A SELECT checked against the schema of its table, ready to produce its rows
+pub(super) struct Query {
+    select: ast::Select,
+    /// Whether the query reads a table, one of constants working out its columns once
+    reads_table: bool,
+    schema: Schema,
+    /// The expressions of the columns of the result
+    columns: Vec<Expr>,
+    /// Whether the result starts with the key, which 'select *' prints apart
+    pub(super) keyed: bool,
+    /// The terms of ORDER BY, each with whether it sorts in descending order
+    order_by: Vec<(Expr, bool)>,
+    aggregates: Vec<Aggregate>,
+    grouped: bool,
+    access: Access,
+    limit: usize,
+    offset: usize,
+}
+
+impl Query {
+    pub(super) fn new(select: &ast::Select, database: &Database) -> Result<Self, Error> {
+        // a bare 'select' reads the tutorial's table, while expressions alone need none
+        let reads_table = select.from.is_some() || select.columns == [SelectColumn::All];
+        let schema = if reads_table {
+            let name = select.from.as_deref().unwrap_or(DEFAULT_TABLE);
+            statement::lookup(database, name)?.clone()
+        } else {
+            let exprs = (select.columns.iter())
+                .filter_map(|column| match column {
+                    SelectColumn::All => None,
+                    SelectColumn::Expr(expr) => Some(expr),
+                })
+                .chain(&select.filter)
+                .chain(&select.group_by)
+                .chain(&select.having)
+                .chain(select.order_by.iter().map(|term| &term.expr));
+            if let Some(name) = exprs.into_iter().find_map(column_name) {
+                return Err(Error::Invalid(format!("no such column: '{name}'")));
+            }
+            Schema {
+                name: String::new(),
+                columns: Vec::new(),
+                primary_key: Vec::new(),
+                root_page_num: 0,
+                indexes: Vec::new(),
+            }
+        };
+
+        // 'select *' alone prints rows the tutorial's way, as 'key: other columns'
+        let key_name = statement::key_name(&schema);
+        let keyed = select.columns == [SelectColumn::All] && key_name.is_some();
+        let mut columns = Vec::new();
+        if let (true, Some(key_name)) = (keyed, key_name) {
+            columns.push(Expr::Column(key_name.into()));
+        }
+        for column in &select.columns {
+            match column {
+                SelectColumn::All if !reads_table => {
+                    return Err(Error::Invalid("no tables specified".into()))
+                }
+                SelectColumn::All => columns.extend(
+                    (schema.columns.iter().enumerate())
+                        .filter(|&(i, _)| !keyed || schema.primary_key != [i])
+                        .map(|(_, column)| Expr::Column(column.name.clone())),
+                ),
+                SelectColumn::Expr(expr) => columns.push(expr.clone()),
+            }
+        }
+
+        let mut order_by = Vec::with_capacity(select.order_by.len());
+        for term in &select.order_by {
+            let expr = match &term.expr {
+                // 'order by 2' sorts on the second column of the result
+                Expr::Literal(Literal::Integer(n)) => usize::try_from(*n)
+                    .ok()
+                    .and_then(|n| columns.get(n.checked_sub(1)?))
+                    .ok_or_else(|| Error::Invalid(format!("ORDER BY term out of range: {n}")))?
+                    .clone(),
+                expr => expr.clone(),
+            };
+            order_by.push((expr, term.descending));
+        }
+        let sort_keys = order_by.iter().map(|(expr, _)| expr);
+
+        // the result of a grouped query is made of its groups rather than of rows
+        let aggregates = aggregate::collect(
+            columns
+                .iter()
+                .chain(&select.having)
+                .chain(sort_keys.clone()),
+        )?;
+        let grouped =
+            !select.group_by.is_empty() || select.having.is_some() || !aggregates.is_empty();
+        if grouped {
+            for term in &select.group_by {
+                eval::check(term, &schema)?;
+            }
+            for expr in columns.iter().chain(&select.having).chain(sort_keys) {
+                aggregate::check_grouped(expr, &select.group_by, &schema)?;
+            }
+        } else {
+            for expr in columns.iter().chain(sort_keys) {
+                eval::check(expr, &schema)?;
+            }
+        }
+
+        let count = |expr: &Option<Expr>, clause: &str| -> Result<Option<usize>, Error> {
+            let Some(expr) = expr else {
+                return Ok(None);
+            };
+            match statement::constant(expr)? {
+                Value::Integer(n) if n >= 0 => Ok(Some(n as usize)),
+                value => Err(Error::Invalid(format!(
+                    "{clause} must be a non-negative integer, not '{value}'"
+                ))),
+            }
+        };
+        let limit = count(&select.limit, "LIMIT")?.unwrap_or(usize::MAX);
+        let offset = count(&select.offset, "OFFSET")?.unwrap_or(0);
+
+        let access = match &select.filter {
+            Some(filter) => {
+                eval::check(filter, &schema)?;
+                statement::access(filter, &schema)
+            }
+            None => Access::Keys((Bound::Unbounded, Bound::Unbounded)),
+        };
+
+        Ok(Self {
+            select: select.clone(),
+            reads_table,
+            schema,
+            columns,
+            keyed,
+            order_by,
+            aggregates,
+            grouped,
+            access,
+            limit,
+            offset,
+        })
+    }
+
+    /// Hand every row of the result to `emit`, in order
+    pub(super) fn run(
+        self,
+        database: &mut Database,
+        mut emit: impl FnMut(&[Value]),
+    ) -> Result<(), Error> {
+        if !self.streams() {
+            return self.gather(database, emit);
+        }
+
+        for row in self.rows(database)? {
+            emit(&row?.0);
+        }
+
+        Ok(())
+    }
+
+    /// The rows of the result. Those of a query reading its table in the order
+    /// it wants are read as the iterator moves on, those of any other are
+    /// worked out in full first.
+    pub(super) fn rows(self, database: &mut Database) -> Result<Rows<'_>, Error> {
+        if !self.streams() {
+            let mut rows = Vec::new();
+            self.gather(database, |values| rows.push(Row(values.to_vec())))?;
+            return Ok(Rows(Source::Gathered(rows.into_iter())));
+        }
+
+        let table = database.table(&self.schema.name).unwrap();
+        let scan = match &self.access {
+            Access::Keys(range) => table.into_scan(range.clone())?,
+            Access::Index(index, range) => table.into_index_scan(index, range.clone())?,
+        };
+
+        Ok(Rows(Source::Scan {
+            scan,
+            query: Box::new(self),
+        }))
+    }
+
+    /// Whether the rows come straight out of the scan, in the order it reads them
+    fn streams(&self) -> bool {
+        let in_scan_order = match (&self.order_by[..], &self.access) {
+            ([], _) => true,
+            ([(expr @ Expr::Column(_), false)], Access::Keys(_)) => statement::is_column(
+                expr,
+                &statement::key_columns(&self.schema)[0],
+                self.schema.has_row_id(),
+            ),
+            _ => false,
+        };
+
+        in_scan_order && !self.grouped && self.reads_table
+    }
+
+    /// The next row of the result `scan` reads, past the offset and up to the limit
+    fn next(&mut self, scan: &mut Scan) -> Result<Option<Row>, Error> {
+        while self.limit > 0 {
+            let Some((key, row)) = scan.next()? else {
+                return Ok(None);
+            };
+            if !statement::matches(self.select.filter.as_ref(), &self.schema, &key, &row)? {
+                continue;
+            }
+            let values = project(&self.columns, &self.schema, &key, &row)?;
+            if self.offset > 0 {
+                self.offset -= 1;
+                continue;
+            }
+            self.limit -= 1;
+            return Ok(Some(Row(values)));
+        }
+
+        Ok(None)
+    }
+
+    /// Hand every row of a sorted or grouped result to `emit`, in order
+    fn gather(&self, database: &mut Database, mut emit: impl FnMut(&[Value])) -> Result<(), Error> {
+        let (select, schema, columns) = (&self.select, &self.schema, &self.columns);
+        let (mut limit, mut offset) = (self.limit, self.offset);
+        if limit == 0 {
+            return Ok(());
+        }
+
+        // the access only narrows the scan, the whole condition is tested on each row
+        let matches =
+            |key: &[Value], row: &Row| statement::matches(select.filter.as_ref(), schema, key, row);
+        // pass on a row of the result past the offset, telling whether more are wanted
+        let mut emit = |values: &[Value]| {
+            if offset > 0 {
+                offset -= 1;
+                return true;
+            }
+            emit(values);
+            limit -= 1;
+            limit > 0
+        };
+
+        // ORDER BY sorts records of its values followed by the columns of the result
+        let sort_keys: Vec<Expr> = self.order_by.iter().map(|(expr, _)| expr.clone()).collect();
+        let num_sort_keys = sort_keys.len();
+        let collations: Vec<Collation> = (sort_keys.iter())
+            .map(|expr| eval::collation(expr, schema).unwrap_or_default())
+            .collect();
+        let order_by = &self.order_by;
+        let compare = move |a: &[Value], b: &[Value]| {
+            for (i, (_, descending)) in order_by.iter().enumerate() {
+                let ordering = eval::sort_order(&a[i], &b[i], collations[i]);
+                let ordering = if *descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                };
+                if ordering.is_ne() {
+                    return ordering;
+                }
+            }
+            Ordering::Equal
+        };
+
+        let mut sorter = (num_sort_keys > 0).then(|| Sorter::new(&database.pager, compare));
+        let mut produce = |record: Vec<Value>| match &mut sorter {
+            Some(sorter) => {
+                sorter.push(record)?;
+                Ok(true)
+            }
+            None => Ok(emit(&record)),
+        };
+
+        if self.grouped {
+            let aggregates = &self.aggregates;
+            let mut group = |keys: &[Value], results: &[Value]| {
+                let eval = |expr: &Expr| {
+                    aggregate::eval_grouped(
+                        expr,
+                        schema,
+                        &select.group_by,
+                        keys,
+                        aggregates,
+                        results,
+                    )
+                };
+                if let Some(having) = &select.having {
+                    if !eval::is_true(&eval(having)?) {
+                        return Ok(true);
+                    }
+                }
+                let record = (sort_keys.iter().chain(columns))
+                    .map(eval)
+                    .collect::<Result<Vec<_>, Error>>()?;
+                produce(record)
+            };
+
+            if self.reads_table
+                && select.filter.is_none()
+                && select.group_by.is_empty()
+                && aggregates.iter().all(Aggregate::is_count_all)
+            {
+                // the leaves know how many rows they hold
+                let count = database.table(&schema.name).unwrap().count()?;
+                let count = Value::Integer(count as i64);
+                group(&[], &vec![count; aggregates.len()])?;
+            } else {
+                let collations = (select.group_by.iter())
+                    .map(|expr| eval::collation(expr, schema).unwrap_or_default())
+                    .collect();
+                let mut grouper = Grouper::new(&database.pager, aggregates, collations);
+                self.scan(database, |key, row| {
+                    if matches(key, &row)? {
+                        let keys = project(&select.group_by, schema, key, &row)?;
+                        let values = (aggregates.iter())
+                            .map(|aggregate| aggregate.arg(schema, key, &row))
+                            .collect::<Result<_, _>>()?;
+                        grouper.push(keys, values)?;
+                    }
+                    Ok(true)
+                })?;
+                grouper.finish(group)?;
+            }
+        } else {
+            self.scan(database, |key, row| {
+                if !matches(key, &row)? {
+                    return Ok(true);
+                }
+                let mut record = project(&sort_keys, schema, key, &row)?;
+                record.extend(project(columns, schema, key, &row)?);
+                produce(record)
+            })?;
+        }
+
+        match sorter {
+            Some(sorter) => sorter.finish(|record| Ok(emit(&record[num_sort_keys..]))),
+            None => Ok(()),
+        }
+    }
+
+    /// Hand the rows the query reads to `visit` until it returns false: those its
+    /// access picks out of its table, or a single row of no columns without a table
+    fn scan(
+        &self,
+        database: &mut Database,
+        mut visit: impl FnMut(&[Value], Row) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
+        if !self.reads_table {
+            return visit(&[], Row(Vec::new())).map(|_| ());
+        }
+
+        let mut table = database.table(&self.schema.name).unwrap();
+        statement::scan(&mut table, &self.access, |_, key, row| visit(key, row))
+    }
+}
+
+/// The first column `expr` names, if it names any
+fn column_name(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::Column(name) => Some(name),
+        expr => expr.children().into_iter().find_map(column_name),
+    }
+}
+
+/// The values of `exprs` for the row keyed by `key`
+fn project(exprs: &[Expr], schema: &Schema, key: &[Value], row: &Row) -> Result<Vec<Value>, Error> {
+    exprs
+        .iter()
+        .map(|expr| eval::eval(expr, schema, key, row))
+        .collect()
+}
+
+/// The rows of the result of a query
+pub struct Rows<'a>(Source<'a>);
+
+enum Source<'a> {
+    /// Rows read off the table as they are asked for
+    Scan { scan: Scan<'a>, query: Box<Query> },
+    /// Rows a sort or a grouping had to work out first
+    Gathered(vec::IntoIter<Row>),
+}
+
+/// A scan that fails ends the rows. It changes nothing, so there is nothing to roll back.
+impl Iterator for Rows<'_> {
+    type Item = Result<Row, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = match &mut self.0 {
+            Source::Scan { scan, query } => query.next(scan).transpose(),
+            Source::Gathered(rows) => return rows.next().map(Ok),
+        };
+        if let Some(Err(_)) = next {
+            self.0 = Source::Gathered(Vec::new().into_iter());
+        }
+
+        next
+    }
+}
@@ -1,55 +1,669 @@
-use crate::backend::{row, Row, Table};
+use std::ops::Bound;
+
+use super::ast::{self, BinaryOp, Expr, Literal, UnaryOp};
+use super::query::Query;
+use super::{eval, parser};
+use crate::backend::row::Row;
+use crate::backend::{
+    Collation, Column, ColumnType, Database, Index, KeyRange, Pager, Schema, Table, Value,
+    CATALOG_NAME, DEFAULT_TABLE,
+};
+use crate::Error;
 
 pub enum Statement {
-    Insert(Row),
-    Select,
+    Parsed(Box<ast::Statement>),
+    /// A statement that doesn't parse, and why
     Error(String),
 }
 
 impl Statement {
     pub fn prepare(input: &str) -> Statement {
-        let mut args: Vec<&str> = input.split_ascii_whitespace().collect();
+        match parser::parse(input) {
+            Ok(statement) => Statement::Parsed(Box::new(statement)),
+            Err(e) => Statement::Error(e.to_string()),
+        }
+    }
+
+    pub fn execute(&self, database: &mut Database) -> Result<(), Error> {
+        let result = match self {
+            Statement::Parsed(statement) => {
+                bind(statement, &[]).and_then(|statement| match &statement {
+                    ast::Statement::Select(select) => {
+                        let query = Query::new(select, database)?;
+                        let keyed = query.keyed;
+                        query.run(database, |values| print_row(values, keyed))
+                    }
+                    ast::Statement::Update(_) => {
+                        let num_updated = Self::run(&statement, database)?;
+                        println!("{num_updated} row(s) updated");
+                        Ok(())
+                    }
+                    ast::Statement::Delete(_) => {
+                        let num_deleted = Self::run(&statement, database)?;
+                        println!("{num_deleted} row(s) deleted");
+                        Ok(())
+                    }
+                    statement => Self::run(statement, database).map(|_| ()),
+                })
+            }
+            Statement::Error(s) => Err(Error::Syntax(s.clone())),
+        };
+
+        finish(database, result)
+    }
+
+    /// Check a statement against the schema of the tables it names, then run it,
+    /// returning how many rows it inserted, updated or deleted. The rows of a query go nowhere.
+    pub(super) fn run(statement: &ast::Statement, database: &mut Database) -> Result<usize, Error> {
+        match statement {
+            ast::Statement::CreateTable(create) => {
+                database.create_table(schema(create)?)?;
+                Ok(0)
+            }
+            ast::Statement::CreateIndex(create) => {
+                let schema = lookup(database, writable(Some(&create.table))?)?;
+                if let Some(column) =
+                    (create.columns.iter()).find(|&column| schema.column_index(column).is_none())
+                {
+                    return Err(Error::Invalid(format!("no such column: '{column}'")));
+                }
+                database.create_index(index(create))?;
+                Ok(0)
+            }
+            ast::Statement::Select(select) => {
+                Query::new(select, database)?.run(database, |_| {})?;
+                Ok(0)
+            }
+            ast::Statement::Insert(insert) => Self::insert(insert, database),
+            ast::Statement::Update(update) => Self::update(update, database),
+            ast::Statement::Delete(delete) => {
+                let name = writable(delete.table.as_deref())?;
+                let schema = lookup(database, name)?.clone();
+                let keys = matching_keys(database, &schema, delete.filter.as_ref())?;
+                let mut table = database.table(name).unwrap();
+                let mut num_deleted = 0;
+                for key in keys {
+                    num_deleted += table.delete(&key)?;
+                }
+                Ok(num_deleted)
+            }
+            ast::Statement::Begin => database.begin().map(|_| 0),
+            ast::Statement::Commit => database.commit().map(|_| 0),
+            ast::Statement::Rollback => database.rollback().map(|_| 0),
+        }
+    }
+
+    fn insert(insert: &ast::Insert, database: &mut Database) -> Result<usize, Error> {
+        let name = writable(insert.table.as_deref())?;
+        let schema = lookup(database, name)?;
+        let columns: Vec<usize> = match &insert.columns {
+            Some(names) => names
+                .iter()
+                .map(|name| {
+                    schema
+                        .column_index(name)
+                        .ok_or_else(|| Error::Invalid(format!("no such column: '{name}'")))
+                })
+                .collect::<Result<_, _>>()?,
+            None => (0..schema.columns.len()).collect(),
+        };
 
-        let command = args.remove(0);
+        // work out every row before inserting any; one clashing with a row
+        // already there fails the statement, which then inserts none of them
+        let mut rows = Vec::with_capacity(insert.rows.len());
+        for exprs in &insert.rows {
+            if exprs.len() != columns.len() {
+                return Err(Error::Invalid(format!(
+                    "{} values for {} columns",
+                    exprs.len(),
+                    columns.len()
+                )));
+            }
 
-        match command {
-            "insert" => Self::parse(&args),
-            "select" => Statement::Select,
-            _ => Statement::Error(format!("unknown command: '{command}'")),
+            let mut values: Vec<Option<Value>> = vec![None; schema.columns.len()];
+            for (&column, expr) in columns.iter().zip(exprs) {
+                values[column] = Some(coerce(schema, column, constant(expr)?)?);
+            }
+            // the columns left out are NULL
+            let values = values
+                .into_iter()
+                .zip(schema.columns.iter().enumerate())
+                .map(|(value, (i, column))| match value {
+                    Some(value) => Ok(value),
+                    None if schema.primary_key.contains(&i) || column.not_null => Err(
+                        Error::Constraint(format!("a value for '{}' is required", column.name)),
+                    ),
+                    None => Ok(Value::Null),
+                })
+                .collect::<Result<_, Error>>()?;
+            rows.push(Row(values));
         }
+
+        let mut table = database.table(name).unwrap();
+        for row in &rows {
+            table.insert(row)?;
+        }
+
+        Ok(rows.len())
     }
 
-    pub fn execute(&self, table: &mut Table) {
-        match &self {
-            Statement::Insert(args) => table.insert(args),
-            Statement::Select => table.select(),
-            Statement::Error(s) => eprintln!("[ERROR]{s}"),
+    fn update(update: &ast::Update, database: &mut Database) -> Result<usize, Error> {
+        let name = writable(update.table.as_deref())?;
+        let schema = lookup(database, name)?.clone();
+
+        let mut assignments = Vec::with_capacity(update.assignments.len());
+        for (name, expr) in &update.assignments {
+            let Some(index) = schema.column_index(name) else {
+                return Err(Error::Invalid(format!("no such column: '{name}'")));
+            };
+            if schema.primary_key.contains(&index) {
+                return Err(Error::Invalid(format!("can't update column '{name}'")));
+            }
+            assignments.push((index, coerce(&schema, index, constant(expr)?)?));
         }
+
+        let keys = matching_keys(database, &schema, update.filter.as_ref())?;
+        let mut table = database.table(name).unwrap();
+        let mut num_updated = 0;
+        for key in keys {
+            num_updated += table.update(&key, &assignments)?;
+        }
+
+        Ok(num_updated)
     }
+}
 
-    fn parse(args: &Vec<&str>) -> Statement {
-        if args.len() < 3 {
-            return Statement::Error("syntax error".into());
+/// End the statement `result` comes from, committing it unless a transaction
+/// holds it. A statement that fails changes nothing, and once the storage
+/// fails, in the statement or in its commit, every change not committed is
+/// thrown away.
+pub(super) fn finish<T>(database: &mut Database, result: Result<T, Error>) -> Result<T, Error> {
+    let result = match result {
+        Ok(value) => database.autocommit().map(|_| value),
+        Err(e) if e.is_storage() => Err(e),
+        Err(e) => database.undo_statement().and(Err(e)),
+    };
+
+    match result {
+        Err(e) if e.is_storage() => {
+            database.abort()?;
+            Err(e)
         }
+        result => result,
+    }
+}
 
-        let Some(id) = args[0].parse().ok() else {
-            return Statement::Error(
-                format!("can't parse '{}' to u32", args[0])
-            );
+/// The statement with the values of `params` in place of its `?` placeholders
+pub(super) fn bind(statement: &ast::Statement, params: &[Value]) -> Result<ast::Statement, Error> {
+    let mut statement = statement.clone();
+    let mut num_params = 0;
+    let mut exprs = statement.exprs_mut();
+    while let Some(expr) = exprs.pop() {
+        let Expr::Parameter(n) = *expr else {
+            exprs.extend(expr.children_mut());
+            continue;
         };
+        num_params = num_params.max(n + 1);
+        if let Some(value) = params.get(n) {
+            *expr = Expr::Literal(literal(value));
+        }
+    }
+    if num_params != params.len() {
+        return Err(Error::Invalid(format!(
+            "{} values for {num_params} parameters",
+            params.len()
+        )));
+    }
+
+    Ok(statement)
+}
+
+/// The literal standing for `value`
+fn literal(value: &Value) -> Literal {
+    match value {
+        Value::Null => Literal::Null,
+        Value::Boolean(b) => Literal::Boolean(*b),
+        Value::Integer(n) => Literal::Integer(*n),
+        Value::Real(n) => Literal::Float(*n),
+        Value::Text(s) => Literal::String(s.clone()),
+        Value::Blob(bytes) => Literal::Blob(bytes.clone()),
+    }
+}
+
+/// Whether `filter`, if there is one, holds for the row keyed by `key`
+pub(super) fn matches(
+    filter: Option<&Expr>,
+    schema: &Schema,
+    key: &[Value],
+    row: &Row,
+) -> Result<bool, Error> {
+    match filter {
+        Some(filter) => eval::eval(filter, schema, key, row).map(|value| eval::is_true(&value)),
+        None => Ok(true),
+    }
+}
+
+/// The keys of the rows `filter` holds for, gathered before any row changes
+fn matching_keys(
+    database: &mut Database,
+    schema: &Schema,
+    filter: Option<&Expr>,
+) -> Result<Vec<Vec<Value>>, Error> {
+    let access = match filter {
+        Some(filter) => {
+            eval::check(filter, schema)?;
+            access(filter, schema)
+        }
+        None => Access::Keys((Bound::Unbounded, Bound::Unbounded)),
+    };
+
+    let mut keys = Vec::new();
+    let mut table = database.table(&schema.name).unwrap();
+    scan(&mut table, &access, |_, key, row| {
+        if matches(filter, schema, key, &row)? {
+            keys.push(key.to_vec());
+        }
+        Ok(true)
+    })?;
+
+    Ok(keys)
+}
+
+/// Print a row of a result, `keyed` ones as their key followed by the other columns
+fn print_row(values: &[Value], keyed: bool) {
+    let values: Vec<String> = values.iter().map(Value::to_string).collect();
+    if keyed {
+        println!("{}: {}", values[0], values[1..].join(" "));
+    } else {
+        println!("{}", values.join(" "));
+    }
+}
+
+/// Build a table definition from its CREATE TABLE statement
+pub(crate) fn schema_from_sql(sql: &str) -> Result<Schema, Error> {
+    match parser::parse(sql) {
+        Ok(ast::Statement::CreateTable(create)) => schema(&create),
+        Ok(_) => Err(Error::Invalid("not a CREATE TABLE statement".into())),
+        Err(e) => Err(Error::Syntax(e.to_string())),
+    }
+}
+
+/// Build an index definition from its CREATE INDEX statement
+pub(crate) fn index_from_sql(sql: &str) -> Result<Index, Error> {
+    match parser::parse(sql) {
+        Ok(ast::Statement::CreateIndex(create)) => Ok(index(&create)),
+        Ok(_) => Err(Error::Invalid("not a CREATE INDEX statement".into())),
+        Err(e) => Err(Error::Syntax(e.to_string())),
+    }
+}
+
+fn index(create: &ast::CreateIndex) -> Index {
+    Index {
+        name: create.name.clone(),
+        table: create.table.clone(),
+        columns: create.columns.clone(),
+        unique: create.unique,
+        root_page_num: 0,
+    }
+}
+
+fn schema(create: &ast::CreateTable) -> Result<Schema, Error> {
+    let mut columns: Vec<Column> = Vec::with_capacity(create.columns.len());
+    let mut primary_key = Vec::new();
+    for def in &create.columns {
+        if columns
+            .iter()
+            .any(|c| c.name.eq_ignore_ascii_case(&def.name))
+        {
+            return Err(Error::Invalid(format!("duplicate column '{}'", def.name)));
+        }
+
+        let type_name = def.type_name.as_deref().unwrap_or("TEXT");
+        let Some(column_type) = ColumnType::from_name(type_name) else {
+            return Err(Error::Invalid(format!(
+                "unknown type '{}' for column '{}'",
+                type_name.to_ascii_uppercase(),
+                def.name
+            )));
+        };
+
+        let collation = match &def.collation {
+            Some(name) => Collation::from_name(name)
+                .ok_or_else(|| Error::Invalid(format!("no such collation sequence: {name}")))?,
+            None => Collation::Binary,
+        };
+
+        if def.primary_key {
+            primary_key.push(columns.len());
+        }
+        columns.push(Column {
+            name: def.name.clone(),
+            column_type,
+            collation,
+            not_null: def.not_null,
+        });
+    }
+
+    if primary_key.len() > 1 || !primary_key.is_empty() && create.primary_key.is_some() {
+        return Err(Error::Invalid(format!(
+            "table '{}' has more than one primary key",
+            create.name
+        )));
+    }
+    // the columns of a key of several columns are listed after every column
+    for name in create.primary_key.iter().flatten() {
+        let column = (columns.iter())
+            .position(|c| c.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| Error::Invalid(format!("no such column: '{name}'")))?;
+        if primary_key.contains(&column) {
+            return Err(Error::Invalid(format!(
+                "duplicate column '{name}' in primary key"
+            )));
+        }
+        primary_key.push(column);
+    }
+
+    Ok(Schema {
+        name: create.name.clone(),
+        columns,
+        primary_key,
+        root_page_num: 0,
+        indexes: Vec::new(),
+    })
+}
+
+pub(super) fn lookup<'a>(database: &'a Database, name: &str) -> Result<&'a Schema, Error> {
+    database
+        .schema(name)
+        .ok_or_else(|| Error::Invalid(format!("no such table: '{name}'")))
+}
+
+/// The table a statement changes, which may not be the catalog
+fn writable(name: Option<&str>) -> Result<&str, Error> {
+    let name = name.unwrap_or(DEFAULT_TABLE);
+    if name.eq_ignore_ascii_case(CATALOG_NAME) {
+        return Err(Error::Invalid(format!(
+            "table '{name}' may not be modified"
+        )));
+    }
 
-        if args[1].len() > row::MAX_USERNAME {
-            return Statement::Error(format!("'{}' is too long for username", args[1]));
+    Ok(name)
+}
+
+/// The value of a literal, which may carry a sign or be cast
+pub(super) fn constant(expr: &Expr) -> Result<Value, Error> {
+    match expr {
+        Expr::Literal(Literal::String(s)) => Ok(Value::Text(s.clone())),
+        Expr::Literal(Literal::Blob(bytes)) => Ok(Value::Blob(bytes.clone())),
+        Expr::Literal(Literal::Integer(n)) => Ok(Value::Integer(*n)),
+        Expr::Literal(Literal::Float(n)) => Ok(Value::Real(*n)),
+        Expr::Literal(Literal::Boolean(b)) => Ok(Value::Boolean(*b)),
+        Expr::Literal(Literal::Null) => Ok(Value::Null),
+        Expr::Unary {
+            op: UnaryOp::Minus,
+            expr,
+        } => match constant(expr)? {
+            Value::Integer(n) => Ok(Value::Integer(-n)),
+            Value::Real(n) => Ok(Value::Real(-n)),
+            _ => Err(Error::Invalid(format!("'-{expr}' is not a constant"))),
+        },
+        Expr::Unary {
+            op: UnaryOp::Plus,
+            expr,
+        } if matches!(
+            **expr,
+            Expr::Literal(Literal::Integer(_) | Literal::Float(_))
+        ) =>
+        {
+            constant(expr)
         }
+        Expr::Cast { expr, type_name } => {
+            eval::cast(constant(expr)?, eval::column_type(type_name)?)
+        }
+        expr => Err(Error::Invalid(format!("'{expr}' is not a constant"))),
+    }
+}
 
-        if args[2].len() > row::MAX_EMAIL {
-            return Statement::Error(format!("'{}' is too long for email", args[2]));
+/// Convert a value to the type of the column of `schema` at `column`, which must take it
+fn coerce(schema: &Schema, column: usize, value: Value) -> Result<Value, Error> {
+    let definition = &schema.columns[column];
+    // unlike CAST, a column doesn't drop the fraction of a real
+    if let (ColumnType::Integer, Some(Value::Real(n))) =
+        (definition.column_type, eval::number(&value))
+    {
+        if n.fract() != 0.0 {
+            return Err(Error::Invalid(format!(
+                "can't store '{value}' in integer column '{}'",
+                definition.name
+            )));
         }
+    }
+    let value = eval::cast(value, definition.column_type)?;
+    match (&value, definition.column_type) {
+        (Value::Null, _) if definition.not_null || schema.primary_key.contains(&column) => Err(
+            Error::Constraint(format!("'{}' may not be NULL", definition.name)),
+        ),
+        (
+            Value::Text(s),
+            ColumnType::Text {
+                max_length: Some(max),
+            },
+        ) if s.len() > max => Err(Error::Constraint(format!(
+            "'{s}' is too long for {}",
+            definition.name
+        ))),
+        _ => Ok(value),
+    }
+}
 
-        Statement::Insert(Row {
-            id,
-            username: args[1].into(),
-            email: args[2].into(),
-        })
+/// The name the key of a table goes by in conditions, unless it is made of several columns
+pub(super) fn key_name(schema: &Schema) -> Option<&str> {
+    match schema.primary_key[..] {
+        [] => Some("rowid"),
+        [column] => Some(&schema.columns[column].name),
+        _ => None,
+    }
+}
+
+/// The columns keying the rows of a table, in key order: those of its primary
+/// key, or else the row id as an integer column of its own
+pub(super) fn key_columns(schema: &Schema) -> Vec<Column> {
+    if schema.primary_key.is_empty() {
+        return vec![Column {
+            name: "rowid".into(),
+            column_type: ColumnType::Integer,
+            collation: Collation::Binary,
+            not_null: true,
+        }];
+    }
+
+    (schema.primary_key.iter())
+        .map(|&column| schema.columns[column].clone())
+        .collect()
+}
+
+/// Whether `expr` names `column`, or the row id it stands for
+pub(super) fn is_column(expr: &Expr, column: &Column, row_id: bool) -> bool {
+    matches!(expr, Expr::Column(name)
+        if name.eq_ignore_ascii_case(&column.name) || row_id && name.eq_ignore_ascii_case("rowid"))
+}
+
+/// The values between a start and an end bound
+type Range<T> = (Bound<T>, Bound<T>);
+
+/// How the rows a query reads are reached
+pub(super) enum Access {
+    /// The rows whose key falls in a range
+    Keys(KeyRange),
+    /// The rows whose values of the columns of an index fall in a range
+    Index(Index, KeyRange),
+}
+
+/// Read the rows of a condition through the range of keys it narrows the rows to,
+/// or else through an index it narrows to equal values, or else to a range
+pub(super) fn access(filter: &Expr, schema: &Schema) -> Access {
+    let range = prefix_range(filter, &key_columns(schema), schema.has_row_id());
+    if range != (Bound::Unbounded, Bound::Unbounded) {
+        return Access::Keys(range);
+    }
+
+    let mut best = None;
+    for index in &schema.indexes {
+        let columns: Vec<Column> = (schema.index_columns(index).into_iter())
+            .map(|column| schema.columns[column].clone())
+            .collect();
+        let range = prefix_range(filter, &columns, false);
+        match &range {
+            (Bound::Included(a), Bound::Included(b)) if a == b => {
+                return Access::Index(index.clone(), range)
+            }
+            (Bound::Unbounded, Bound::Unbounded) => {}
+            _ => {
+                best.get_or_insert_with(|| Access::Index(index.clone(), range));
+            }
+        }
+    }
+
+    best.unwrap_or(Access::Keys((Bound::Unbounded, Bound::Unbounded)))
+}
+
+/// Hand `visit` every row `access` reaches
+pub(super) fn scan(
+    table: &mut Table,
+    access: &Access,
+    visit: impl FnMut(&mut Pager, &[Value], Row) -> Result<bool, Error>,
+) -> Result<(), Error> {
+    match access {
+        Access::Keys(range) => table.scan(range.clone(), visit),
+        Access::Index(index, range) => table.scan_index(index, range.clone(), visit),
+    }
+}
+
+/// The keys a condition narrows `columns`, the leading columns of a key, to:
+/// those equal to values for the first of them, then in a range for the next one
+fn prefix_range(filter: &Expr, columns: &[Column], row_id: bool) -> KeyRange {
+    let mut prefix = Vec::new();
+    for column in columns {
+        match bounds(filter, &|expr| column_range(expr, column, row_id)) {
+            (Bound::Included(a), Bound::Included(b)) if a == b => prefix.push(a),
+            (start, end) => {
+                // an open end still keeps to the keys starting with the prefix
+                let extend = |bound: Bound<Value>| match bound {
+                    Bound::Included(value) => Bound::Included([&prefix[..], &[value]].concat()),
+                    Bound::Excluded(value) => Bound::Excluded([&prefix[..], &[value]].concat()),
+                    Bound::Unbounded if prefix.is_empty() => Bound::Unbounded,
+                    Bound::Unbounded => Bound::Included(prefix.clone()),
+                };
+                return (extend(start), extend(end));
+            }
+        }
+    }
+
+    (Bound::Included(prefix.clone()), Bound::Included(prefix))
+}
+
+/// The values of `column` matched by a comparison of it with a constant of its type.
+/// Text that reads as a number compares as one with integers, so a constant of the
+/// other type can't bound the order of the key.
+fn column_range(filter: &Expr, column: &Column, row_id: bool) -> Option<Range<Value>> {
+    let is_column = |expr: &Expr| is_column(expr, column, row_id);
+    let value = |expr: &Expr| {
+        let value = constant(expr).ok()?;
+        match (column.column_type, &value) {
+            (ColumnType::Integer | ColumnType::Real, Value::Integer(_) | Value::Real(_))
+            | (ColumnType::Text { .. }, Value::Text(_))
+            | (ColumnType::Blob, Value::Blob(_))
+            | (ColumnType::Boolean, Value::Boolean(_)) => Some(value),
+            _ => None,
+        }
+    };
+
+    match filter {
+        Expr::Binary { left, op, right } if is_column(left) => comparison_range(*op, value(right)?),
+        Expr::Binary { left, op, right } if is_column(right) => {
+            comparison_range(flip(*op), value(left)?)
+        }
+        Expr::Between {
+            expr,
+            negated: false,
+            low,
+            high,
+        } if is_column(expr) => Some((Bound::Included(value(low)?), Bound::Included(value(high)?))),
+        _ => None,
+    }
+}
+/// The range a comparison `column op n` puts the column in
+fn comparison_range<T: Clone>(op: BinaryOp, n: T) -> Option<Range<T>> {
+    match op {
+        BinaryOp::Eq => Some((Bound::Included(n.clone()), Bound::Included(n))),
+        BinaryOp::Lt => Some((Bound::Unbounded, Bound::Excluded(n))),
+        BinaryOp::LtEq => Some((Bound::Unbounded, Bound::Included(n))),
+        BinaryOp::Gt => Some((Bound::Excluded(n), Bound::Unbounded)),
+        BinaryOp::GtEq => Some((Bound::Included(n), Bound::Unbounded)),
+        _ => None,
+    }
+}
+
+/// Read 'n < id' as 'id > n'
+fn flip(op: BinaryOp) -> BinaryOp {
+    match op {
+        BinaryOp::Lt => BinaryOp::Gt,
+        BinaryOp::LtEq => BinaryOp::GtEq,
+        BinaryOp::Gt => BinaryOp::Lt,
+        BinaryOp::GtEq => BinaryOp::LtEq,
+        op => op,
+    }
+}
+
+/// The range a condition can match, narrowed by every comparison `range` makes
+/// out of the conjunction it is. Conditions of any other shape leave the range open.
+fn bounds<T: Ord>(filter: &Expr, range: &dyn Fn(&Expr) -> Option<Range<T>>) -> Range<T> {
+    let Expr::Binary {
+        left,
+        op: BinaryOp::And,
+        right,
+    } = filter
+    else {
+        return range(filter).unwrap_or((Bound::Unbounded, Bound::Unbounded));
+    };
+
+    let (left_start, left_end) = bounds(left, range);
+    let (right_start, right_end) = bounds(right, range);
+
+    (
+        later_start(left_start, right_start),
+        earlier_end(left_end, right_end),
+    )
+}
+
+/// The narrower of two lower bounds
+fn later_start<T: Ord>(a: Bound<T>, b: Bound<T>) -> Bound<T> {
+    match (a, b) {
+        (Bound::Unbounded, bound) | (bound, Bound::Unbounded) => bound,
+        (Bound::Included(a), Bound::Included(b)) => Bound::Included(a.max(b)),
+        (Bound::Excluded(a), Bound::Excluded(b)) => Bound::Excluded(a.max(b)),
+        (Bound::Included(a), Bound::Excluded(b)) | (Bound::Excluded(b), Bound::Included(a)) => {
+            if b >= a {
+                Bound::Excluded(b)
+            } else {
+                Bound::Included(a)
+            }
+        }
+    }
+}
+
+/// The narrower of two upper bounds
+fn earlier_end<T: Ord>(a: Bound<T>, b: Bound<T>) -> Bound<T> {
+    match (a, b) {
+        (Bound::Unbounded, bound) | (bound, Bound::Unbounded) => bound,
+        (Bound::Included(a), Bound::Included(b)) => Bound::Included(a.min(b)),
+        (Bound::Excluded(a), Bound::Excluded(b)) => Bound::Excluded(a.min(b)),
+        (Bound::Included(a), Bound::Excluded(b)) | (Bound::Excluded(b), Bound::Included(a)) => {
+            if b <= a {
+                Bound::Excluded(b)
+            } else {
+                Bound::Included(a)
+            }
+        }
     }
 }
@@ -0,0 +1,59 @@
+use std::{error, fmt, io};
+
+/// Why opening a database, or running a statement or a metacommand, failed
+#[derive(Debug)]
+pub enum Error {
+    /// A file could not be opened, locked, read, written or synced
+    Io { message: String, source: io::Error },
+    /// The file is not a database this version can read
+    Unsupported(String),
+    /// The file holds something no database writes
+    Corrupt(String),
+    /// A page, or a child of a page, that isn't there
+    OutOfBounds(String),
+    /// The statement doesn't parse
+    Syntax(String),
+    /// The statement asks for something that can't be done, like reading a
+    /// table that doesn't exist or adding a number to a blob
+    Invalid(String),
+    /// A change would break a rule of a table, like its keys being unique
+    Constraint(String),
+}
+
+impl Error {
+    pub(crate) fn io(message: String, source: io::Error) -> Self {
+        Error::Io { message, source }
+    }
+
+    /// Whether the file failed the statement rather than the other way around,
+    /// leaving what the statement changed half done
+    pub(crate) fn is_storage(&self) -> bool {
+        matches!(
+            self,
+            Error::Io { .. } | Error::Corrupt(_) | Error::OutOfBounds(_)
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io { message, .. } => write!(f, "{message}"),
+            Error::Unsupported(message)
+            | Error::Corrupt(message)
+            | Error::OutOfBounds(message)
+            | Error::Syntax(message)
+            | Error::Invalid(message)
+            | Error::Constraint(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
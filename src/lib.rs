@@ -2,12 +2,13 @@ use std::io::{self, Write};
 
 pub mod backend;
 pub mod core;
+mod error;
 
-pub fn print_prompt() {
-    print!(">> ");
-    io::stdout().flush().expect("error: stdout flush");
-}
+pub use backend::{Row, Value};
+pub use core::{Connection, PreparedStatement, Rows};
+pub use error::Error;
 
-fn error(s: &str) {
-    eprintln!("[ERROR]{s}");
+pub fn print_prompt(prompt: &str) {
+    print!("{prompt}");
+    io::stdout().flush().expect("error: stdout flush");
 }
@@ -1,33 +1,117 @@
 use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, IsTerminal};
+use std::process;
 
-use resql::backend::Table;
-use resql::core::{InputBuffer, MetaCommand, Statement};
+use resql::backend::Database;
+use resql::core::{Flow, InputBuffer, MetaCommand, Statement};
+use resql::Error;
+
+const USAGE: &str = "usage: resql FILENAME [SCRIPT | -c SQL] [--interactive]";
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        println!("error: must supply a database filename.");
-        std::process::exit(0x0100);
+    let mut args = env::args().skip(1);
+    let mut positional = Vec::new();
+    let mut sql = None;
+    // a terminal gets prompts, a pipe doesn't unless asked to
+    let mut interactive = io::stdin().is_terminal();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-c" => sql = Some(args.next().unwrap_or_else(|| usage())),
+            "--interactive" => interactive = true,
+            _ => positional.push(arg),
+        }
     }
-    let mut table = Table::open(args[1].as_str());
+    let Some(filename) = positional.first() else {
+        println!("error: must supply a database filename.");
+        process::exit(0x0100);
+    };
 
-    loop {
-        resql::print_prompt();
-        let mut input_buffer = InputBuffer::new();
-        input_buffer.read();
+    // a script or statements given with -c stop at the first error
+    let (mut input_buffer, batch) = match (positional.get(1), sql) {
+        _ if positional.len() > 2 => usage(),
+        (None, None) => (InputBuffer::new(), false),
+        (Some(script), None) => {
+            let file = File::open(script).unwrap_or_else(|e| {
+                eprintln!("[ERROR]can't open '{script}'. {e}");
+                process::exit(1);
+            });
+            (
+                InputBuffer::from_reader(Box::new(BufReader::new(file))),
+                true,
+            )
+        }
+        (None, Some(sql)) => (
+            InputBuffer::from_reader(Box::new(Cursor::new(sql.into_bytes()))),
+            true,
+        ),
+        (Some(_), Some(_)) => usage(),
+    };
 
-        let Some(input) = input_buffer.get_input() else {
-            continue;
+    let mut database = Database::open(filename).unwrap_or_else(|e| {
+        eprintln!("[ERROR]{e}");
+        process::exit(1);
+    });
+
+    loop {
+        if interactive && !batch {
+            resql::print_prompt(input_buffer.prompt());
+        }
+        // input that can't be read ends there, failing a script like any error
+        let more = match input_buffer.read() {
+            Ok(more) => more,
+            Err(e) => {
+                report(Err(e), &mut database, batch);
+                false
+            }
         };
-        if input.is_empty() {
-            // guarantee that unwrap() is ok
+
+        if let Some(command) = input_buffer.meta_command() {
+            match MetaCommand::process(command, &mut database) {
+                Ok(Flow::Exit) => {
+                    println!("exiting...");
+                    exit(&mut database, 0);
+                }
+                result => report(result.map(|_| ()), &mut database, batch),
+            }
             continue;
         }
+        for statement in input_buffer.statements() {
+            let result = Statement::prepare(&statement).execute(&mut database);
+            report(result, &mut database, batch);
+        }
 
-        match input.chars().next().unwrap() {
-            // never empty, thus ok
-            '.' => MetaCommand::process(input, &mut table),
-            _ => Statement::prepare(input).execute(&mut table),
+        if !more {
+            // the end of the input is as good as '.exit'
+            if interactive && !batch {
+                println!();
+            }
+            exit(&mut database, 0);
         }
     }
 }
+
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    process::exit(2);
+}
+
+/// Print the error of a command, ending a script there
+fn report(result: Result<(), Error>, database: &mut Database, batch: bool) {
+    if let Err(e) = result {
+        eprintln!("[ERROR]{e}");
+        if batch {
+            exit(database, 1);
+        }
+    }
+}
+
+/// Close the database, keeping everything committed so far, and exit with `code`
+fn exit(database: &mut Database, code: i32) -> ! {
+    if let Err(e) = database.close() {
+        eprintln!("[ERROR]{e}");
+        process::exit(1);
+    }
+
+    process::exit(code);
+}
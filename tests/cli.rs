@@ -1,11 +1,16 @@
-use std::io::{ErrorKind, Write};
+use std::io::{ErrorKind, Read, Write};
 use std::path::Path;
 use std::process::{Command, Stdio};
 
+use resql::backend::{Database, Fault, FaultyStorage, MemoryStorage, Storage};
+use resql::core::{MetaCommand, Statement};
+use resql::{Connection, Error, Row, Value};
+
 fn run(commands: Vec<String>, filename: &str) -> (Vec<String>, Vec<String>) {
     let mut child = Command::new("cargo")
         .arg("run")
         .arg(filename)
+        .arg("--interactive")
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
@@ -22,7 +27,7 @@ fn run(commands: Vec<String>, filename: &str) -> (Vec<String>, Vec<String>) {
     let handle = std::thread::spawn(move || {
         commands.iter().for_each(|cmd| {
             stdin
-                .write_all(&[cmd.as_bytes(), b"\n"].concat())
+                .write_all(&line(cmd))
                 .expect("failed to write to stdin");
         });
     });
@@ -31,12 +36,10 @@ fn run(commands: Vec<String>, filename: &str) -> (Vec<String>, Vec<String>) {
     let output = child.wait_with_output().expect("failed to read stdout");
     handle.join().unwrap();
     let out: Vec<String> = String::from_utf8_lossy(&output.stdout)
-        .to_string()
         .split("\n")
         .map(String::from)
         .collect();
     let err: Vec<String> = String::from_utf8_lossy(&output.stderr)
-        .to_string()
         .split("\n")
         .map(String::from)
         .collect();
@@ -44,23 +47,107 @@ fn run(commands: Vec<String>, filename: &str) -> (Vec<String>, Vec<String>) {
     (out, err)
 }
 
+/// Feed `commands` to a fresh process and kill it as soon as the first `num_done` have run
+fn run_and_kill(commands: Vec<String>, num_done: usize, filename: &str) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_resql"))
+        .arg(filename)
+        .arg("--interactive")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("failed to spawn child process");
+
+    let mut stdin = child.stdin.take().expect("failed to get stdin");
+    let handle = std::thread::spawn(move || {
+        for cmd in commands {
+            if stdin.write_all(&line(&cmd)).is_err() {
+                // killed
+                break;
+            }
+        }
+        // closing stdin early would let the process run into end of input
+        stdin
+    });
+
+    // the prompt is printed again once a command has run
+    let mut stdout = child.stdout.take().expect("failed to get stdout");
+    let mut out = Vec::new();
+    let mut buf = [0u8; 4096];
+    while out.windows(3).filter(|w| w == b">> ").count() <= num_done {
+        let n = stdout.read(&mut buf).expect("failed to read stdout");
+        assert!(n > 0, "the process exited before being killed");
+        out.extend_from_slice(&buf[..n]);
+    }
+
+    child.kill().expect("failed to kill child process");
+    child.wait().unwrap();
+    drop(handle.join().unwrap());
+}
+
+/// Run the shell on `filename` with `args` and `input` piped in, returning its exit code, stdout and stderr
+fn run_batch(filename: &str, args: &[&str], input: &str) -> (Option<i32>, String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_resql"))
+        .arg(filename)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to spawn child process");
+
+    // dropping stdin ends the input
+    (child.stdin.take().expect("failed to get stdin"))
+        .write_all(input.as_bytes())
+        .expect("failed to write to stdin");
+    let output = child.wait_with_output().expect("failed to read stdout");
+
+    (
+        output.status.code(),
+        String::from_utf8_lossy(&output.stdout).into(),
+        String::from_utf8_lossy(&output.stderr).into(),
+    )
+}
+
+/// A command as typed into the shell, a statement ending with `;`
+fn line(command: &str) -> Vec<u8> {
+    let end = if command.starts_with('.') || command.ends_with(';') {
+        "\n"
+    } else {
+        ";\n"
+    };
+
+    [command, end].concat().into_bytes()
+}
+
+/// An insert of a row as wide as the users table allows, so that thirteen fill a leaf
+fn insert_wide(i: usize) -> String {
+    format!(
+        "insert {i} {:_<31} {:_<255}",
+        format!("user{i}"),
+        format!("person{i}@example.com")
+    )
+}
+
 fn ensure_clean_fs<P: AsRef<Path>>(test_filename: P) {
-    std::fs::remove_file(test_filename)
-        .or_else(|e| match e.kind() {
-            ErrorKind::NotFound => Ok(()),
-            _ => Err(e),
-        })
-        .expect("could not clean up database files before running tests");
+    let wal_filename = format!("{}-wal", test_filename.as_ref().display());
+    for filename in [test_filename.as_ref(), Path::new(&wal_filename)] {
+        std::fs::remove_file(filename)
+            .or_else(|e| match e.kind() {
+                ErrorKind::NotFound => Ok(()),
+                _ => Err(e),
+            })
+            .expect("could not clean up database files before running tests");
+    }
 }
 
 fn clean_test(test_case: &str, test: fn(&str)) -> impl Fn() {
     let test_filename = format!("test_db_{}.db", test_case);
-    let clean_test_wrapper = move || {
+    move || {
         ensure_clean_fs(&test_filename);
         test(&test_filename);
         ensure_clean_fs(&test_filename);
-    };
-    clean_test_wrapper
+    }
 }
 
 #[test]
@@ -96,10 +183,10 @@ fn test_parse_error() {
 
     let test = |test_filename: &str| {
         let (_, err) = run(
-            vec!["insert -32 user1 user1@example.com".into(), ".exit".into()],
+            vec!["insert abc user1 user1@example.com".into(), ".exit".into()],
             test_filename,
         );
-        assert!(err[err.len() - 2].contains("[ERROR]can't parse '-32' to u32"));
+        assert!(err[err.len() - 2].contains("[ERROR]can't parse 'abc' to integer"));
     };
 
     clean_test(test_case, test)();
@@ -117,6 +204,81 @@ fn test_syntax_error() {
     clean_test(test_case, test)();
 }
 
+#[test]
+fn test_syntax_error_position() {
+    let test_case = "syntax_error_position";
+
+    let test = |test_filename: &str| {
+        let (_, err) = run(
+            vec![
+                "SELECT * FROM users WHERE".into(),
+                "delete from users where id = 1 2".into(),
+                "selec * from users".into(),
+                ".exit".into(),
+            ],
+            test_filename,
+        );
+        let errors: Vec<&String> = err.iter().filter(|s| s.contains("[ERROR]")).collect();
+        assert_eq!(
+            errors[0].as_str(),
+            "[ERROR]syntax error at line 1, column 26: expected an expression, found end of input"
+        );
+        assert_eq!(
+            errors[1].as_str(),
+            "[ERROR]syntax error at line 1, column 32: expected end of statement, found '2'"
+        );
+        assert_eq!(
+            errors[2].as_str(),
+            "[ERROR]syntax error at line 1, column 1: expected a statement, found 'selec'"
+        );
+
+        // the shell waits for the rest of an unterminated string
+        let mut conn = Connection::open(test_filename).unwrap();
+        let result = conn.execute("insert into users values (1, 'abc", &[]);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "syntax error at line 1, column 30: unterminated string"
+        );
+        conn.close().unwrap();
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_sql_statements() {
+    let test_case = "sql_statements";
+
+    let test = |test_filename: &str| {
+        let (out, err) = run(
+            vec![
+                "INSERT INTO users VALUES (1, 'John Smith', 'john@example.com'), (2, 'it''s me', 'a\\tb');".into(),
+                "insert into Users (email, id) values ('three@example.com', 3); -- no username".into(),
+                "Insert /* short form */ 4 'Jane Doe' jane@example.com".into(),
+                "UPDATE users SET username = 'Johnny', email = 'j@example.com' WHERE id = 1".into(),
+                "delete from users where 4 <= id".into(),
+                "select * FROM users;".into(),
+                "select * from accounts".into(),
+                ".exit".into(),
+            ],
+            test_filename,
+        );
+        let errors: Vec<&String> = err.iter().filter(|s| s.contains("[ERROR]")).collect();
+        assert_eq!(errors, vec!["[ERROR]no such table: 'accounts'"]);
+
+        let start = out.iter().position(|s| s.contains("1: ")).unwrap();
+        assert_eq!(
+            out[start].trim_start_matches(">> "),
+            "1: Johnny j@example.com"
+        );
+        assert_eq!(out[start + 1], "2: it's me a\tb");
+        assert_eq!(out[start + 2], "3: NULL three@example.com");
+        assert_eq!(selected_ids(&out), vec![1, 2, 3]);
+    };
+
+    clean_test(test_case, test)();
+}
+
 #[test]
 fn test_too_long() {
     let test_case = "too_long";
@@ -242,23 +404,23 @@ fn test_tree() {
     let test = |test_filename: &str| {
         let mut cmds = Vec::new();
         for i in 1..15 {
-            cmds.push(format!("insert {i} user{i} person{i}@example.com"));
+            cmds.push(insert_wide(i));
         }
         cmds.push(".btree".into());
         cmds.push(".exit".into());
         let (out, _) = run(cmds, test_filename);
         let expected_out = [
             "- internal (size 1)",
-            "  - leaf (size 7)",
+            "  - leaf (size 6)",
             "    - key 1",
             "    - key 2",
             "    - key 3",
             "    - key 4",
             "    - key 5",
             "    - key 6",
+            "  - key 6",
+            "  - leaf (size 8)",
             "    - key 7",
-            "  - key 7",
-            "  - leaf (size 7)",
             "    - key 8",
             "    - key 9",
             "    - key 10",
@@ -266,7 +428,7 @@ fn test_tree() {
             "    - key 12",
             "    - key 13",
             "    - key 14",
-            "exitting...",
+            "exiting...",
             "",
         ];
         for (i, s) in out.iter().enumerate() {
@@ -325,7 +487,7 @@ fn test_select() {
             "13: user13 person13@example.com",
             "14: user14 person14@example.com",
             "15: user15 person15@example.com",
-            "exitting...",
+            "exiting...",
             "",
         ];
         for (i, s) in out.iter().enumerate() {
@@ -337,58 +499,93 @@ fn test_select() {
     clean_test(test_case, test)();
 }
 
+#[test]
+fn test_select_without_table() {
+    let test_case = "select_without_table";
+
+    let test = |test_filename: &str| {
+        let sql = "select cast('12' as integer) + 1; select 1, 'a' || 'b'; select count(*); \
+                   select 2 where 0; select 3 order by 1 limit 1; select";
+        // the constants come once, however many rows the tutorial's table holds
+        let (code, out, _) = run_batch(test_filename, &["-c", sql], "");
+        assert_eq!((code, out.as_str()), (Some(0), "13\n1 ab\n1\n3\n"));
+        let (code, out, _) =
+            run_batch(test_filename, &["-c", "insert 1 a a@x; insert 2 b b@x"], "");
+        assert_eq!((code, out.as_str()), (Some(0), ""));
+        let (code, out, _) = run_batch(test_filename, &["-c", sql], "");
+        assert_eq!(
+            (code, out.as_str()),
+            (Some(0), "13\n1 ab\n1\n3\n1: a a@x\n2: b b@x\n")
+        );
+
+        for (sql, error) in [
+            ("select id", "no such column: 'id'"),
+            ("select 1 where rowid = 1", "no such column: 'rowid'"),
+            ("select *, 1", "no tables specified"),
+        ] {
+            let (code, _, err) = run_batch(test_filename, &["-c", sql], "");
+            assert_eq!(
+                (code, err.trim_end()),
+                (Some(1), format!("[ERROR]{error}").as_str())
+            );
+        }
+    };
+
+    clean_test(test_case, test)();
+}
+
 #[test]
 fn test_multiple_leaves() {
     let test_case = "multiple_leaves";
 
     let test = |test_filename: &str| {
         let cmds = vec![
-            "insert 18 user18 person18@example.com".into(),
-            "insert 7 user7 person7@example.com".into(),
-            "insert 10 user10 person10@example.com".into(),
-            "insert 29 user29 person29@example.com".into(),
-            "insert 23 user23 person23@example.com".into(),
-            "insert 4 user4 person4@example.com".into(),
-            "insert 14 user14 person14@example.com".into(),
-            "insert 30 user30 person30@example.com".into(),
-            "insert 15 user15 person15@example.com".into(),
-            "insert 26 user26 person26@example.com".into(),
-            "insert 22 user22 person22@example.com".into(),
-            "insert 19 user19 person19@example.com".into(),
-            "insert 2 user2 person2@example.com".into(),
-            "insert 1 user1 person1@example.com".into(),
-            "insert 21 user21 person21@example.com".into(),
-            "insert 11 user11 person11@example.com".into(),
-            "insert 6 user6 person6@example.com".into(),
-            "insert 20 user20 person20@example.com".into(),
-            "insert 5 user5 person5@example.com".into(),
-            "insert 8 user8 person8@example.com".into(),
-            "insert 9 user9 person9@example.com".into(),
-            "insert 3 user3 person3@example.com".into(),
-            "insert 12 user12 person12@example.com".into(),
-            "insert 27 user27 person27@example.com".into(),
-            "insert 17 user17 person17@example.com".into(),
-            "insert 16 user16 person16@example.com".into(),
-            "insert 13 user13 person13@example.com".into(),
-            "insert 24 user24 person24@example.com".into(),
-            "insert 25 user25 person25@example.com".into(),
-            "insert 28 user28 person28@example.com".into(),
+            insert_wide(18),
+            insert_wide(7),
+            insert_wide(10),
+            insert_wide(29),
+            insert_wide(23),
+            insert_wide(4),
+            insert_wide(14),
+            insert_wide(30),
+            insert_wide(15),
+            insert_wide(26),
+            insert_wide(22),
+            insert_wide(19),
+            insert_wide(2),
+            insert_wide(1),
+            insert_wide(21),
+            insert_wide(11),
+            insert_wide(6),
+            insert_wide(20),
+            insert_wide(5),
+            insert_wide(8),
+            insert_wide(9),
+            insert_wide(3),
+            insert_wide(12),
+            insert_wide(27),
+            insert_wide(17),
+            insert_wide(16),
+            insert_wide(13),
+            insert_wide(24),
+            insert_wide(25),
+            insert_wide(28),
             ".btree".into(),
             ".exit".into(),
         ];
         let (out, _) = run(cmds, test_filename);
         let expected_out = [
             "- internal (size 3)",
-            "  - leaf (size 7)",
+            "  - leaf (size 6)",
             "    - key 1",
             "    - key 2",
             "    - key 3",
             "    - key 4",
             "    - key 5",
             "    - key 6",
+            "  - key 6",
+            "  - leaf (size 9)",
             "    - key 7",
-            "  - key 7",
-            "  - leaf (size 8)",
             "    - key 8",
             "    - key 9",
             "    - key 10",
@@ -398,16 +595,16 @@ fn test_multiple_leaves() {
             "    - key 14",
             "    - key 15",
             "  - key 15",
-            "  - leaf (size 7)",
+            "  - leaf (size 6)",
             "    - key 16",
             "    - key 17",
             "    - key 18",
             "    - key 19",
             "    - key 20",
             "    - key 21",
+            "  - key 21",
+            "  - leaf (size 9)",
             "    - key 22",
-            "  - key 22",
-            "  - leaf (size 8)",
             "    - key 23",
             "    - key 24",
             "    - key 25",
@@ -416,7 +613,43 @@ fn test_multiple_leaves() {
             "    - key 28",
             "    - key 29",
             "    - key 30",
-            "exitting...",
+            "exiting...",
+            "",
+        ];
+        for (i, s) in out.iter().enumerate() {
+            let str = s.trim_start_matches(">> ").trim_end();
+            assert_eq!(str, expected_out[i]);
+        }
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_update() {
+    let test_case = "update";
+
+    let test = |test_filename: &str| {
+        let (out, _) = run(
+            vec![
+                "insert 1 user1 person1@example.com".into(),
+                "insert 2 user2 person2@example.com".into(),
+                "update 2 set username = bob, email = bob@example.com".into(),
+                "update 1 set email = someone@example.com".into(),
+                "update 3 set username = nobody".into(),
+                ".exit".into(),
+            ],
+            test_filename,
+        );
+        assert_eq!(out[0].trim_start_matches(">> "), "1 row(s) updated");
+        assert_eq!(out[1].trim_start_matches(">> "), "1 row(s) updated");
+        assert_eq!(out[2].trim_start_matches(">> "), "0 row(s) updated");
+
+        let (out, _) = run(vec!["select".into(), ".exit".into()], test_filename);
+        let expected_out = [
+            "1: user1 someone@example.com",
+            "2: bob bob@example.com",
+            "exiting...",
             "",
         ];
         for (i, s) in out.iter().enumerate() {
@@ -427,3 +660,2057 @@ fn test_multiple_leaves() {
 
     clean_test(test_case, test)();
 }
+
+#[test]
+fn test_update_too_long() {
+    let test_case = "update_too_long";
+
+    let test = |test_filename: &str| {
+        let (_, err) = run(
+            vec![
+                "insert 1 user1 person1@example.com".into(),
+                "update 1 set username = nnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnn".into(),
+                ".exit".into(),
+            ],
+            test_filename,
+        );
+        assert!(err[err.len() - 2]
+            .contains("[ERROR]'nnnnnnnnnnnnnnnnnnnnnnnnnnnnnnnn' is too long for username"));
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_freelist() {
+    let test_case = "freelist";
+
+    let test = |test_filename: &str| {
+        let free_pages = |out: &[String]| -> Vec<usize> {
+            out.iter()
+                .filter_map(|s| s.trim_start_matches(">> ").strip_prefix("free pages: "))
+                .map(|n| n.parse().unwrap())
+                .collect()
+        };
+
+        let mut cmds: Vec<String> = (1..=300)
+            .map(|i| format!("insert {i} user{i} person{i}@example.com"))
+            .collect();
+        cmds.push(".freelist".into());
+        cmds.push("delete where id > 20".into());
+        cmds.push(".freelist".into());
+        cmds.push(".exit".into());
+        let (out, _) = run(cmds, test_filename);
+        let freed = free_pages(&out);
+        assert_eq!(freed[0], 0);
+        assert!(freed[1] > 0);
+        let file_length = std::fs::metadata(test_filename).unwrap().len();
+
+        // the free list survives reopening, and new pages are taken from it first
+        let mut cmds = vec![".freelist".into()];
+        for i in 400..=500 {
+            cmds.push(format!("insert {i} user{i} person{i}@example.com"));
+        }
+        cmds.push(".freelist".into());
+        cmds.push(".exit".into());
+        let (out, _) = run(cmds, test_filename);
+        let reused = free_pages(&out);
+        assert_eq!(reused[0], freed[1]);
+        assert!(reused[1] < reused[0]);
+        assert_eq!(std::fs::metadata(test_filename).unwrap().len(), file_length);
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_not_a_database() {
+    let test_case = "not_a_database";
+
+    let test = |test_filename: &str| {
+        let bytes: Vec<u8> = (0..8192u32).map(|i| (i * 7 + 3) as u8).collect();
+        std::fs::write(test_filename, &bytes).unwrap();
+
+        let (_, err) = run(vec!["select".into(), ".exit".into()], test_filename);
+        assert!(err[err.len() - 2]
+            .contains(&format!("[ERROR]'{test_filename}' is not a resql database")));
+        // the failed open leaves no log behind
+        let wal_filename = format!("{test_filename}-wal");
+        assert!(!Path::new(&wal_filename).exists());
+
+        // nor is a log left by a database checkpointed into a file put in its place
+        std::fs::remove_file(test_filename).unwrap();
+        let cmds = vec!["insert 1 user1 person1@example.com".into(), "select".into()];
+        run_and_kill(cmds, 1, test_filename);
+        let wal = std::fs::read(&wal_filename).unwrap();
+        assert!(!wal.is_empty());
+        std::fs::write(test_filename, &bytes).unwrap();
+
+        let (_, err) = run(vec!["select".into(), ".exit".into()], test_filename);
+        assert!(err[err.len() - 2]
+            .contains(&format!("[ERROR]'{test_filename}' is not a resql database")));
+        assert_eq!(std::fs::read(test_filename).unwrap(), bytes);
+        assert_eq!(std::fs::read(&wal_filename).unwrap(), wal);
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_unsupported_version() {
+    let test_case = "unsupported_version";
+
+    let test = |test_filename: &str| {
+        let _ = run(
+            vec!["insert 1 user1 person1@example.com".into(), ".exit".into()],
+            test_filename,
+        );
+        let mut bytes = std::fs::read(test_filename).unwrap();
+        assert!(bytes.starts_with(b"resql database"));
+        bytes[16] = 99; // format version
+        std::fs::write(test_filename, bytes).unwrap();
+
+        let (_, err) = run(vec!["select".into(), ".exit".into()], test_filename);
+        assert!(err[err.len() - 2].contains("unsupported format version '99'"));
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_corrupted_page() {
+    let test_case = "corrupted_page";
+
+    let test = |test_filename: &str| {
+        let mut cmds: Vec<String> = (1..=30).map(insert_wide).collect();
+        cmds.push(".exit".into());
+        let _ = run(cmds, test_filename);
+
+        // point the first leaf of the table that links to another past the end of the file
+        let mut bytes = std::fs::read(test_filename).unwrap();
+        let leaf = (1..bytes.len() / 4096)
+            .map(|page_num| page_num * 4096)
+            .find(|&start| bytes[start] == 1 && bytes[start + 1] == 0 && bytes[start + 10] != 0)
+            .unwrap();
+        bytes[leaf + 10..leaf + 14].copy_from_slice(&999u32.to_le_bytes());
+        std::fs::write(test_filename, bytes).unwrap();
+
+        let (out, err) = run(
+            vec![
+                "select".into(),
+                "create table pets (id integer primary key, name text)".into(),
+                "insert into pets values (1, 'rex')".into(),
+                "select * from pets".into(),
+                ".exit".into(),
+            ],
+            test_filename,
+        );
+        assert!(err[err.len() - 2].contains("[ERROR]page '999' is out of bounds"));
+        assert_eq!(err.iter().filter(|s| s.contains("[ERROR]")).count(), 1);
+        assert!(out.contains(&">> >> >> 1: rex".to_string()));
+        assert_eq!(out[out.len() - 2], ">> exiting...");
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_corrupted_cells() {
+    let test_case = "corrupted_cells";
+
+    let test = |test_filename: &str| {
+        let mut cmds: Vec<String> = (1..=30).map(insert_wide).collect();
+        cmds.push(".exit".into());
+        let _ = run(cmds, test_filename);
+
+        let bytes = std::fs::read(test_filename).unwrap();
+        let leaves: Vec<usize> = (1..bytes.len() / 4096)
+            .map(|page_num| page_num * 4096)
+            .filter(|&start| bytes[start] == 1 && bytes[start + 1] == 0)
+            .collect();
+        let first_cell = |leaf: usize| u16::from_le_bytes([bytes[leaf + 18], bytes[leaf + 19]]);
+        let corruptions: [&dyn Fn(usize) -> (usize, Vec<u8>); 4] = [
+            // the cell count, past what the page holds
+            &|leaf| (leaf + 6, 60000u32.to_le_bytes().to_vec()),
+            // the pointer to the first cell, whose key size no longer fits
+            &|leaf| (leaf + 18, 4095u16.to_le_bytes().to_vec()),
+            // the key size of the first cell, running past the end of the page
+            &|leaf| {
+                (
+                    leaf + first_cell(leaf) as usize,
+                    u16::MAX.to_le_bytes().to_vec(),
+                )
+            },
+            // the page type
+            &|leaf| (leaf, vec![7]),
+        ];
+        for corruption in corruptions {
+            let mut corrupted = bytes.clone();
+            for &leaf in &leaves {
+                let (offset, value) = corruption(leaf);
+                corrupted[offset..offset + value.len()].copy_from_slice(&value);
+            }
+            std::fs::write(test_filename, corrupted).unwrap();
+
+            let (out, err) = run(vec!["select".into(), ".exit".into()], test_filename);
+            assert!(err[err.len() - 2].contains("[ERROR]corrupted page"));
+            assert_eq!(out[out.len() - 2], ">> >> exiting...");
+        }
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_upgrade_baseline_format() {
+    let test_case = "upgrade_baseline_format";
+
+    let test = |test_filename: &str| {
+        // written by the original version: no header, and the users tree, rows
+        // 1 to 40 inserted out of order, rooted at page 0
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/baseline.db");
+        std::fs::copy(fixture, test_filename).unwrap();
+
+        let (out, _) = run(
+            vec![
+                "select".into(),
+                ".tables".into(),
+                "insert 41 user41 person41@example.com".into(),
+                ".exit".into(),
+            ],
+            test_filename,
+        );
+        for (i, line) in out[..40].iter().enumerate() {
+            let id = i + 1;
+            assert_eq!(
+                line.trim_start_matches(">> "),
+                format!("{id}: user{id} person{id}@example.com")
+            );
+        }
+        assert_eq!(out[40].trim_start_matches(">> "), "users");
+
+        // the file now starts with a header
+        let bytes = std::fs::read(test_filename).unwrap();
+        assert_eq!(&bytes[0..16], b"resql database\0\0");
+        assert_eq!(bytes[16], 1);
+
+        let (out, _) = run(
+            vec!["select * from users where id > 39".into(), ".exit".into()],
+            test_filename,
+        );
+        assert_eq!(
+            out[0].trim_start_matches(">> "),
+            "40: user40 person40@example.com"
+        );
+        assert_eq!(
+            out[1].trim_start_matches(">> "),
+            "41: user41 person41@example.com"
+        );
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_small_cache() {
+    let test_case = "small_cache";
+
+    let test = |test_filename: &str| {
+        const NUM_ROWS: usize = 5_000;
+
+        // three pages are enough to walk and split the tree, evicting constantly
+        let mut cmds = vec![".cache 3".into()];
+        cmds.extend(
+            shuffled((1..=NUM_ROWS).collect())
+                .iter()
+                .map(|i| format!("insert {i} user{i} person{i}@example.com")),
+        );
+        cmds.push("delete where id between 1000 and 3999".into());
+        cmds.push(".cache".into());
+        cmds.push(".exit".into());
+        let (out, err) = run(cmds, test_filename);
+        assert!(!err
+            .iter()
+            .any(|s| s.contains("[ERROR]") || s.contains("panicked")));
+
+        let stats: Vec<&str> = out
+            .iter()
+            .filter_map(|s| s.trim_start_matches(">> ").strip_prefix("cache: "))
+            .collect();
+        assert!(stats[1].starts_with("3/3 pages"));
+        let misses: usize = stats[1]
+            .split(", ")
+            .nth(2)
+            .unwrap()
+            .trim_end_matches(" misses")
+            .parse()
+            .unwrap();
+        assert!(misses > NUM_ROWS);
+
+        let (out, _) = run(vec!["select".into(), ".exit".into()], test_filename);
+        let expected: Vec<usize> = (1..1000).chain(4000..=NUM_ROWS).collect();
+        assert_eq!(selected_ids(&out), expected);
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_transaction() {
+    let test_case = "transaction";
+
+    let test = |test_filename: &str| {
+        let mut cmds: Vec<String> = vec![
+            "insert 1 user1 person1@example.com".into(),
+            "begin".into(),
+            "begin".into(),
+        ];
+        // enough rows to split the root, all gone after the rollback
+        cmds.extend((2..=100).map(|i| format!("insert {i} user{i} person{i}@example.com")));
+        cmds.push("rollback".into());
+        cmds.push("rollback".into());
+        cmds.push(".btree".into());
+        cmds.push(".freelist".into());
+        cmds.push("begin".into());
+        cmds.push("insert 4 user4 person4@example.com".into());
+        cmds.push("commit".into());
+        cmds.push("begin".into());
+        cmds.push("insert 5 user5 person5@example.com".into());
+        cmds.push(".exit".into());
+        let (out, err) = run(cmds, test_filename);
+        let errors: Vec<&String> = err.iter().filter(|s| s.contains("[ERROR]")).collect();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("a transaction is already active"));
+        assert!(errors[1].contains("no transaction is active"));
+
+        let start = out.iter().position(|s| s.contains("- leaf")).unwrap();
+        assert_eq!(out[start].trim_start_matches(">> "), "- leaf (size 1)");
+        assert_eq!(out[start + 1], "  - key 1");
+        assert_eq!(out[start + 2].trim_start_matches(">> "), "free pages: 0");
+
+        // the transaction left open at exit was rolled back
+        let (out, _) = run(vec!["select".into(), ".exit".into()], test_filename);
+        assert_eq!(selected_ids(&out), vec![1, 4]);
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_failed_statement_changes_nothing() {
+    let test_case = "failed_statement_changes_nothing";
+
+    let test = |test_filename: &str| {
+        let mut cmds: Vec<String> = vec![
+            "create table t (k integer primary key, v integer, s text)".into(),
+            "create unique index tv on t (v)".into(),
+            "insert into t values (1, 1, 'a'), (2, 2, 'b'), (3, 3, 'c')".into(),
+            // the first row is changed before the second one clashes with it
+            "update t set v = 99 where k >= 1".into(),
+            "select * from t".into(),
+            // in a transaction only the statement is undone, even once a tiny
+            // cache pushed what came before it into the log
+            ".cache 3".into(),
+            "begin".into(),
+        ];
+        let wide = "x".repeat(500);
+        cmds.extend((4..=40).map(|i| format!("insert into t values ({i}, {i}, '{wide}')")));
+        cmds.push("update t set v = 99 where k >= 1".into());
+        cmds.push("commit".into());
+        cmds.push(".exit".into());
+        let (out, err) = run(cmds, test_filename);
+        let errors: Vec<&String> = err.iter().filter(|s| s.contains("[ERROR]")).collect();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("duplicate value '99' in unique index 'tv'"));
+        assert!(errors[1].contains("duplicate value '99' in unique index 'tv'"));
+        assert_eq!(out[0].trim_start_matches(">> "), "1: 1 a");
+        assert_eq!(out[1].trim_start_matches(">> "), "2: 2 b");
+        assert_eq!(out[2].trim_start_matches(">> "), "3: 3 c");
+
+        let (out, _) = run(
+            vec![
+                "select count(*) from t where v = k".into(),
+                "select * from t where v = 99".into(),
+                ".exit".into(),
+            ],
+            test_filename,
+        );
+        assert_eq!(out[0].trim_start_matches(">> "), "40");
+        assert_eq!(out[1].trim_start_matches(">> "), "exiting...");
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_crash_in_transaction() {
+    let test_case = "crash_in_transaction";
+
+    let test = |test_filename: &str| {
+        const NUM_ROWS: usize = 500;
+
+        // a tiny cache pushes the uncommitted rows into the log
+        let mut cmds: Vec<String> = vec![
+            "insert 1 user1 person1@example.com".into(),
+            ".cache 3".into(),
+            "begin".into(),
+        ];
+        cmds.extend((2..=NUM_ROWS).map(|i| format!("insert {i} user{i} person{i}@example.com")));
+        cmds.push("select".into());
+        run_and_kill(cmds, NUM_ROWS + 2, test_filename);
+
+        let (out, err) = run(vec!["select".into(), ".exit".into()], test_filename);
+        assert!(!err.iter().any(|s| s.contains("[ERROR]")));
+        assert_eq!(selected_ids(&out), vec![1]);
+    };
+
+    clean_test(test_case, test)();
+}
+
+fn selected_ids(out: &[String]) -> Vec<usize> {
+    out.iter()
+        .filter_map(|s| s.trim_start_matches(">> ").split(':').next()?.parse().ok())
+        .collect()
+}
+
+#[test]
+fn test_crash_after_commit() {
+    let test_case = "crash_after_commit";
+
+    let test = |test_filename: &str| {
+        const NUM_ROWS: usize = 50;
+
+        let mut cmds: Vec<String> = (1..=NUM_ROWS)
+            .map(|i| format!("insert {i} user{i} person{i}@example.com"))
+            .collect();
+        cmds.push("select".into());
+        run_and_kill(cmds, NUM_ROWS, test_filename);
+
+        // every statement was committed to the log, none was checkpointed
+        let wal_filename = format!("{test_filename}-wal");
+        assert!(std::fs::metadata(&wal_filename).unwrap().len() > 0);
+
+        let (out, err) = run(vec!["select".into(), ".exit".into()], test_filename);
+        assert!(!err.iter().any(|s| s.contains("[ERROR]")));
+        assert_eq!(selected_ids(&out), (1..=NUM_ROWS).collect::<Vec<_>>());
+
+        // a clean exit leaves everything in the database file
+        assert!(!Path::new(&wal_filename).exists());
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_crash_mid_statement() {
+    const NUM_ROWS: i64 = 500;
+
+    let (db, wal) = (MemoryStorage::new(), MemoryStorage::new());
+    let mut conn = Connection::open_with_storage("crash", db.clone(), wal.clone()).unwrap();
+    let insert = conn.prepare("insert into users values (?, ?, ?)").unwrap();
+    for i in shuffled((1..=NUM_ROWS as usize).collect()) {
+        let (username, email) = (format!("user{i}"), format!("person{i}@example.com"));
+        let params = [(i as i64).into(), username.into(), email.into()];
+        insert.execute(&mut conn, &params).unwrap();
+    }
+    conn.close().unwrap();
+
+    // delete every row of a copy of the database, through a tiny cache that pushes
+    // half-done changes into the log before the commit, tearing log write `crash`
+    // of the delete; then count the writes and the rows left after recovery
+    let delete = |crash: Option<usize>| {
+        let (db, wal) = (copied(&db), copied(&wal));
+        let faulty_wal = FaultyStorage::new(wal.clone());
+        let mut database =
+            Database::open_with_storage("crash", db.clone(), faulty_wal.clone()).unwrap();
+        MetaCommand::process(".cache 3", &mut database).unwrap();
+        let start = faulty_wal.num_writes();
+        if let Some(crash) = crash {
+            faulty_wal.fail_write(start + crash, Fault::Tear(100));
+        }
+        let result = Statement::prepare("delete from users").execute(&mut database);
+        let num_writes = faulty_wal.num_writes() - start;
+        drop(database);
+
+        let mut conn = Connection::open_with_storage("crash", db, wal).unwrap();
+        let ids: Vec<Value> = (conn.query("select id from users", &[]).unwrap())
+            .map(|row| row.unwrap().into_values().remove(0))
+            .collect();
+        (result, num_writes, ids)
+    };
+
+    let (result, num_writes, ids) = delete(None);
+    assert!(result.is_ok());
+    assert!(ids.is_empty());
+    // the evicted pages were logged before the commit
+    assert!(num_writes > 10, "{num_writes}");
+
+    // the commit frame comes last, so a crash at any write leaves the delete undone
+    let all: Vec<Value> = (1..=NUM_ROWS).map(Value::Integer).collect();
+    for crash in 0..num_writes {
+        let (result, _, ids) = delete(Some(crash));
+        assert!(matches!(result, Err(Error::Io { .. })), "{crash}");
+        assert_eq!(ids, all, "{crash}");
+    }
+}
+
+/// A storage holding what `storage` holds now, without sharing it
+fn copied(storage: &MemoryStorage) -> MemoryStorage {
+    let mut storage = storage.clone();
+    let mut bytes = vec![0; storage.size().unwrap()];
+    storage.read_at(0, &mut bytes).unwrap();
+    let mut copy = MemoryStorage::new();
+    copy.write_at(0, &bytes).unwrap();
+
+    copy
+}
+
+#[test]
+fn test_torn_wal() {
+    let test_case = "torn_wal";
+
+    let test = |test_filename: &str| {
+        const NUM_ROWS: usize = 10;
+
+        let mut cmds: Vec<String> = (1..=NUM_ROWS)
+            .map(|i| format!("insert {i} user{i} person{i}@example.com"))
+            .collect();
+        cmds.push("select".into());
+        run_and_kill(cmds, NUM_ROWS, test_filename);
+
+        // damage the frame committing the last insert, then leave half a frame behind it
+        let wal_filename = format!("{test_filename}-wal");
+        let mut bytes = std::fs::read(&wal_filename).unwrap();
+        let len = bytes.len();
+        bytes[len - 1] ^= 0xff;
+        bytes.extend_from_slice(&[0xab; 1000]);
+        std::fs::write(&wal_filename, bytes).unwrap();
+
+        let (out, err) = run(vec!["select".into(), ".exit".into()], test_filename);
+        assert!(!err.iter().any(|s| s.contains("[ERROR]")));
+        assert_eq!(selected_ids(&out), (1..NUM_ROWS).collect::<Vec<_>>());
+    };
+
+    clean_test(test_case, test)();
+}
+
+/// Deterministic Fisher-Yates shuffle driven by a xorshift generator
+fn shuffled(mut ids: Vec<usize>) -> Vec<usize> {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    for i in (1..ids.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        ids.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+
+    ids
+}
+
+/// Collect the leaf keys printed by `.btree`, checking that each internal
+/// key is the largest key of the subtree printed right before it
+fn btree_leaf_keys(out: &[String]) -> Vec<usize> {
+    let mut leaf_keys = Vec::new();
+    let mut in_leaf_at = None;
+    for line in out.iter() {
+        let line = line.trim_start_matches(">> ").trim_end();
+        let depth = (line.len() - line.trim_start().len()) / 2;
+        let line = line.trim_start();
+        if line.starts_with("- leaf") {
+            in_leaf_at = Some(depth);
+        } else if line.starts_with("- internal") {
+            in_leaf_at = None;
+        } else if let Some(key) = line.strip_prefix("- key ") {
+            let key: usize = key.parse().unwrap();
+            if in_leaf_at.is_some_and(|leaf_depth| depth == leaf_depth + 1) {
+                leaf_keys.push(key);
+            } else {
+                in_leaf_at = None;
+                assert_eq!(Some(&key), leaf_keys.last());
+            }
+        }
+    }
+
+    leaf_keys
+}
+
+#[test]
+fn test_split_internal_node() {
+    let test_case = "split_internal_node";
+
+    let test = |test_filename: &str| {
+        const NUM_ROWS: usize = 200_000;
+
+        let mut cmds: Vec<String> = shuffled((1..=NUM_ROWS).collect())
+            .iter()
+            .map(|i| format!("insert {i} user{i} person{i}@example.com"))
+            .collect();
+        cmds.push(".btree".into());
+        cmds.push(".exit".into());
+        let (out, err) = run(cmds, test_filename);
+        assert!(!err
+            .iter()
+            .any(|s| s.contains("[ERROR]") || s.contains("panicked")));
+
+        // leaves sit below at least two levels of internal pages
+        assert!(out.iter().any(|s| s.starts_with("    - leaf")));
+        assert_eq!(btree_leaf_keys(&out), (1..=NUM_ROWS).collect::<Vec<_>>());
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_delete() {
+    let test_case = "delete";
+
+    let test = |test_filename: &str| {
+        let mut cmds = Vec::new();
+        for i in 1..=30 {
+            cmds.push(format!("insert {i} user{i} person{i}@example.com"));
+        }
+        cmds.push("delete where id = 3".into());
+        cmds.push("delete where id between 10 and 25".into());
+        cmds.push("delete where id > 28".into());
+        cmds.push("delete where id <= 1".into());
+        cmds.push("select".into());
+        cmds.push(".exit".into());
+        let (out, _) = run(cmds, test_filename);
+        let expected_out = [
+            "1 row(s) deleted",
+            "16 row(s) deleted",
+            "2 row(s) deleted",
+            "1 row(s) deleted",
+            "2: user2 person2@example.com",
+            "4: user4 person4@example.com",
+            "5: user5 person5@example.com",
+            "6: user6 person6@example.com",
+            "7: user7 person7@example.com",
+            "8: user8 person8@example.com",
+            "9: user9 person9@example.com",
+            "26: user26 person26@example.com",
+            "27: user27 person27@example.com",
+            "28: user28 person28@example.com",
+            "exiting...",
+            "",
+        ];
+        for (i, s) in out.iter().enumerate() {
+            let str = s.trim_start_matches(">> ").trim_end();
+            assert_eq!(str, expected_out[i]);
+        }
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_delete_rebalance() {
+    let test_case = "delete_rebalance";
+
+    let test = |test_filename: &str| {
+        const NUM_ROWS: usize = 100_000;
+
+        let mut cmds: Vec<String> = shuffled((1..=NUM_ROWS).collect())
+            .iter()
+            .map(|i| format!("insert {i} user{i} person{i}@example.com"))
+            .collect();
+        cmds.push("delete where id between 20000 and 60000".into());
+        let mut remaining: Vec<usize> = (1..20000).chain(60001..=NUM_ROWS).collect();
+        for i in shuffled(remaining.clone()) {
+            if i % 3 != 0 {
+                cmds.push(format!("delete where id = {i}"));
+            }
+        }
+        remaining.retain(|i| i % 3 == 0);
+        cmds.push(".btree".into());
+        cmds.push("select".into());
+        cmds.push("delete where id >= 0".into());
+        cmds.push(".btree".into());
+        cmds.push(".exit".into());
+        let (out, err) = run(cmds, test_filename);
+        assert!(!err
+            .iter()
+            .any(|s| s.contains("[ERROR]") || s.contains("panicked")));
+
+        let select_start = out.iter().position(|s| s.contains(": user")).unwrap();
+        assert_eq!(btree_leaf_keys(&out[..select_start]), remaining);
+
+        // the leaves are still chained together in key order
+        let selected: Vec<usize> = out[select_start..]
+            .iter()
+            .filter_map(|s| s.trim_start_matches(">> ").split(':').next()?.parse().ok())
+            .collect();
+        assert_eq!(selected, remaining);
+
+        // deleting everything collapses the tree back into an empty root leaf
+        let end = &out[out.len() - 3..];
+        assert_eq!(end[0].trim_start_matches(">> "), "- leaf (size 0)");
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_create_table() {
+    let test_case = "create_table";
+
+    let test = |test_filename: &str| {
+        let (out, err) = run(
+            vec![
+                "create table pets (id integer primary key, name varchar(8) not null, age int)"
+                    .into(),
+                "insert into pets values (2, 'rex', 3)".into(),
+                "insert into pets (name, id) values ('tom', 1)".into(),
+                "select * from pets".into(),
+                ".tables".into(),
+                ".schema pets".into(),
+                ".exit".into(),
+            ],
+            test_filename,
+        );
+        assert!(!err.iter().any(|s| s.contains("[ERROR]")));
+        let expected_out = [
+            "1: tom NULL",
+            "2: rex 3",
+            "users pets",
+            "CREATE TABLE pets (id INTEGER PRIMARY KEY, name VARCHAR(8) NOT NULL, age INTEGER);",
+            "exiting...",
+            "",
+        ];
+        for (i, s) in out.iter().enumerate() {
+            assert_eq!(s.trim_start_matches(">> "), expected_out[i]);
+        }
+
+        // the catalog lists the table, and it survives a restart
+        let (out, _) = run(
+            vec![
+                "select * from resql_schema".into(),
+                "select * from pets".into(),
+                ".exit".into(),
+            ],
+            test_filename,
+        );
+        let expected_out = [
+            "1: table users 2 CREATE TABLE users (id INTEGER PRIMARY KEY, username VARCHAR(31), email VARCHAR(255))",
+            "2: table pets 3 CREATE TABLE pets (id INTEGER PRIMARY KEY, name VARCHAR(8) NOT NULL, age INTEGER)",
+            "1: tom NULL",
+            "2: rex 3",
+            "exiting...",
+            "",
+        ];
+        for (i, s) in out.iter().enumerate() {
+            assert_eq!(s.trim_start_matches(">> "), expected_out[i]);
+        }
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_create_table_rowid() {
+    let test_case = "create_table_rowid";
+
+    let test = |test_filename: &str| {
+        let (out, err) = run(
+            vec![
+                "create table notes (body text, pinned integer)".into(),
+                "insert into notes values ('first', 1), ('second', 0)".into(),
+                "update notes set pinned = 1 where rowid = 2".into(),
+                "delete from notes where rowid = 1".into(),
+                "insert into notes (body) values ('third')".into(),
+                "select * from notes".into(),
+                ".exit".into(),
+            ],
+            test_filename,
+        );
+        assert!(!err.iter().any(|s| s.contains("[ERROR]")));
+        let expected_out = [
+            "1 row(s) updated",
+            "1 row(s) deleted",
+            "2: second 1",
+            "3: third NULL",
+            "exiting...",
+            "",
+        ];
+        for (i, s) in out.iter().enumerate() {
+            assert_eq!(s.trim_start_matches(">> "), expected_out[i]);
+        }
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_create_table_errors() {
+    let test_case = "create_table_errors";
+
+    let test = |test_filename: &str| {
+        let (out, err) = run(
+            vec![
+                "create table users (id integer)".into(),
+                "create table t (a widget)".into(),
+                "create table t (a int primary key, b int primary key)".into(),
+                "create table t (a int, primary key (a, a))".into(),
+                "create table t (a int, A int)".into(),
+                "select * from t".into(),
+                "create table t (id int primary key, name varchar(3) not null)".into(),
+                "insert into t values (1, 'toolong')".into(),
+                "insert into t (id) values (1)".into(),
+                "insert into t (id, nope) values (1, 'a')".into(),
+                "insert into t values ('one', 'a')".into(),
+                "insert into resql_schema values ('table', 'x', 5, 'CREATE TABLE x (a int)')"
+                    .into(),
+                "insert into t values (1, 'a')".into(),
+                // the row before the duplicate goes too
+                "insert into t values (3, 'c'), (1, 'dup')".into(),
+                "select * from t".into(),
+                ".exit".into(),
+            ],
+            test_filename,
+        );
+        let errors: Vec<&String> = err.iter().filter(|s| s.contains("[ERROR]")).collect();
+        let expected = [
+            "table 'users' already exists",
+            "unknown type 'WIDGET' for column 'a'",
+            "table 't' has more than one primary key",
+            "duplicate column 'a' in primary key",
+            "duplicate column 'A'",
+            "no such table: 't'",
+            "'toolong' is too long for name",
+            "a value for 'name' is required",
+            "no such column: 'nope'",
+            "can't parse 'one' to integer",
+            "table 'resql_schema' may not be modified",
+            "duplicate key '1'",
+        ];
+        assert_eq!(errors.len(), expected.len());
+        for (error, expected) in errors.iter().zip(expected) {
+            assert!(
+                error.contains(expected),
+                "{error} should contain {expected}"
+            );
+        }
+        assert_eq!(out[0].trim_start_matches(">> "), "1: a");
+        assert_eq!(out[1].trim_start_matches(">> "), "exiting...");
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_create_table_rollback() {
+    let test_case = "create_table_rollback";
+
+    let test = |test_filename: &str| {
+        let (out, err) = run(
+            vec![
+                "begin".into(),
+                "create table t (a int)".into(),
+                "insert into t values (1)".into(),
+                ".tables".into(),
+                "rollback".into(),
+                ".tables".into(),
+                "create table t (a text)".into(),
+                ".schema".into(),
+                ".exit".into(),
+            ],
+            test_filename,
+        );
+        assert!(!err.iter().any(|s| s.contains("[ERROR]")));
+        let expected_out = [
+            "users t",
+            "users",
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, username VARCHAR(31), email VARCHAR(255));",
+            "CREATE TABLE t (a TEXT);",
+            "exiting...",
+            "",
+        ];
+        for (i, s) in out.iter().enumerate() {
+            assert_eq!(s.trim_start_matches(">> "), expected_out[i]);
+        }
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_leaf_density() {
+    let test_case = "leaf_density";
+
+    let test = |test_filename: &str| {
+        // short rows take only the bytes they need
+        let mut cmds: Vec<String> = (1..=60)
+            .map(|i| format!("insert {i} user{i} person{i}@example.com"))
+            .collect();
+        cmds.push(".btree".into());
+        cmds.push(".exit".into());
+        let (out, _) = run(cmds, test_filename);
+        let start = out.iter().position(|s| s.contains("- leaf")).unwrap();
+        assert_eq!(out[start].trim_start_matches(">> "), "- leaf (size 60)");
+        assert_eq!(btree_leaf_keys(&out), (1..=60).collect::<Vec<_>>());
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_variable_length_rows() {
+    let test_case = "variable_length_rows";
+
+    let test = |test_filename: &str| {
+        const NUM_ROWS: usize = 200;
+
+        let mut cmds: Vec<String> = shuffled((1..=NUM_ROWS).collect())
+            .iter()
+            .map(|i| format!("insert {i} u{i} e{i}"))
+            .collect();
+        // growing rows in place runs their pages out of room
+        for i in (2..=NUM_ROWS).step_by(2) {
+            cmds.push(format!(
+                "update {i} set username = {:x<31}, email = {:y<255}",
+                i, i
+            ));
+        }
+        // shrinking and deleting leaves holes for later inserts to reuse
+        for i in (1..=NUM_ROWS).step_by(4) {
+            cmds.push(format!("delete where id = {i}"));
+        }
+        for i in (4..=NUM_ROWS).step_by(4) {
+            cmds.push(format!("update {i} set email = e{i}"));
+        }
+        for i in (1..=NUM_ROWS).step_by(4) {
+            cmds.push(format!("insert {i} again{i} e{i}"));
+        }
+        cmds.push("select".into());
+        cmds.push(".exit".into());
+        let (out, err) = run(cmds, test_filename);
+        assert!(!err
+            .iter()
+            .any(|s| s.contains("[ERROR]") || s.contains("panicked")));
+
+        let rows: Vec<&str> = out
+            .iter()
+            .map(|s| s.trim_start_matches(">> "))
+            .filter(|s| s.contains(": "))
+            .collect();
+        assert_eq!(rows.len(), NUM_ROWS);
+        for (i, row) in (1..=NUM_ROWS).zip(rows) {
+            let expected = match i % 4 {
+                1 => format!("{i}: again{i} e{i}"),
+                0 => format!("{i}: {:x<31} e{i}", i),
+                2 => format!("{i}: {:x<31} {:y<255}", i, i),
+                _ => format!("{i}: u{i} e{i}"),
+            };
+            assert_eq!(row, expected);
+        }
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_overflow_pages() {
+    let test_case = "overflow_pages";
+
+    let test = |test_filename: &str| {
+        let body = |c: char, len: usize| c.to_string().repeat(len);
+        let free_pages = |out: &[String]| -> Vec<usize> {
+            out.iter()
+                .filter_map(|s| s.trim_start_matches(">> ").strip_prefix("free pages: "))
+                .map(|n| n.parse().unwrap())
+                .collect()
+        };
+
+        let (out, err) = run(
+            vec![
+                "create table docs (id integer primary key, body text)".into(),
+                format!("insert into docs values (1, '{}')", body('a', 10_000)),
+                format!("insert into docs values (2, '{}')", body('b', 900)),
+                format!("insert into docs values (3, '{}')", body('c', 5_000)),
+                ".freelist".into(),
+                "delete from docs where id = 1".into(),
+                ".freelist".into(),
+                // two of the freed pages take the grown row
+                format!("update docs set body = '{}' where id = 2", body('d', 9_000)),
+                ".freelist".into(),
+                ".exit".into(),
+            ],
+            test_filename,
+        );
+        assert!(!err.iter().any(|s| s.contains("[ERROR]")));
+        // 10 000 bytes keep about 1 000 in the leaf and need three more pages
+        assert_eq!(free_pages(&out), vec![0, 3, 1]);
+
+        let (out, _) = run(
+            vec!["select * from docs".into(), ".exit".into()],
+            test_filename,
+        );
+        assert_eq!(
+            out[0].trim_start_matches(">> "),
+            format!("2: {}", body('d', 9_000))
+        );
+        assert_eq!(
+            out[1].trim_start_matches(">> "),
+            format!("3: {}", body('c', 5_000))
+        );
+        assert_eq!(out[2].trim_start_matches(">> "), "exiting...");
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_overflow_pages_many_rows() {
+    let test_case = "overflow_pages_many_rows";
+
+    let test = |test_filename: &str| {
+        const NUM_ROWS: usize = 100;
+
+        let body = |i: usize| format!("{i}:").repeat(200 + i * 20);
+        let mut cmds = vec!["create table docs (id integer primary key, body text)".into()];
+        for i in shuffled((1..=NUM_ROWS).collect()) {
+            cmds.push(format!("insert into docs values ({i}, '{}')", body(i)));
+        }
+        cmds.push("delete from docs where id > 50".into());
+        cmds.push("select * from docs".into());
+        cmds.push("delete from docs".into());
+        cmds.push(".freelist".into());
+        cmds.push(".exit".into());
+        let (out, err) = run(cmds, test_filename);
+        assert!(!err
+            .iter()
+            .any(|s| s.contains("[ERROR]") || s.contains("panicked")));
+
+        assert_eq!(out[0].trim_start_matches(">> "), "50 row(s) deleted");
+        for (i, line) in (1..=50).zip(&out[1..]) {
+            assert_eq!(line.trim_start_matches(">> "), format!("{i}: {}", body(i)));
+        }
+        assert_eq!(out[51].trim_start_matches(">> "), "50 row(s) deleted");
+        // every page but the header, the catalog and the two roots went back to the free list
+        let file_pages = std::fs::metadata(test_filename).unwrap().len() as usize / 4096;
+        assert_eq!(
+            out[52].trim_start_matches(">> "),
+            format!("free pages: {}", file_pages - 4)
+        );
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_where() {
+    let test_case = "where";
+
+    let test = |test_filename: &str| {
+        let mut cmds: Vec<String> = (1..=30)
+            .map(|i| format!("insert {i} user{i} person{i}@example.com"))
+            .collect();
+        let queries = [
+            "select * from users where id = 15",
+            "select * from users where id > 27",
+            "select * from users where id between 10 and 12",
+            "select * from users where 3 >= id",
+            "select * from users where id >= 20 and id < 24 and email <> 'person21@example.com'",
+            "select * from users where username = 'user7' or id = 30",
+            "select * from users where id % 10 = 0",
+            "select * from users where not id > 2",
+            "select * from users where id > 5 and id < 3",
+            "select * from users where id = 100",
+            "select * from users where id = -1",
+            "select * from users where nope = 1",
+            "select * from users where id / 0",
+        ];
+        for query in queries {
+            cmds.push(query.into());
+            cmds.push(".tables".into()); // a marker between results
+        }
+        cmds.push(".exit".into());
+        let (out, err) = run(cmds, test_filename);
+
+        let results: Vec<Vec<usize>> = out
+            .split(|s| s.ends_with("users"))
+            .map(selected_ids)
+            .collect();
+        assert_eq!(results[0], vec![15]);
+        assert_eq!(results[1], vec![28, 29, 30]);
+        assert_eq!(results[2], vec![10, 11, 12]);
+        assert_eq!(results[3], vec![1, 2, 3]);
+        assert_eq!(results[4], vec![20, 22, 23]);
+        assert_eq!(results[5], vec![7, 30]);
+        assert_eq!(results[6], vec![10, 20, 30]);
+        assert_eq!(results[7], vec![1, 2]);
+        for result in &results[8..queries.len()] {
+            assert!(result.is_empty());
+        }
+
+        let errors: Vec<&String> = err.iter().filter(|s| s.contains("[ERROR]")).collect();
+        assert_eq!(
+            errors,
+            vec!["[ERROR]no such column: 'nope'", "[ERROR]division by zero"]
+        );
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_where_seek() {
+    let test_case = "where_seek";
+
+    let test = |test_filename: &str| {
+        const NUM_ROWS: usize = 1000;
+
+        let pages_read = |out: &[String]| -> Vec<usize> {
+            out.iter()
+                .filter_map(|s| s.split_once("pages, "))
+                .map(|(_, rest)| {
+                    let words: Vec<&str> = rest.split(' ').collect();
+                    words[0].parse::<usize>().unwrap() + words[2].parse::<usize>().unwrap()
+                })
+                .collect()
+        };
+
+        let mut cmds: Vec<String> = (1..=NUM_ROWS)
+            .map(|i| format!("insert {i} user{i} person{i}@example.com"))
+            .collect();
+        cmds.push(".cache".into());
+        cmds.push("select * from users where id = 500".into());
+        cmds.push(".cache".into());
+        cmds.push("select * from users where id between 600 and 605".into());
+        cmds.push(".cache".into());
+        cmds.push("select * from users where username = 'user500'".into());
+        cmds.push(".cache".into());
+        cmds.push(".exit".into());
+        let (out, _) = run(cmds, test_filename);
+
+        assert_eq!(
+            selected_ids(&out),
+            vec![500, 600, 601, 602, 603, 604, 605, 500]
+        );
+        let reads = pages_read(&out);
+        // a seek walks down the tree and stops after the range, a filter reads every leaf
+        assert!(reads[1] - reads[0] < 20);
+        assert!(reads[2] - reads[1] < 50);
+        assert!(reads[3] - reads[2] > 1000);
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_select_order_limit() {
+    let test_case = "select_order_limit";
+
+    let test = |test_filename: &str| {
+        // usernames run in another order than ids
+        let mut cmds: Vec<String> = (1..=40)
+            .map(|i| format!("insert {i} user{} person{i}@example.com", i * 7 % 40))
+            .collect();
+        let queries = [
+            "select email, id from users order by username desc limit 3 offset 2",
+            "select * from users order by username limit 3",
+            "select * from users limit 3 offset 5",
+            "select id, id * 2, username || '!' from users where id < 4",
+            "select * from users where id > 10 order by id desc limit 2",
+            "select id from users order by 1 desc limit 1",
+            "select * from users limit 0",
+            "select id from users order by 2",
+            "select id from users limit -1",
+            "select id from users order by nope",
+        ];
+        for query in queries {
+            cmds.push(query.into());
+            cmds.push(".tables".into()); // a marker between results
+        }
+        cmds.push(".exit".into());
+        let (out, err) = run(cmds, test_filename);
+
+        let results: Vec<Vec<&str>> = out
+            .split(|s| s.ends_with("users"))
+            .map(|result| result.iter().map(|s| s.trim_start_matches(">> ")).collect())
+            .collect();
+        assert_eq!(
+            results[0],
+            [
+                "person1@example.com 1",
+                "person18@example.com 18",
+                "person35@example.com 35"
+            ]
+        );
+        assert_eq!(
+            results[1],
+            [
+                "40: user0 person40@example.com",
+                "23: user1 person23@example.com",
+                "30: user10 person30@example.com"
+            ]
+        );
+        assert_eq!(
+            results[2],
+            [
+                "6: user2 person6@example.com",
+                "7: user9 person7@example.com",
+                "8: user16 person8@example.com"
+            ]
+        );
+        assert_eq!(results[3], ["1 2 user7!", "2 4 user14!", "3 6 user21!"]);
+        assert_eq!(
+            results[4],
+            [
+                "40: user0 person40@example.com",
+                "39: user33 person39@example.com"
+            ]
+        );
+        assert_eq!(results[5], ["40"]);
+        for result in &results[6..queries.len()] {
+            assert!(result.is_empty());
+        }
+
+        let errors: Vec<&String> = err.iter().filter(|s| s.contains("[ERROR]")).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "[ERROR]ORDER BY term out of range: 2",
+                "[ERROR]LIMIT must be a non-negative integer, not '-1'",
+                "[ERROR]no such column: 'nope'"
+            ]
+        );
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_external_sort() {
+    let test_case = "external_sort";
+
+    let test = |test_filename: &str| {
+        const NUM_ROWS: usize = 2000;
+
+        let username = |i: usize| format!("user{}", i * 7919 % NUM_ROWS);
+        let mut cmds: Vec<String> = (1..=NUM_ROWS)
+            .map(|i| format!("insert {i} {} person{i}@example.com", username(i)))
+            .collect();
+        // a cache of two pages leaves too little memory to sort in one go
+        cmds.push(".cache 2".into());
+        cmds.push("select username, id from users order by username desc".into());
+        cmds.push(".freelist".into());
+        cmds.push("select username from users order by username limit 2 offset 10".into());
+        cmds.push(".exit".into());
+        let (out, err) = run(cmds, test_filename);
+        assert!(!err
+            .iter()
+            .any(|s| s.contains("[ERROR]") || s.contains("panicked")));
+
+        let mut expected: Vec<(String, usize)> = (1..=NUM_ROWS).map(|i| (username(i), i)).collect();
+        expected.sort_by(|a, b| b.cmp(a));
+        let start = out.iter().position(|s| s.contains("cache:")).unwrap() + 1;
+        for (i, (username, id)) in expected.iter().enumerate() {
+            assert_eq!(
+                out[start + i].trim_start_matches(">> "),
+                format!("{username} {id}")
+            );
+        }
+
+        // the runs went to a file of their own, not to pages of the database
+        let free_pages = out[start + NUM_ROWS].trim_start_matches(">> ");
+        assert_eq!(free_pages, "free pages: 0");
+        assert_eq!(
+            out[start + NUM_ROWS + 1].trim_start_matches(">> "),
+            "user1006"
+        );
+        assert_eq!(out[start + NUM_ROWS + 2], "user1007");
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_aggregates() {
+    let test_case = "aggregates";
+
+    let test = |test_filename: &str| {
+        let mut cmds: Vec<String> = (1..=30)
+            .map(|i| format!("insert {i} user{i} person{i}@domain{}.com", i % 3))
+            .collect();
+        let domain = "substr(email, instr(email, '@') + 1)";
+        let queries = [
+            "select count(*) from users".into(),
+            "select count(*), count(email), sum(id), avg(id), min(username), max(username) from users".into(),
+            format!("select {domain}, count(*) from users group by {domain}"),
+            format!("select {domain}, count(*), sum(id) from users group by {domain} having sum(id) > 150 order by 3 desc"),
+            "select count(*) from users where id > 25".into(),
+            "select id % 2, count(id), max(id) from users group by id % 2".into(),
+            "select count(*) + 1, count(*) * 2 from users".into(),
+            "create table empty (a integer, b text)".into(),
+            "select count(*), sum(a), min(b) from empty".into(),
+            "select b, count(*) from empty group by b".into(),
+            "select username, count(*) from users group by id % 2".into(),
+            "select count(*) from users where count(*) > 1".into(),
+            "select sum(*) from users".into(),
+            "select nope(id) from users".into(),
+        ];
+        for query in &queries {
+            cmds.push(query.clone());
+            cmds.push(".tables".into()); // a marker between results
+        }
+        cmds.push(".exit".into());
+        let (out, err) = run(cmds, test_filename);
+
+        let results: Vec<Vec<&str>> = out
+            .split(|s| s.ends_with("users") || s.ends_with("users empty"))
+            .map(|result| result.iter().map(|s| s.trim_start_matches(">> ")).collect())
+            .collect();
+        assert_eq!(results[0], ["30"]);
+        assert_eq!(results[1], ["30 30 465 15.5 user1 user9"]);
+        assert_eq!(
+            results[2],
+            ["domain0.com 10", "domain1.com 10", "domain2.com 10"]
+        );
+        assert_eq!(results[3], ["domain0.com 10 165", "domain2.com 10 155"]);
+        assert_eq!(results[4], ["5"]);
+        assert_eq!(results[5], ["0 15 30", "1 15 29"]);
+        assert_eq!(results[6], ["31 60"]);
+        assert_eq!(results[8], ["0 NULL NULL"]);
+        for result in &results[9..queries.len()] {
+            assert!(result.is_empty());
+        }
+
+        let errors: Vec<&String> = err.iter().filter(|s| s.contains("[ERROR]")).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "[ERROR]column 'username' must appear in GROUP BY or be used in an aggregate function",
+                "[ERROR]misuse of aggregate function count()",
+                "[ERROR]'*' may only be counted, as in count(*)",
+                "[ERROR]no such function: 'nope'"
+            ]
+        );
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_group_by_spill() {
+    let test_case = "group_by_spill";
+
+    let test = |test_filename: &str| {
+        const NUM_ROWS: usize = 2000;
+        const NUM_GROUPS: usize = 500;
+
+        let mut cmds: Vec<String> = (1..=NUM_ROWS)
+            .map(|i| format!("insert {i} user{i} person{i}@example.com"))
+            .collect();
+        cmds.push(".cache".into());
+        cmds.push("select count(*) from users".into());
+        cmds.push(".cache".into());
+        cmds.push("select count(*) from users where id > 0".into());
+        cmds.push(".cache".into());
+        // a page of memory holds far fewer groups than there are
+        cmds.push(".cache 1".into());
+        cmds.push(format!(
+            "select id % {NUM_GROUPS}, count(*), sum(id), min(username), max(id) from users group by id % {NUM_GROUPS}"
+        ));
+        cmds.push(".exit".into());
+        let (out, err) = run(cmds, test_filename);
+        assert!(!err
+            .iter()
+            .any(|s| s.contains("[ERROR]") || s.contains("panicked")));
+
+        let pages_read: Vec<usize> = out
+            .iter()
+            .filter_map(|s| s.split_once("pages, "))
+            .map(|(_, rest)| {
+                let words: Vec<&str> = rest.split(' ').collect();
+                words[0].parse::<usize>().unwrap() + words[2].parse::<usize>().unwrap()
+            })
+            .collect();
+        // counting every row reads each leaf once, without decoding a row
+        assert!(pages_read[1] - pages_read[0] < 100);
+        assert!(pages_read[2] - pages_read[1] > 1000);
+
+        let start = out.iter().position(|s| s.contains("cache: 1/1")).unwrap() + 1;
+        for group in 0..NUM_GROUPS {
+            let ids: Vec<usize> = (1..=NUM_ROWS).filter(|i| i % NUM_GROUPS == group).collect();
+            let min_username = ids.iter().map(|i| format!("user{i}")).min().unwrap();
+            assert_eq!(
+                out[start + group].trim_start_matches(">> "),
+                format!(
+                    "{group} {} {} {min_username} {}",
+                    ids.len(),
+                    ids.iter().sum::<usize>(),
+                    ids.iter().max().unwrap()
+                )
+            );
+        }
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_group_by_collation() {
+    let test_case = "group_by_collation";
+
+    let test = |test_filename: &str| {
+        const NUM_GROUPS: usize = 300;
+
+        // each tag comes once in lower case and once in upper case
+        let values: Vec<String> = (0..2 * NUM_GROUPS)
+            .map(|i| match i < NUM_GROUPS {
+                true => format!("({i}, 'tag{i}')"),
+                false => format!("({i}, 'TAG{}')", i - NUM_GROUPS),
+            })
+            .collect();
+        let query = "select tag, count(*) from tags group by tag";
+        let (out, err) = run(
+            vec![
+                "create table tags (id int primary key, tag text collate nocase)".into(),
+                format!("insert into tags values {}", values.join(", ")),
+                query.into(),
+                // a page of memory holds far fewer groups than there are
+                ".cache 1".into(),
+                query.into(),
+                ".exit".into(),
+            ],
+            test_filename,
+        );
+        assert!(!err
+            .iter()
+            .any(|s| s.contains("[ERROR]") || s.contains("panicked")));
+
+        let mut expected: Vec<String> = (0..NUM_GROUPS).map(|i| format!("tag{i} 2")).collect();
+        expected.sort();
+        let groups: Vec<String> = out
+            .iter()
+            .map(|s| s.trim_start_matches(">> ").to_lowercase())
+            .filter(|s| s.starts_with("tag"))
+            .collect();
+        assert_eq!(groups[..NUM_GROUPS], expected);
+        assert_eq!(groups[NUM_GROUPS..], expected);
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_index() {
+    let test_case = "index";
+
+    let test = |test_filename: &str| {
+        const NUM_ROWS: usize = 1000;
+        let pages_read = |out: &[String]| -> Vec<usize> {
+            out.iter()
+                .filter_map(|s| s.split_once("pages, "))
+                .map(|(_, rest)| {
+                    let words: Vec<&str> = rest.split(' ').collect();
+                    words[0].parse::<usize>().unwrap() + words[2].parse::<usize>().unwrap()
+                })
+                .collect()
+        };
+
+        // the index is filled from the rows already there and kept up by later ones
+        let mut cmds: Vec<String> = (1..=NUM_ROWS / 2)
+            .map(|i| format!("insert {i} user{i} person{i}@example.com"))
+            .collect();
+        cmds.push("create unique index users_username on users (username)".into());
+        cmds.extend(
+            (NUM_ROWS / 2 + 1..=NUM_ROWS)
+                .map(|i| format!("insert {i} user{i} person{i}@example.com")),
+        );
+        cmds.push(".cache".into());
+        cmds.push("select * from users where username = 'user700'".into());
+        cmds.push(".cache".into());
+        cmds.push("select id from users where username >= 'user998'".into());
+        cmds.push(".cache".into());
+        cmds.push("select * from users where email = 'person700@example.com'".into());
+        cmds.push(".cache".into());
+        cmds.push(".exit".into());
+        let (out, err) = run(cmds, test_filename);
+        assert!(!err.iter().any(|s| s.contains("[ERROR]")));
+
+        let start = out.iter().position(|s| s.contains("cache:")).unwrap() + 1;
+        let expected_out = ["700: user700 person700@example.com", "998", "999"];
+        for (i, s) in expected_out.iter().enumerate() {
+            let line = out[start + i + (i > 0) as usize].trim_start_matches(">> ");
+            assert_eq!(line, *s);
+        }
+        let reads = pages_read(&out);
+        // an index seek walks down two trees, a filter on another column reads every leaf
+        assert!(reads[1] - reads[0] < 20);
+        assert!(reads[2] - reads[1] < 20);
+        assert!(reads[3] - reads[2] > 1000);
+
+        // the entries follow the rows, and unique values stay unique
+        let (out, err) = run(
+            vec![
+                "insert 1001 user1 someone@example.com".into(),
+                "update users set username = 'user2' where id = 1".into(),
+                "update users set username = 'alice' where id = 1".into(),
+                "delete from users where id = 2".into(),
+                "insert 1002 user2 person2@example.com".into(),
+                "select * from users where username = 'user1'".into(),
+                "select * from users where username = 'alice'".into(),
+                "select * from users where username = 'user2'".into(),
+                "create index users_email on users (email)".into(),
+                "create unique index users_username on users (email)".into(),
+                "create index users_id on users (nope)".into(),
+                "insert 1003 bob person3@example.com".into(),
+                "select * from users where email = 'person3@example.com'".into(),
+                ".schema".into(),
+                ".exit".into(),
+            ],
+            test_filename,
+        );
+        let errors: Vec<&str> = err
+            .iter()
+            .filter_map(|s| s.split_once("[ERROR]"))
+            .map(|(_, e)| e)
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "duplicate value 'user1' in unique index 'users_username'",
+                "duplicate value 'user2' in unique index 'users_username'",
+                "index 'users_username' already exists",
+                "no such column: 'nope'",
+            ]
+        );
+        let out: Vec<&str> = out
+            .iter()
+            .map(|s| s.trim_start_matches(">> "))
+            .filter(|s| !s.is_empty())
+            .collect();
+        let expected_out = [
+            "1 row(s) updated",
+            "1 row(s) deleted",
+            "1: alice person1@example.com",
+            "1002: user2 person2@example.com",
+            "3: user3 person3@example.com",
+            "1003: bob person3@example.com",
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, username VARCHAR(31), email VARCHAR(255));",
+            "CREATE UNIQUE INDEX users_username ON users (username);",
+            "CREATE INDEX users_email ON users (email);",
+            "exiting...",
+        ];
+        assert_eq!(out, expected_out);
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_index_rollback() {
+    let test_case = "index_rollback";
+
+    let test = |test_filename: &str| {
+        let (out, err) = run(
+            vec![
+                "insert 1 user1 person1@example.com".into(),
+                "begin transaction".into(),
+                "create index users_email on users (email)".into(),
+                "insert 2 user2 person2@example.com".into(),
+                "rollback".into(),
+                "select * from users where email = 'person2@example.com'".into(),
+                ".schema".into(),
+                "create index users_email on users (email)".into(),
+                ".btree users_email".into(),
+                ".exit".into(),
+            ],
+            test_filename,
+        );
+        assert!(!err.iter().any(|s| s.contains("[ERROR]")));
+        let out: Vec<&str> = out
+            .iter()
+            .map(|s| s.trim_start_matches(">> "))
+            .filter(|s| !s.is_empty())
+            .collect();
+        let expected_out = [
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, username VARCHAR(31), email VARCHAR(255));",
+            "- leaf (size 1)",
+            "  - key person1@example.com, 1",
+            "exiting...",
+        ];
+        assert_eq!(out, expected_out);
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_composite_keys() {
+    let test_case = "composite_keys";
+
+    let test = |test_filename: &str| {
+        let (out, err) = run(
+            vec![
+                "create table people (name text collate nocase primary key, age int, photo blob)"
+                    .into(),
+                "insert into people values ('alice', 30, X'00ff10'), ('Bob', -5, X'01')".into(),
+                "insert into people values ('ALICE', 1, X'02')".into(),
+                "select * from people where name = 'BOB'".into(),
+                "create table pairs (a int, b text, c int, primary key (a, b))".into(),
+                "insert into pairs values (1, 'x', 10), (1, 'y', 11), (2, 'x', 12), (-3, 'z', 13)"
+                    .into(),
+                "insert into pairs values (1, 'x', 99)".into(),
+                "select * from pairs where a = 1 and b > 'x'".into(),
+                "update pairs set c = 0 where a = 1".into(),
+                "delete from pairs where b = 'x'".into(),
+                "create unique index people_photo on people (photo)".into(),
+                "insert into people values ('carol', 3, X'00FF10')".into(),
+                "select name from people where photo = X'01'".into(),
+                "create index pairs_c on pairs (c, b)".into(),
+                ".btree pairs".into(),
+                ".btree pairs_c".into(),
+                ".schema".into(),
+                "create table t (k text collate french)".into(),
+                "create table t (k text, primary key (k, nope))".into(),
+                ".exit".into(),
+            ],
+            test_filename,
+        );
+        let errors: Vec<&str> = err
+            .iter()
+            .filter_map(|s| s.split_once("[ERROR]"))
+            .map(|(_, e)| e)
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "duplicate key 'ALICE'",
+                "duplicate key '1, x'",
+                "duplicate value 'X'00FF10'' in unique index 'people_photo'",
+                "no such collation sequence: french",
+                "no such column: 'nope'",
+            ]
+        );
+        let out: Vec<&str> = out
+            .iter()
+            .map(|s| s.trim_start_matches(">> "))
+            .filter(|s| !s.is_empty())
+            .collect();
+        let expected_out = [
+            "Bob: -5 X'01'",
+            "1 y 11",
+            "2 row(s) updated",
+            "2 row(s) deleted",
+            "Bob",
+            "- leaf (size 2)",
+            "  - key -3, z",
+            "  - key 1, y",
+            "- leaf (size 2)",
+            "  - key 0, y, 1, y",
+            "  - key 13, z, -3, z",
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, username VARCHAR(31), email VARCHAR(255));",
+            "CREATE TABLE people (name TEXT PRIMARY KEY COLLATE NOCASE, age INTEGER, photo BLOB);",
+            "CREATE UNIQUE INDEX people_photo ON people (photo);",
+            "CREATE TABLE pairs (a INTEGER, b TEXT, c INTEGER, PRIMARY KEY (a, b));",
+            "CREATE INDEX pairs_c ON pairs (c, b);",
+            "exiting...",
+        ];
+        assert_eq!(out, expected_out);
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_types() {
+    let test_case = "types";
+
+    let test = |test_filename: &str| {
+        let (out, err) = run(
+            vec![
+                "create table items (id int primary key, price real, sold boolean, note text not null, tag varchar(3))".into(),
+                "insert into items values (1, 2.5, true, 'a', 'x'), (2, 3, false, 'b', null), (3, null, null, 'c', 'yy')".into(),
+                "insert into items (id, price) values (4, 1.0)".into(),
+                "insert into items values (null, 1.0, true, 'd', 'z')".into(),
+                "insert into items values (5, 'cheap', true, 'e', 'z')".into(),
+                "insert into items values (5, '7.25', 'true', 'e', 'toolong')".into(),
+                "insert into items values (2.5, 1.0, true, 'd', 'z')".into(),
+                "create table counts (id int primary key, n integer)".into(),
+                "update counts set n = '3.5'".into(),
+                "select id from items where price is null".into(),
+                "select id from items where sold is not null and not sold".into(),
+                "select id from items where price > 2 or sold".into(),
+                "select id, price * 2, cast(price as integer), cast(id as text) || '!' from items where id < 3".into(),
+                "select count(price), sum(price), avg(price), min(tag), max(tag) from items".into(),
+                "select null = null, 1 = 1.0, null and false, null or true, null and true, null or false, not null, 7 / 2, 7.0 / 2".into(),
+                "select cast('12' as integer), cast('12' as integer) + 1, cast(2.9 as integer), cast('1.5' as real), cast(12 as text) || '!'".into(),
+                "select cast('abc' as integer)".into(),
+                "select cast(1 as widget)".into(),
+                ".exit".into(),
+            ],
+            test_filename,
+        );
+        let errors: Vec<&str> = err
+            .iter()
+            .filter_map(|s| s.split_once("[ERROR]"))
+            .map(|(_, e)| e)
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "a value for 'note' is required",
+                "'id' may not be NULL",
+                "can't parse 'cheap' to real",
+                "'toolong' is too long for tag",
+                "can't store '2.5' in integer column 'id'",
+                "can't store '3.5' in integer column 'n'",
+                "can't parse 'abc' to integer",
+                "unknown type 'WIDGET'",
+            ]
+        );
+
+        let (out2, _) = run(
+            vec![
+                "select * from items".into(),
+                ".schema items".into(),
+                ".exit".into(),
+            ],
+            test_filename,
+        );
+        let out: Vec<&str> = (out.iter().chain(&out2))
+            .map(|s| s.trim_start_matches(">> "))
+            .filter(|s| !s.is_empty())
+            .collect();
+        let expected_out = [
+            "3",
+            "2",
+            "1",
+            "2",
+            "1 5.0 2 1!",
+            "2 6.0 3 2!",
+            "2 5.5 2.75 x yy",
+            "NULL true false true NULL NULL NULL 3 3.5",
+            "12 13 2 1.5 12!",
+            "exiting...",
+            "1: 2.5 true a x",
+            "2: 3.0 false b NULL",
+            "3: NULL NULL c yy",
+            "CREATE TABLE items (id INTEGER PRIMARY KEY, price REAL, sold BOOLEAN, note TEXT NOT NULL, tag VARCHAR(3));",
+            "exiting...",
+        ];
+        assert_eq!(out, expected_out);
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_connection() {
+    let test_case = "connection";
+
+    let test = |test_filename: &str| {
+        let mut conn = Connection::open(test_filename).unwrap();
+        conn.execute(
+            "create table pets (id integer primary key, name text not null, weight real)",
+            &[],
+        )
+        .unwrap();
+        let insert = conn.prepare("insert into pets values (?, ?, ?)").unwrap();
+        for (id, name, weight) in [
+            (1, "rex", Some(9.5)),
+            (2, "tom", None),
+            (3, "kit", Some(2.0)),
+        ] {
+            let params = [id.into(), name.into(), weight.into()];
+            assert_eq!(insert.execute(&mut conn, &params).unwrap(), 1);
+        }
+
+        let rows: Vec<Row> = conn
+            .query("select name, weight from pets where id >= ?", &[2.into()])
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let values: Vec<&[Value]> = rows.iter().map(Row::values).collect();
+        assert_eq!(
+            values,
+            [
+                &[Value::Text("tom".into()), Value::Null][..],
+                &[Value::Text("kit".into()), Value::Real(2.0)][..],
+            ]
+        );
+
+        // a sorted query is worked out before its first row
+        let select = conn
+            .prepare("select id from pets order by name desc limit ?")
+            .unwrap();
+        let ids: Vec<Value> = select
+            .query(&mut conn, &[2.into()])
+            .unwrap()
+            .map(|row| row.unwrap().get(0).cloned().unwrap())
+            .collect();
+        assert_eq!(ids, [Value::Integer(2), Value::Integer(1)]);
+
+        let updated = conn
+            .execute(
+                "update pets set weight = ? where weight is null",
+                &[4.25.into()],
+            )
+            .unwrap();
+        assert_eq!(updated, 1);
+        let deleted = conn
+            .execute("delete from pets where id < ?", &[3.into()])
+            .unwrap();
+        assert_eq!(deleted, 2);
+
+        let errors = [
+            conn.execute("insert into pets values (?, ?, 1.0)", &[4.into()]),
+            conn.execute("insert into pets values (3, 'kit', 1.0)", &[]),
+            conn.execute("insert into pets values (?, 'x', 1.0)", &[Value::Null]),
+            conn.execute("selec 1", &[]),
+        ];
+        let errors: Vec<String> = errors
+            .iter()
+            .map(|e| e.as_ref().unwrap_err().to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "1 values for 2 parameters",
+                "duplicate key '3'",
+                "'id' may not be NULL",
+                "syntax error at line 1, column 1: expected a statement, found 'selec'",
+            ]
+        );
+        assert!(matches!(
+            conn.query("delete from pets", &[]),
+            Err(Error::Invalid(_))
+        ));
+        // a failed statement commits nothing, not even the rows it inserted first
+        assert!(matches!(
+            conn.execute(
+                "insert into pets values (?, 'x', 1.0), (3, 'kit', 1.0)",
+                &[5.into()]
+            ),
+            Err(Error::Constraint(_))
+        ));
+        conn.close().unwrap();
+
+        let (out, _) = run(
+            vec!["select * from pets".into(), ".exit".into()],
+            test_filename,
+        );
+        assert_eq!(out[0], ">> 3: kit 2.0");
+        assert_eq!(out[1], ">> exiting...");
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_memory_database() {
+    let mut cmds = vec!["create table t (id integer primary key, name text)".to_string()];
+    cmds.extend((1..=300).map(|i| format!("insert into t values ({i}, '{i:0>300}')")));
+    cmds.extend([
+        "begin".into(),
+        "delete from t where id > 100".into(),
+        "rollback".into(),
+        "select count(*), max(id) from t".into(),
+        ".exit".into(),
+    ]);
+    let (out, _) = run(cmds, ":memory:");
+    assert!(out[out.len() - 4].ends_with(">> 200 row(s) deleted"));
+    assert_eq!(out[out.len() - 3], ">> >> 300 300");
+    assert!(!Path::new(":memory:").exists());
+    assert!(!Path::new(":memory:-wal").exists());
+
+    // every database in memory starts out empty
+    let (_, err) = run(vec!["select * from t".into(), ".exit".into()], ":memory:");
+    assert!(err[err.len() - 2].contains("[ERROR]no such table: 't'"));
+
+    let mut conn = Connection::open(":memory:").unwrap();
+    let mut other = Connection::open(":memory:").unwrap();
+    conn.execute("insert into users values (1, 'a', 'a@example.com')", &[])
+        .unwrap();
+    assert_eq!(conn.query("select", &[]).unwrap().count(), 1);
+    assert_eq!(other.query("select", &[]).unwrap().count(), 0);
+}
+
+#[test]
+fn test_storage_faults() {
+    let ids = |conn: &mut Connection| -> Vec<Value> {
+        (conn.query("select id from t", &[]).unwrap())
+            .map(|row| row.unwrap().into_values().remove(0))
+            .collect()
+    };
+    let (db, wal) = (MemoryStorage::new(), MemoryStorage::new());
+
+    // a commit that fails partway through logging is rolled back, and the connection goes on
+    let faulty_wal = FaultyStorage::new(wal.clone());
+    let mut conn = Connection::open_with_storage("faults", db.clone(), faulty_wal.clone()).unwrap();
+    conn.execute("create table t (id integer primary key, name text)", &[])
+        .unwrap();
+    let insert = conn.prepare("insert into t values (?, ?)").unwrap();
+    insert.execute(&mut conn, &[1.into(), "a".into()]).unwrap();
+    faulty_wal.fail_write(faulty_wal.num_writes() + 1, Fault::Fail);
+    let result = insert.execute(&mut conn, &[2.into(), "b".repeat(5000).into()]);
+    assert!(matches!(result, Err(Error::Io { .. })));
+    assert_eq!(ids(&mut conn), [Value::Integer(1)]);
+    insert.execute(&mut conn, &[3.into(), "c".into()]).unwrap();
+
+    // a crash halfway through logging a commit loses that commit alone
+    faulty_wal.fail_write(faulty_wal.num_writes() + 1, Fault::Tear(100));
+    let result = insert.execute(&mut conn, &[4.into(), "d".repeat(5000).into()]);
+    assert!(matches!(result, Err(Error::Io { .. })));
+    assert!(insert.execute(&mut conn, &[5.into(), "e".into()]).is_err());
+    drop(conn);
+    let mut conn = Connection::open_with_storage("faults", db.clone(), wal.clone()).unwrap();
+    assert_eq!(ids(&mut conn), [Value::Integer(1), Value::Integer(3)]);
+    conn.close().unwrap();
+
+    // a crash while copying the log into the database is made good by the next open
+    let faulty_db = FaultyStorage::new(db.clone());
+    let mut conn = Connection::open_with_storage("faults", faulty_db.clone(), wal.clone()).unwrap();
+    conn.execute("insert into t values (6, 'f')", &[]).unwrap();
+    faulty_db.fail_write(faulty_db.num_writes(), Fault::Tear(10));
+    assert!(matches!(conn.close(), Err(Error::Io { .. })));
+    let mut conn = Connection::open_with_storage("faults", db.clone(), wal.clone()).unwrap();
+    let expected = [Value::Integer(1), Value::Integer(3), Value::Integer(6)];
+    assert_eq!(ids(&mut conn), expected);
+    conn.execute("insert into t values (2, 'b')", &[]).unwrap();
+    conn.close().unwrap();
+}
+
+#[test]
+fn test_reads_write_nothing() {
+    let (db, wal) = (MemoryStorage::new(), MemoryStorage::new());
+    let mut conn = Connection::open_with_storage("reads", db.clone(), wal.clone()).unwrap();
+    conn.execute("create table t (id integer primary key, name text)", &[])
+        .unwrap();
+    conn.execute("create index t_name on t (name)", &[])
+        .unwrap();
+    let insert = conn.prepare("insert into t values (?, ?)").unwrap();
+    for i in 1..=300 {
+        let name = format!("{i:0>100}");
+        insert.execute(&mut conn, &[i.into(), name.into()]).unwrap();
+    }
+    conn.close().unwrap();
+
+    // queries, and statements finding nothing to change, leave every page as it was
+    let (faulty_db, faulty_wal) = (FaultyStorage::new(db), FaultyStorage::new(wal));
+    let mut conn =
+        Connection::open_with_storage("reads", faulty_db.clone(), faulty_wal.clone()).unwrap();
+    let rows = (conn.query("select * from t", &[]).unwrap()).count();
+    assert_eq!(rows, 300);
+    let name = format!("{:0>100}", 150);
+    for (sql, params) in [
+        ("select name from t where id = ?", vec![150.into()]),
+        ("select id from t where name = ?", vec![name.into()]),
+        ("select max(id), count(*) from t", vec![]),
+    ] {
+        let rows = (conn.query(sql, &params).unwrap()).count();
+        assert_eq!(rows, 1);
+    }
+    let changed = [
+        conn.execute("update t set name = 'x' where id > 300", &[]),
+        conn.execute("delete from t where id > 300", &[]),
+        conn.execute("delete from t where name = 'x'", &[]),
+    ];
+    assert!(changed.iter().all(|changed| matches!(changed, Ok(0))));
+    conn.close().unwrap();
+
+    // nor do a sort and a grouping that outgrow a cache this small
+    let mut database =
+        Database::open_with_storage("reads", faulty_db.clone(), faulty_wal.clone()).unwrap();
+    MetaCommand::process(".cache 2", &mut database).unwrap();
+    for sql in [
+        "select id from t order by name desc limit 1",
+        "select name, count(*) from t group by name limit 1",
+    ] {
+        assert!(Statement::prepare(sql).execute(&mut database).is_ok());
+    }
+    database.close().unwrap();
+    assert_eq!((faulty_db.num_writes(), faulty_wal.num_writes()), (0, 0));
+}
+
+#[test]
+fn test_database_lock() {
+    let test_case = "database_lock";
+
+    let test = |test_filename: &str| {
+        let conn = Connection::open(test_filename).unwrap();
+        let locked = format!("'{test_filename}' is locked by another connection");
+        let result = Connection::open(test_filename);
+        assert!(matches!(&result, Err(e @ Error::Io { .. }) if e.to_string() == locked));
+        let (_, err) = run(vec![".exit".into()], test_filename);
+        assert_eq!(err[err.len() - 2], format!("[ERROR]{locked}"));
+
+        conn.close().unwrap();
+        let (out, _) = run(vec!["select".into(), ".exit".into()], test_filename);
+        assert_eq!(out[0], ">> >> exiting...");
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_multi_line_statements() {
+    let test_case = "multi_line_statements";
+
+    let test = |test_filename: &str| {
+        let (out, err) = run(
+            vec![
+                "create table t (\n  id integer primary key,\n  name text\n);".into(),
+                "insert into t values (1, 'a;b'); insert into t values (2, 'it''s');".into(),
+                "select name\nfrom t -- not the end;\nwhere id = 1 /* ; */;".into(),
+                ";;".into(),
+                "select count(*) from t; selec; select max(id) from t\n;".into(),
+                ".exit".into(),
+            ],
+            test_filename,
+        );
+        assert_eq!(out[0], ">> ..> ..> ..> >> >> ..> ..> a;b");
+        assert_eq!(out[1], ">> >> 2");
+        assert_eq!(out[2], "..> 2");
+        assert_eq!(
+            err[err.len() - 2],
+            "[ERROR]syntax error at line 1, column 1: expected a statement, found 'selec'"
+        );
+    };
+
+    clean_test(test_case, test)();
+}
+
+#[test]
+fn test_shell_modes() {
+    let test_case = "shell_modes";
+
+    let test = |test_filename: &str| {
+        // a pipe gets no prompts, and the end of its input closes the database
+        let (code, out, _) = run_batch(test_filename, &[], "insert 1 a a@x;\nselect");
+        assert_eq!((code, out.as_str()), (Some(0), "1: a a@x\n"));
+        assert!(!Path::new(&format!("{test_filename}-wal")).exists());
+
+        // a script stops at its first error, keeping what ran before it
+        let script = format!("{test_filename}.sql");
+        std::fs::write(&script, "insert 2 b b@x;\nselec;\ninsert 3 c c@x;\n").unwrap();
+        let (code, _, err) = run_batch(test_filename, &[&script], "");
+        std::fs::remove_file(&script).unwrap();
+        assert_eq!(code, Some(1));
+        assert_eq!(
+            err,
+            "[ERROR]syntax error at line 1, column 1: expected a statement, found 'selec'\n"
+        );
+
+        // so does one that can't be read
+        std::fs::write(&script, b"insert 3 c c@x;\n\xff\xfe;\n").unwrap();
+        let (code, _, err) = run_batch(test_filename, &[&script], "");
+        std::fs::remove_file(&script).unwrap();
+        assert_eq!(code, Some(1));
+        assert!(err.starts_with("[ERROR]failed to read the input."));
+
+        let sql = "select id from users; select count(*) from users";
+        let (code, out, _) = run_batch(test_filename, &["-c", sql], "");
+        assert_eq!((code, out.as_str()), (Some(0), "1\n2\n3\n3\n"));
+        let (code, _, err) = run_batch(test_filename, &["-c", "select 'abc"], "");
+        assert_eq!(code, Some(1));
+        assert!(err.ends_with("unterminated string\n"));
+        let (code, _, _) = run_batch(test_filename, &["-c", "select", "extra"], "");
+        assert_eq!(code, Some(2));
+    };
+
+    clean_test(test_case, test)();
+}